@@ -1,6 +1,11 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
 use lact_client::DaemonClient;
-use lact_schema::args::{CliArgs, CliCommand};
+use lact_schema::{
+    args::{CliArgs, CliCommand, ConfigCommand},
+    ErrorKind,
+};
+use std::{fs, path::Path};
 
 pub fn run(args: CliArgs) -> Result<()> {
     let rt = tokio::runtime::Builder::new_current_thread()
@@ -14,6 +19,12 @@ pub fn run(args: CliArgs) -> Result<()> {
             CliCommand::ListGpus => list_gpus(&args, &client).await,
             CliCommand::Info => info(&args, &client).await,
             CliCommand::Snapshot => snapshot(&client).await,
+            CliCommand::ApiSchema => api_schema(&client).await,
+            CliCommand::Config { ref command } => match command {
+                ConfigCommand::Check { file } => check_config(file, &client).await,
+                ConfigCommand::Revisions => list_config_revisions(&client).await,
+                ConfigCommand::Restore { id } => restore_config_revision(*id, &client).await,
+            },
         }
     })
 }
@@ -37,7 +48,15 @@ async fn info(args: &CliArgs, client: &DaemonClient) -> Result<()> {
         println!("{gpu_line}");
         println!("{}", "=".repeat(gpu_line.len()));
 
-        let info = client.get_device_info(&id).await?;
+        let info = client.get_device_info(&id).await.map_err(|err| {
+            if ErrorKind::of(&err) == ErrorKind::NotFound {
+                err.context(format!(
+                    "GPU {id} not found, use `lact cli list-gpus` to see the available GPUs"
+                ))
+            } else {
+                err
+            }
+        })?;
         let stats = client.get_device_stats(&id).await?;
 
         let elements = info.info_elements(Some(&stats));
@@ -68,3 +87,70 @@ async fn snapshot(client: &DaemonClient) -> Result<()> {
     println!("Generated debug snapshot in {path}");
     Ok(())
 }
+
+async fn api_schema(client: &DaemonClient) -> Result<()> {
+    let schema = client.get_api_schema().await?;
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+async fn check_config(file: &Path, client: &DaemonClient) -> Result<()> {
+    let yaml =
+        fs::read_to_string(file).with_context(|| format!("Could not read {}", file.display()))?;
+    let validation = client.validate_config(yaml).await?;
+
+    for (label, issues) in [
+        ("error", &validation.errors),
+        ("warning", &validation.warnings),
+    ] {
+        for issue in issues {
+            if issue.path.is_empty() {
+                println!("{label}: {}", issue.message);
+            } else {
+                println!("{label}: {}: {}", issue.path, issue.message);
+            }
+        }
+    }
+
+    for (id, writes) in &validation.writes {
+        println!("GPU {id}:");
+        if writes.is_empty() {
+            println!("  no changes");
+        }
+        for write in writes {
+            println!("  {} <- {}", write.target, write.value);
+        }
+    }
+
+    if !validation.is_valid() {
+        bail!(
+            "The config has {} error(s) and would not apply cleanly",
+            validation.errors.len()
+        );
+    }
+    Ok(())
+}
+
+async fn list_config_revisions(client: &DaemonClient) -> Result<()> {
+    let revisions = client.list_config_revisions().await?;
+    if revisions.is_empty() {
+        println!("No previous config versions");
+    }
+    for revision in revisions.iter().rev() {
+        let saved_at = i64::try_from(revision.timestamp)
+            .ok()
+            .and_then(DateTime::from_timestamp_millis)
+            .map(|datetime| datetime.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"));
+        match saved_at {
+            Some(saved_at) => println!("{}: saved at {saved_at}", revision.id),
+            None => println!("{}", revision.id),
+        }
+    }
+    Ok(())
+}
+
+async fn restore_config_revision(id: u64, client: &DaemonClient) -> Result<()> {
+    client.restore_config_revision(id).await?;
+    println!("Restored config version {id}");
+    Ok(())
+}
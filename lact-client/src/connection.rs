@@ -1,30 +1,364 @@
 pub mod tcp;
+pub mod tls;
 pub mod unix;
 
-use anyhow::anyhow;
-use futures::future::BoxFuture;
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use anyhow::{anyhow, Context};
+use futures::future::{select, BoxFuture, Either};
+use lact_schema::{
+    request::{AuthToken, RequestEnvelope},
+    Request,
+};
+use serde::{de::IgnoredAny, Deserialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    mem,
+    pin::pin,
+    sync::{Mutex as StdMutex, MutexGuard},
+};
+use tokio::{
+    io::{
+        split, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf,
+        WriteHalf,
+    },
+    sync::{oneshot, Mutex},
+};
+use tracing::debug;
 
 pub trait DaemonConnection {
-    fn request<'a>(&'a mut self, payload: &'a str) -> BoxFuture<'a, anyhow::Result<String>>;
+    /// Several requests can be in flight at once, their responses are matched by id
+    fn request<'a>(&'a self, request: &'a Request<'a>) -> BoxFuture<'a, anyhow::Result<String>>;
+
+    /// Wait for the next message sent by the daemon without a request, such as a subscription event
+    fn receive(&self) -> BoxFuture<'_, anyhow::Result<String>>;
 
     /// Establish a new connection to the same service
     fn new_connection(&self) -> BoxFuture<'_, anyhow::Result<Box<dyn DaemonConnection>>>;
 }
 
-async fn request(
-    socket: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin>,
-    payload: &str,
-) -> anyhow::Result<String> {
-    if !socket.buffer().is_empty() {
-        return Err(anyhow!("Another request was not processed properly"));
+/// Fields used to route a message from the daemon to whoever is waiting for it
+#[derive(Deserialize)]
+struct MessageHeader {
+    id: Option<u64>,
+    event: Option<IgnoredAny>,
+}
+
+/// Shared by all connection types. Every request is tagged with an id, so that several of them
+/// can be in flight on the same stream and the daemon can answer them in any order.
+///
+/// There is no background task reading the stream: whichever request holds the reader
+/// reads until its own response arrives, and hands over the other messages it comes across.
+struct Multiplexer<S> {
+    reader: Mutex<LineReader<ReadHalf<S>>>,
+    writer: Mutex<WriteHalf<S>>,
+    state: StdMutex<MultiplexerState>,
+}
+
+#[derive(Default)]
+struct MultiplexerState {
+    next_id: u64,
+    /// Ordered by id, which is also the order in which the requests were sent
+    pending: BTreeMap<u64, oneshot::Sender<String>>,
+    events: VecDeque<String>,
+}
+
+impl<S: AsyncRead + AsyncWrite> Multiplexer<S> {
+    fn new(stream: S) -> Self {
+        let (reader, writer) = split(stream);
+        Self {
+            reader: Mutex::new(LineReader::new(reader)),
+            writer: Mutex::new(writer),
+            state: StdMutex::default(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MultiplexerState> {
+        self.state.lock().expect("Multiplexer state lock poisoned")
+    }
+
+    async fn request(&self, request: &Request<'_>) -> anyhow::Result<String> {
+        let (response_tx, mut response_rx) = oneshot::channel();
+
+        let mut writer = self.writer.lock().await;
+        // Ids are assigned while holding the writer, so that they are in the same order as the requests on the stream
+        let id = {
+            let mut state = self.state();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.pending.insert(id, response_tx);
+            id
+        };
+        let _pending = PendingGuard {
+            state: &self.state,
+            id,
+        };
+
+        let mut payload = serde_json::to_vec(&RequestEnvelope {
+            id: Some(id),
+            request,
+        })?;
+        payload.push(b'\n');
+        writer.write_all(&payload).await?;
+        drop(writer);
+
+        let mut reader = match select(&mut response_rx, pin!(self.reader.lock())).await {
+            Either::Left((response, _)) => {
+                return response.map_err(|_| anyhow!("Connection closed"));
+            }
+            Either::Right((reader, _)) => reader,
+        };
+        // The response could have been read by someone else before the reader was released
+        if let Ok(response) = response_rx.try_recv() {
+            return Ok(response);
+        }
+
+        loop {
+            let line = reader.read_line().await?;
+            if let Some(response) = self.dispatch(Some(id), line) {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Returns the message if it is the response to `own_id`, otherwise passes it on.
+    /// Messages that are not a response to any pending request are queued as events.
+    fn dispatch(&self, own_id: Option<u64>, line: String) -> Option<String> {
+        let header = match serde_json::from_str::<MessageHeader>(&line) {
+            Ok(header) => header,
+            Err(err) => {
+                debug!("could not read message header: {err}");
+                MessageHeader {
+                    id: None,
+                    event: None,
+                }
+            }
+        };
+
+        let mut state = self.state();
+        if header.event.is_some() {
+            state.events.push_back(line);
+            return None;
+        }
+
+        // Daemons without request id support answer in order, so the oldest pending request is the one being answered
+        let Some(id) = header.id.or_else(|| state.pending.keys().next().copied()) else {
+            state.events.push_back(line);
+            return None;
+        };
+
+        if Some(id) == own_id {
+            state.pending.remove(&id);
+            return Some(line);
+        }
+        match state.pending.remove(&id) {
+            Some(response_tx) => {
+                let _ = response_tx.send(line);
+            }
+            None => debug!("dropping response to unknown request {id}"),
+        }
+        None
+    }
+
+    async fn receive(&self) -> anyhow::Result<String> {
+        if let Some(event) = self.state().events.pop_front() {
+            return Ok(event);
+        }
+
+        let mut reader = self.reader.lock().await;
+        loop {
+            if let Some(event) = self.state().events.pop_front() {
+                return Ok(event);
+            }
+            // Responses to requests made in the meantime are handed over to them
+            let line = reader.read_line().await?;
+            self.dispatch(None, line);
+        }
+    }
+}
+
+/// Removes a request from the pending list if it is cancelled before its response arrives
+struct PendingGuard<'a> {
+    state: &'a StdMutex<MultiplexerState>,
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.pending.remove(&self.id);
+        }
+    }
+}
+
+/// Keeps a partially read line across calls, so that a request which is cancelled
+/// while holding the reader does not leave the rest of the line for the next one
+struct LineReader<R> {
+    reader: BufReader<R>,
+    line: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            line: Vec::new(),
+        }
     }
 
-    socket.write_all(payload.as_bytes()).await?;
-    socket.write_all(b"\n").await?;
+    /// Cancellation safe
+    async fn read_line(&mut self) -> anyhow::Result<String> {
+        if self.reader.read_until(b'\n', &mut self.line).await? == 0 && self.line.is_empty() {
+            return Err(anyhow!("Connection closed"));
+        }
+        String::from_utf8(mem::take(&mut self.line)).context("Daemon sent invalid UTF-8")
+    }
+}
+
+/// Authenticate a freshly opened remote connection
+async fn authenticate<S: AsyncRead + AsyncWrite>(
+    connection: &Multiplexer<S>,
+    token: &str,
+) -> anyhow::Result<()> {
+    let request = Request::Authenticate {
+        token: AuthToken(token.into()),
+    };
+    let response = connection.request(&request).await?;
+    crate::deserialize_response::<()>(&response).context("Could not authenticate with the daemon")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Multiplexer;
+    use futures::FutureExt;
+    use lact_schema::Request;
+    use serde_json::Value;
+    use tokio::io::{
+        duplex, split, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf,
+        WriteHalf,
+    };
 
-    let mut response_payload = String::new();
-    socket.read_line(&mut response_payload).await?;
+    struct Daemon {
+        lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl Daemon {
+        /// Returns the id of the next request
+        async fn read_request(&mut self) -> u64 {
+            let line = self.lines.next_line().await.unwrap().unwrap();
+            let request: Value = serde_json::from_str(&line).unwrap();
+            request["id"].as_u64().unwrap()
+        }
+
+        async fn send(&mut self, message: &str) {
+            self.writer.write_all(message.as_bytes()).await.unwrap();
+        }
+    }
 
-    Ok(response_payload)
+    fn connect() -> (Multiplexer<DuplexStream>, Daemon) {
+        let (client, daemon) = duplex(4096);
+        let (reader, writer) = split(daemon);
+        let daemon = Daemon {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        (Multiplexer::new(client), daemon)
+    }
+
+    fn data(response: &str) -> Value {
+        serde_json::from_str::<Value>(response).unwrap()["data"].clone()
+    }
+
+    #[tokio::test]
+    async fn out_of_order_responses() {
+        let (connection, mut daemon) = connect();
+
+        let first = connection.request(&Request::Ping);
+        let second = connection.request(&Request::ListDevices);
+        let daemon = async {
+            let first_id = daemon.read_request().await;
+            let second_id = daemon.read_request().await;
+            assert_ne!(first_id, second_id);
+
+            daemon
+                .send(&format!(
+                    "{{\"id\":{second_id},\"status\":\"ok\",\"data\":2}}\n"
+                ))
+                .await;
+            daemon
+                .send(&format!(
+                    "{{\"id\":{first_id},\"status\":\"ok\",\"data\":1}}\n"
+                ))
+                .await;
+        };
+
+        let (first, second, ()) = tokio::join!(first, second, daemon);
+        assert_eq!(1, data(&first.unwrap()));
+        assert_eq!(2, data(&second.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn responses_without_id_answer_oldest_request() {
+        let (connection, mut daemon) = connect();
+
+        let first = connection.request(&Request::Ping);
+        let second = connection.request(&Request::ListDevices);
+        let daemon = async {
+            daemon.read_request().await;
+            daemon.read_request().await;
+            daemon.send("{\"status\":\"ok\",\"data\":1}\n").await;
+            daemon.send("{\"status\":\"ok\",\"data\":2}\n").await;
+        };
+
+        let (first, second, ()) = tokio::join!(first, second, daemon);
+        assert_eq!(1, data(&first.unwrap()));
+        assert_eq!(2, data(&second.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn events_between_responses() {
+        let (connection, mut daemon) = connect();
+
+        let request = connection.request(&Request::Ping);
+        let daemon = async {
+            let id = daemon.read_request().await;
+            daemon.send("{\"event\":\"first\"}\n").await;
+            daemon
+                .send(&format!("{{\"id\":{id},\"status\":\"ok\",\"data\":1}}\n"))
+                .await;
+            daemon.send("{\"event\":\"second\"}\n").await;
+        };
+
+        let (response, ()) = tokio::join!(request, daemon);
+        assert_eq!(1, data(&response.unwrap()));
+
+        let event = connection.receive().await.unwrap();
+        assert_eq!("{\"event\":\"first\"}\n", event);
+        let event = connection.receive().await.unwrap();
+        assert_eq!("{\"event\":\"second\"}\n", event);
+    }
+
+    #[tokio::test]
+    async fn cancelled_read_keeps_partial_line() {
+        let (connection, mut daemon) = connect();
+
+        let mut request = Box::pin(connection.request(&Request::Ping));
+        assert!((&mut request).now_or_never().is_none());
+        daemon.read_request().await;
+
+        // The request is holding the reader when it is cancelled in the middle of a line
+        daemon.send("{\"event\":").await;
+        assert!((&mut request).now_or_never().is_none());
+        drop(request);
+
+        daemon.send("\"partial\"}\n").await;
+        let event = connection.receive().await.unwrap();
+        assert_eq!("{\"event\":\"partial\"}\n", event);
+    }
+
+    #[tokio::test]
+    async fn closed_connection() {
+        let (connection, daemon) = connect();
+        drop(daemon);
+        assert!(connection.receive().await.is_err());
+    }
 }
@@ -1,40 +1,55 @@
-use super::{request, DaemonConnection};
+use super::{authenticate, DaemonConnection, Multiplexer};
 use anyhow::Context;
 use futures::future::BoxFuture;
-use tokio::{
-    io::BufReader,
-    net::{TcpStream, ToSocketAddrs},
-};
+use lact_schema::Request;
+use std::net::SocketAddr;
+use tokio::net::{TcpStream, ToSocketAddrs};
 use tracing::info;
 
 pub struct TcpConnection {
-    inner: BufReader<TcpStream>,
+    inner: Multiplexer<TcpStream>,
+    peer_addr: SocketAddr,
+    auth_token: Option<String>,
 }
 
 impl TcpConnection {
-    pub async fn connect(addr: impl ToSocketAddrs) -> anyhow::Result<Box<Self>> {
+    /// Connects to the daemon, authenticating with `auth_token` if one is given
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        auth_token: Option<String>,
+    ) -> anyhow::Result<Box<Self>> {
         info!("connecting to remote TCP service");
         let inner = TcpStream::connect(addr).await?;
-        Ok(Box::new(Self {
-            inner: BufReader::new(inner),
-        }))
+        let peer_addr = inner.peer_addr().context("Could not read peer address")?;
+        let connection = Self {
+            inner: Multiplexer::new(inner),
+            peer_addr,
+            auth_token,
+        };
+
+        if let Some(token) = &connection.auth_token {
+            authenticate(&connection.inner, token).await?;
+        }
+
+        Ok(Box::new(connection))
     }
 }
 
 impl DaemonConnection for TcpConnection {
-    fn request<'a>(&'a mut self, payload: &'a str) -> BoxFuture<'a, anyhow::Result<String>> {
-        Box::pin(async { request(&mut self.inner, payload).await })
+    fn request<'a>(&'a self, request: &'a Request<'a>) -> BoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(self.inner.request(request))
+    }
+
+    fn receive(&self) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(self.inner.receive())
     }
 
     fn new_connection(&self) -> BoxFuture<'_, anyhow::Result<Box<dyn DaemonConnection>>> {
         Box::pin(async {
-            let peer_addr = self
-                .inner
-                .get_ref()
-                .peer_addr()
-                .context("Could not read peer address")?;
-
-            Ok(Self::connect(peer_addr).await? as Box<dyn DaemonConnection>)
+            Ok(
+                Self::connect(self.peer_addr, self.auth_token.clone()).await?
+                    as Box<dyn DaemonConnection>,
+            )
         })
     }
 }
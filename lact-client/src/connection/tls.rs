@@ -0,0 +1,262 @@
+use super::{authenticate, DaemonConnection, Multiplexer};
+use anyhow::{anyhow, Context};
+use futures::future::BoxFuture;
+use lact_schema::Request;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+use tracing::{info, warn};
+
+/// Certificates used for a TLS connection to the daemon
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// CA to verify the daemon's certificate with. The system certificate store is used if not set.
+    pub ca_cert_path: Option<PathBuf>,
+    /// Certificate to present to the daemon, for when it requires client certificates
+    pub client_cert_path: Option<PathBuf>,
+    pub client_key_path: Option<PathBuf>,
+}
+
+pub struct TlsConnection {
+    inner: Multiplexer<TlsStream<TcpStream>>,
+    addr: String,
+    connector: TlsConnector,
+    auth_token: Option<String>,
+}
+
+impl TlsConnection {
+    /// Connects to the daemon at `addr` (in the `host:port` format), authenticating with `auth_token` if one is given
+    pub async fn connect(
+        addr: &str,
+        options: &TlsOptions,
+        auth_token: Option<String>,
+    ) -> anyhow::Result<Box<Self>> {
+        let connector = TlsConnector::from(Arc::new(client_config(options)?));
+        Self::connect_with(addr.to_owned(), connector, auth_token).await
+    }
+
+    async fn connect_with(
+        addr: String,
+        connector: TlsConnector,
+        auth_token: Option<String>,
+    ) -> anyhow::Result<Box<Self>> {
+        info!("connecting to remote TLS service");
+        let server_name = server_name(&addr)?;
+        let stream = TcpStream::connect(&addr).await?;
+        let stream = connector
+            .connect(server_name, stream)
+            .await
+            .context("TLS handshake failed")?;
+
+        let connection = Self {
+            inner: Multiplexer::new(stream),
+            addr,
+            connector,
+            auth_token,
+        };
+
+        if let Some(token) = &connection.auth_token {
+            authenticate(&connection.inner, token).await?;
+        }
+
+        Ok(Box::new(connection))
+    }
+}
+
+impl DaemonConnection for TlsConnection {
+    fn request<'a>(&'a self, request: &'a Request<'a>) -> BoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(self.inner.request(request))
+    }
+
+    fn receive(&self) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(self.inner.receive())
+    }
+
+    fn new_connection(&self) -> BoxFuture<'_, anyhow::Result<Box<dyn DaemonConnection>>> {
+        Box::pin(async {
+            Ok(Self::connect_with(
+                self.addr.clone(),
+                self.connector.clone(),
+                self.auth_token.clone(),
+            )
+            .await? as Box<dyn DaemonConnection>)
+        })
+    }
+}
+
+fn client_config(options: &TlsOptions) -> anyhow::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    match &options.ca_cert_path {
+        Some(path) => {
+            for cert in load_certs(path)? {
+                roots.add(cert).context("Could not add CA certificate")?;
+            }
+        }
+        None => {
+            let native_certs = rustls_native_certs::load_native_certs();
+            for error in native_certs.errors {
+                warn!("could not load system certificate: {error}");
+            }
+            let (added, ignored) = roots.add_parsable_certificates(native_certs.certs);
+            info!("loaded {added} system certificates ({ignored} ignored)");
+        }
+    }
+
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let config = match (&options.client_cert_path, &options.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = load_certs(cert_path)?;
+            let key = PrivateKeyDer::from_pem_file(key_path).with_context(|| {
+                format!("Could not read client key from {}", key_path.display())
+            })?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("Invalid client certificate or key")?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(anyhow!(
+                "Both a client certificate and a key have to be specified"
+            ))
+        }
+    };
+    Ok(config)
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect)
+        .with_context(|| format!("Could not read certificates from {}", path.display()))
+}
+
+/// Extracts the host part of a `host:port` address, which the daemon's certificate is verified against
+fn server_name(addr: &str) -> anyhow::Result<ServerName<'static>> {
+    let host = addr
+        .rsplit_once(':')
+        .map_or(addr, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    ServerName::try_from(host.to_owned()).with_context(|| format!("Invalid server name '{host}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{server_name, TlsConnection, TlsOptions};
+    use crate::connection::DaemonConnection;
+    use lact_schema::Request;
+    use std::{path::PathBuf, sync::Arc};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+    use tokio_rustls::{
+        rustls::{
+            pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+            ServerConfig,
+        },
+        TlsAcceptor,
+    };
+
+    /// Test certificates of the daemon, the server certificate is signed by `ca.pem` and valid for `localhost`
+    fn cert_path(name: &str) -> PathBuf {
+        PathBuf::from(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../lact-daemon/src/tests/tls"
+        ))
+        .join(name)
+    }
+
+    /// Accepts a single connection on localhost and answers every request with an empty response
+    async fn serve() -> String {
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from_pem_file(cert_path("server.pem")).unwrap()],
+                PrivateKeyDer::from_pem_file(cert_path("server.key")).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let Ok(stream) = acceptor.accept(stream).await else {
+                return;
+            };
+            let mut lines = BufReader::new(stream).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let response = format!(
+                    "{{\"id\":{},\"status\":\"ok\",\"data\":null}}\n",
+                    request["id"]
+                );
+                lines
+                    .get_mut()
+                    .write_all(response.as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        format!("localhost:{port}")
+    }
+
+    fn options(ca: &str) -> TlsOptions {
+        TlsOptions {
+            ca_cert_path: Some(cert_path(ca)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn connect() {
+        let addr = serve().await;
+        let connection = TlsConnection::connect(&addr, &options("ca.pem"), None)
+            .await
+            .unwrap();
+
+        let response = connection.request(&Request::Ping).await.unwrap();
+        assert!(response.contains("\"status\":\"ok\""), "{response}");
+    }
+
+    #[tokio::test]
+    async fn untrusted_cert_is_rejected() {
+        let addr = serve().await;
+        let err = TlsConnection::connect(&addr, &options("other-ca.pem"), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("UnknownIssuer"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn wrong_host_is_rejected() {
+        let addr = serve().await;
+        let addr = addr.replace("localhost", "127.0.0.1");
+        let err = TlsConnection::connect(&addr, &options("ca.pem"), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("not valid for name"), "{err:#}");
+    }
+
+    #[test]
+    fn server_names() {
+        assert_eq!(
+            server_name("localhost:12853").unwrap().to_str(),
+            "localhost"
+        );
+        assert_eq!(server_name("[::1]:12853").unwrap().to_str(), "::1");
+    }
+}
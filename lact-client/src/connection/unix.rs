@@ -1,13 +1,15 @@
-use super::{request, DaemonConnection};
+use super::{DaemonConnection, Multiplexer};
 use anyhow::Context;
 use futures::future::BoxFuture;
+use lact_schema::Request;
 use std::os::unix::net::UnixStream as StdUnixStream;
-use std::path::Path;
-use tokio::{io::BufReader, net::UnixStream};
+use std::path::{Path, PathBuf};
+use tokio::net::UnixStream;
 use tracing::info;
 
 pub struct UnixConnection {
-    inner: BufReader<UnixStream>,
+    inner: Multiplexer<UnixStream>,
+    path: Option<PathBuf>,
 }
 
 impl UnixConnection {
@@ -15,15 +17,21 @@ impl UnixConnection {
         info!("connecting to service at {path:?}");
         let inner = UnixStream::connect(path).await?;
         Ok(Box::new(Self {
-            inner: BufReader::new(inner),
+            inner: Multiplexer::new(inner),
+            path: Some(path.to_owned()),
         }))
     }
 }
 
 impl From<UnixStream> for UnixConnection {
     fn from(inner: UnixStream) -> Self {
+        let path = inner
+            .peer_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(Path::to_owned));
         Self {
-            inner: BufReader::new(inner),
+            inner: Multiplexer::new(inner),
+            path,
         }
     }
 }
@@ -37,19 +45,19 @@ impl TryFrom<StdUnixStream> for UnixConnection {
 }
 
 impl DaemonConnection for UnixConnection {
-    fn request<'a>(&'a mut self, payload: &'a str) -> BoxFuture<'a, anyhow::Result<String>> {
-        Box::pin(async { request(&mut self.inner, payload).await })
+    fn request<'a>(&'a self, request: &'a Request<'a>) -> BoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(self.inner.request(request))
+    }
+
+    fn receive(&self) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(self.inner.receive())
     }
 
     fn new_connection(&self) -> BoxFuture<'_, anyhow::Result<Box<dyn DaemonConnection>>> {
         Box::pin(async {
-            let peer_addr = self
-                .inner
-                .get_ref()
-                .peer_addr()
-                .context("Could not read peer address")?;
-            let path = peer_addr
-                .as_pathname()
+            let path = self
+                .path
+                .as_deref()
                 .context("Connected socket addr is not a path")?;
 
             Ok(Self::connect(path).await? as Box<dyn DaemonConnection>)
@@ -9,65 +9,124 @@ use amdgpu_sysfs::gpu_handle::{
     power_profile_mode::PowerProfileModesTable, PerformanceLevel, PowerLevelKind,
 };
 use anyhow::Context;
-use connection::{tcp::TcpConnection, unix::UnixConnection, DaemonConnection};
+pub use connection::tls::TlsOptions;
+
+use connection::{tcp::TcpConnection, tls::TlsConnection, unix::UnixConnection, DaemonConnection};
+use futures::{stream, Stream};
 use nix::unistd::getuid;
 use schema::{
-    request::{ConfirmCommand, ProfileBase, SetClocksCommand},
-    ClocksInfo, DeviceInfo, DeviceListEntry, DeviceStats, FanOptions, PowerStates, ProfilesInfo,
-    Request, Response, SystemInfo,
+    api_schema::ApiSchema,
+    request::{features, ConfirmCommand, ProfileBase, SetClocksCommand, PROTOCOL_REVISION},
+    AuditLogEntry, Capabilities, ClocksInfo, ConfigRevision, ConfigValidation, DeviceInfo,
+    DeviceListEntry, DeviceStats, DeviceStatsHistoryEntry, ErrorKind, Event, FanOptions,
+    PowerStates, ProfilesInfo, Request, Response, SaveStatus, SystemInfo,
 };
 use serde::de::DeserializeOwned;
 use std::{
-    future::Future, os::unix::net::UnixStream, path::PathBuf, pin::Pin, rc::Rc, time::Duration,
+    cell::{Cell, RefCell},
+    future::Future,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    pin::Pin,
+    rc::Rc,
+    time::Duration,
 };
 use tokio::{
     net::ToSocketAddrs,
-    sync::{broadcast, Mutex},
+    sync::{broadcast, RwLock},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 const STATUS_MSG_CHANNEL_SIZE: usize = 16;
 const RECONNECT_INTERVAL_MS: u64 = 250;
+const SOCKET_NAME: &str = "lactd.sock";
+const MONITOR_SOCKET_NAME: &str = "lactd-monitor.sock";
 
+/// Errors returned by the daemon are [`schema::ApiError`]s, use [`ErrorKind::of`] to branch on their kind
 #[derive(Clone)]
 pub struct DaemonClient {
-    stream: Rc<Mutex<Box<dyn DaemonConnection>>>,
+    /// Requests share the connection, reconnecting takes exclusive access
+    stream: Rc<RwLock<Box<dyn DaemonConnection>>>,
+    /// Incremented on every reconnect, so that requests which failed on the same connection only reconnect once
+    generation: Rc<Cell<u64>>,
+    /// Reported by the daemon when connecting, `None` if it predates capability negotiation
+    capabilities: Rc<RefCell<Option<Capabilities>>>,
     status_tx: broadcast::Sender<ConnectionStatusMsg>,
     pub embedded: bool,
 }
 
 impl DaemonClient {
+    /// Connect to the local daemon. Falls back to the read-only monitor socket
+    /// if the admin socket is not accessible to the current user.
     pub async fn connect() -> anyhow::Result<Self> {
-        let path =
-            get_socket_path().context("Could not connect to daemon: socket file not found")?;
-        let stream = UnixConnection::connect(&path).await?;
+        let path = get_socket_path(SOCKET_NAME)
+            .context("Could not connect to daemon: socket file not found")?;
+        let stream = match UnixConnection::connect(&path).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                match get_socket_path(MONITOR_SOCKET_NAME) {
+                    Some(monitor_path) => {
+                        info!("could not connect to {path:?} ({err:#}), using read-only monitor socket");
+                        UnixConnection::connect(&monitor_path).await?
+                    }
+                    None => return Err(err),
+                }
+            }
+        };
 
-        Ok(Self {
-            stream: Rc::new(Mutex::new(stream)),
-            embedded: false,
-            status_tx: broadcast::Sender::new(STATUS_MSG_CHANNEL_SIZE),
-        })
+        Ok(Self::with_connection(stream).await)
     }
 
-    pub async fn connect_tcp(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
-        let stream = TcpConnection::connect(addr).await?;
+    pub async fn connect_tcp(
+        addr: impl ToSocketAddrs,
+        auth_token: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let stream = TcpConnection::connect(addr, auth_token.map(str::to_owned)).await?;
 
-        Ok(Self {
-            stream: Rc::new(Mutex::new(stream)),
-            embedded: false,
-            status_tx: broadcast::Sender::new(STATUS_MSG_CHANNEL_SIZE),
-        })
+        Ok(Self::with_connection(stream).await)
+    }
+
+    /// Connect to a remote daemon over TLS. `addr` has to be in the `host:port` format,
+    /// as the host is used to verify the daemon's certificate.
+    pub async fn connect_tls(
+        addr: &str,
+        tls_options: &TlsOptions,
+        auth_token: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let stream =
+            TlsConnection::connect(addr, tls_options, auth_token.map(str::to_owned)).await?;
+
+        Ok(Self::with_connection(stream).await)
     }
 
+    /// Capabilities are not negotiated, as an embedded daemon is always the same version as the client
     pub fn from_stream(stream: UnixStream, embedded: bool) -> anyhow::Result<Self> {
         let connection = UnixConnection::try_from(stream)?;
         Ok(Self {
-            stream: Rc::new(Mutex::new(Box::new(connection))),
+            stream: Rc::new(RwLock::new(Box::new(connection))),
+            generation: Rc::default(),
+            capabilities: Rc::default(),
             embedded,
             status_tx: broadcast::Sender::new(STATUS_MSG_CHANNEL_SIZE),
         })
     }
 
+    async fn with_connection(stream: Box<dyn DaemonConnection>) -> Self {
+        let capabilities = negotiate(&*stream).await;
+        Self {
+            stream: Rc::new(RwLock::new(stream)),
+            generation: Rc::default(),
+            capabilities: Rc::new(RefCell::new(capabilities)),
+            embedded: false,
+            status_tx: broadcast::Sender::new(STATUS_MSG_CHANNEL_SIZE),
+        }
+    }
+
+    /// Versions, commands and features supported by the daemon, if it reported them
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities.borrow().clone()
+    }
+
     pub fn status_receiver(&self) -> broadcast::Receiver<ConnectionStatusMsg> {
         self.status_tx.subscribe()
     }
@@ -77,44 +136,152 @@ impl DaemonClient {
         request: Request<'a>,
     ) -> Pin<Box<dyn Future<Output = anyhow::Result<T>> + 'a>> {
         Box::pin(async {
-            let mut stream = self.stream.lock().await;
-
-            let request_payload = serde_json::to_string(&request)?;
-            match stream.request(&request_payload).await {
-                Ok(response_payload) => {
-                    let response: Response<T> = serde_json::from_str(&response_payload)
-                        .context("Could not deserialize response from daemon")?;
-                    match response {
-                        Response::Ok(data) => Ok(data),
-                        Response::Error(err) => Err(anyhow::Error::new(err)
-                            .context("Got error from daemon, end of client boundary")),
-                    }
+            if let Some(capabilities) = &*self.capabilities.borrow() {
+                let command = request.command();
+                if !capabilities.commands.iter().any(|name| name == command) {
+                    return Err(ErrorKind::Unsupported.error(format!(
+                        "The daemon (version {}) does not support the `{command}` request, it may need to be updated",
+                        capabilities.daemon_version
+                    )));
                 }
+            }
+
+            let stream = self.stream.read().await;
+            let generation = self.generation.get();
+
+            match stream.request(&request).await {
+                Ok(response_payload) => deserialize_response(&response_payload),
                 Err(err) => {
+                    drop(stream);
                     error!("Could not make request: {err}, reconnecting to socket");
-                    let _ = self.status_tx.send(ConnectionStatusMsg::Disconnected);
+                    self.reconnect(generation).await;
+                    self.make_request(request).await
+                }
+            }
+        })
+    }
 
-                    loop {
-                        match stream.new_connection().await {
-                            Ok(new_connection) => {
-                                info!("Established new socket connection");
-                                *stream = new_connection;
-                                drop(stream);
+    /// Replaces the connection that failed, unless another request has already done so
+    async fn reconnect(&self, failed_generation: u64) {
+        let mut stream = self.stream.write().await;
+        if self.generation.get() != failed_generation {
+            return;
+        }
+        let _ = self.status_tx.send(ConnectionStatusMsg::Disconnected);
+
+        loop {
+            match stream.new_connection().await {
+                Ok(new_connection) => {
+                    info!("Established new socket connection");
+                    *self.capabilities.borrow_mut() = negotiate(&*new_connection).await;
+                    *stream = new_connection;
+                    self.generation.set(failed_generation + 1);
+
+                    let _ = self.status_tx.send(ConnectionStatusMsg::Reconnected);
+                    return;
+                }
+                Err(err) => {
+                    error!("Could not reconnect: {err:#}, retrying in {RECONNECT_INTERVAL_MS}ms");
+                    tokio::time::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS)).await;
+                }
+            }
+        }
+    }
+
+    /// Subscribe to periodic stats updates of the given GPU.
+    /// The updates are delivered over a dedicated connection, which is closed when the stream is dropped.
+    pub async fn subscribe_stats(
+        &self,
+        id: &str,
+        interval: Duration,
+    ) -> anyhow::Result<impl Stream<Item = DeviceStats>> {
+        let request = Request::SubscribeStats {
+            id,
+            interval_ms: interval.as_millis().try_into()?,
+        };
+        self.subscribe(&request, |event| match event {
+            Event::DeviceStats { stats, .. } => Some(stats),
+            Event::ProfileChanged { .. } => None,
+        })
+        .await
+    }
 
-                                let _ = self.status_tx.send(ConnectionStatusMsg::Reconnected);
+    /// Subscribe to switches of the current profile. The stream starts with the name of the current profile,
+    /// and is delivered over a dedicated connection just like [`DaemonClient::subscribe_stats`].
+    pub async fn subscribe_profile(&self) -> anyhow::Result<impl Stream<Item = Option<String>>> {
+        self.subscribe(&Request::SubscribeProfile, |event| match event {
+            Event::ProfileChanged { profile } => Some(profile),
+            Event::DeviceStats { .. } => None,
+        })
+        .await
+    }
 
-                                return self.make_request(request).await;
-                            }
-                            Err(err) => {
-                                error!("Could not reconnect: {err:#}, retrying in {RECONNECT_INTERVAL_MS}ms");
-                                tokio::time::sleep(Duration::from_millis(RECONNECT_INTERVAL_MS))
-                                    .await;
+    async fn subscribe<T>(
+        &self,
+        request: &Request<'_>,
+        map_event: fn(Event) -> Option<T>,
+    ) -> anyhow::Result<impl Stream<Item = T>> {
+        let connection = self.stream.read().await.new_connection().await?;
+
+        let response_payload = connection.request(request).await?;
+        deserialize_response::<()>(&response_payload)?;
+
+        let command = request.command();
+        Ok(stream::unfold(connection, move |connection| async move {
+            loop {
+                match connection.receive().await {
+                    Ok(payload) => match serde_json::from_str(&payload) {
+                        Ok(event) => {
+                            if let Some(item) = map_event(event) {
+                                return Some((item, connection));
                             }
                         }
+                        Err(err) => error!("could not deserialize event from daemon: {err}"),
+                    },
+                    Err(err) => {
+                        error!("{command} subscription closed: {err:#}");
+                        return None;
                     }
                 }
             }
+        }))
+    }
+
+    pub async fn get_device_stats_history(
+        &self,
+        id: &str,
+        since: Option<u64>,
+        resolution: Option<u64>,
+    ) -> anyhow::Result<Vec<DeviceStatsHistoryEntry>> {
+        self.make_request(Request::DeviceStatsHistory {
+            id,
+            since,
+            resolution,
+        })
+        .await
+    }
+
+    pub async fn get_audit_log(
+        &self,
+        gpu_id: Option<&str>,
+        since: Option<u64>,
+        limit: Option<usize>,
+    ) -> anyhow::Result<Vec<AuditLogEntry>> {
+        self.make_request(Request::AuditLog {
+            gpu_id,
+            since,
+            limit,
         })
+        .await
+    }
+
+    pub async fn validate_config(&self, yaml: String) -> anyhow::Result<ConfigValidation> {
+        self.make_request(Request::ValidateConfig { yaml }).await
+    }
+
+    pub async fn restore_config_revision(&self, id: u64) -> anyhow::Result<()> {
+        self.make_request(Request::RestoreConfigRevision { id })
+            .await
     }
 
     pub async fn list_devices(&self) -> anyhow::Result<Vec<DeviceListEntry>> {
@@ -130,10 +297,16 @@ impl DaemonClient {
     }
 
     request_plain!(get_system_info, SystemInfo, SystemInfo);
+    request_plain!(get_api_schema, ApiSchema, ApiSchema);
     request_plain!(enable_overdrive, EnableOverdrive, String);
     request_plain!(disable_overdrive, DisableOverdrive, String);
     request_plain!(generate_debug_snapshot, GenerateSnapshot, String);
     request_plain!(reset_config, RestConfig, ());
+    request_plain!(
+        list_config_revisions,
+        ListConfigRevisions,
+        Vec<ConfigRevision>
+    );
     request_with_id!(get_device_info, DeviceInfo, DeviceInfo);
     request_with_id!(get_device_stats, DeviceStats, DeviceStats);
     request_with_id!(get_device_clocks_info, DeviceClocksInfo, ClocksInfo);
@@ -238,21 +411,82 @@ impl DaemonClient {
         .await
     }
 
-    pub async fn confirm_pending_config(&self, command: ConfirmCommand) -> anyhow::Result<()> {
+    /// Returns whether the settings were saved when confirming.
+    /// `None` when reverting, or when the daemon does not report it.
+    pub async fn confirm_pending_config(
+        &self,
+        command: ConfirmCommand,
+    ) -> anyhow::Result<Option<SaveStatus>> {
         self.make_request(Request::ConfirmPendingConfig(command))
             .await
     }
 }
 
-fn get_socket_path() -> Option<PathBuf> {
-    let root_path = PathBuf::from("/var/run/lactd.sock");
+/// Returns `None` when the daemon predates capability negotiation
+async fn negotiate(connection: &dyn DaemonConnection) -> Option<Capabilities> {
+    let request = Request::Hello {
+        client_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+        features: features::ALL
+            .iter()
+            .map(|feature| (*feature).to_owned())
+            .collect(),
+    };
+    let capabilities = match connection.request(&request).await {
+        Ok(payload) => match deserialize_response::<Capabilities>(&payload) {
+            Ok(capabilities) => capabilities,
+            Err(err) => {
+                info!("daemon does not support capability negotiation: {err:#}");
+                return None;
+            }
+        },
+        Err(err) => {
+            error!("could not negotiate capabilities: {err:#}");
+            return None;
+        }
+    };
+
+    if capabilities.protocol_revision != PROTOCOL_REVISION {
+        warn!(
+            "daemon uses protocol revision {}, while the client uses {PROTOCOL_REVISION}",
+            capabilities.protocol_revision
+        );
+    }
+    let missing_features: Vec<&str> = features::ALL
+        .iter()
+        .copied()
+        .filter(|feature| !capabilities.features.iter().any(|name| name == feature))
+        .collect();
+    if !missing_features.is_empty() {
+        warn!(
+            "daemon version {} does not support features: {}",
+            capabilities.daemon_version,
+            missing_features.join(", ")
+        );
+    }
+
+    Some(capabilities)
+}
+
+fn deserialize_response<T: DeserializeOwned>(payload: &str) -> anyhow::Result<T> {
+    let response: Response<T> =
+        serde_json::from_str(payload).context("Could not deserialize response from daemon")?;
+    match response {
+        Response::Ok(data) => Ok(data),
+        Response::Error(err) => {
+            Err(anyhow::Error::new(err).context("Got error from daemon, end of client boundary"))
+        }
+    }
+}
+
+fn get_socket_path(name: &str) -> Option<PathBuf> {
+    let root_path = PathBuf::from(format!("/var/run/{name}"));
 
     if root_path.exists() {
         return Some(root_path);
     }
 
     let uid = getuid();
-    let user_path = PathBuf::from(format!("/var/run/user/{}/lactd.sock", uid));
+    let user_path = PathBuf::from(format!("/var/run/user/{uid}/{name}"));
 
     if user_path.exists() {
         Some(user_path)
@@ -1,3 +1,9 @@
+mod document;
+pub mod fragments;
+pub mod history;
+mod inheritance;
+mod merge;
+
 use crate::server::gpu_controller::{fan_control::FanCurve, VENDOR_NVIDIA};
 use amdgpu_sysfs::gpu_handle::{PerformanceLevel, PowerLevelKind};
 use anyhow::Context;
@@ -7,19 +13,19 @@ use lact_schema::{
     request::{ClockspeedType, SetClocksCommand},
     FanControlMode, PmfwOptions, ProfileRule,
 };
-use nix::unistd::getuid;
+use nix::unistd::{access, getuid, AccessFlags};
 use notify::{RecommendedWatcher, Watcher};
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::{
     cell::Cell,
     env, fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
     time::{Duration, Instant},
 };
 use tokio::{sync::mpsc, time};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 const FILE_NAME: &str = "config.yaml";
 const DEFAULT_ADMIN_GROUPS: [&str; 2] = ["wheel", "sudo"];
@@ -61,12 +67,31 @@ impl Default for Config {
 
 #[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Daemon {
     pub log_level: String,
     pub admin_groups: Vec<String>,
     #[serde(default)]
     pub disable_clocks_cleanup: bool,
+    /// Expose a second socket accessible by all users, which only allows read-only requests
+    #[serde(default)]
+    pub monitor_socket: bool,
+    pub access_control: Option<AccessControl>,
+    /// Serve the `io.github.lact_linux.Daemon` interface on the system bus
+    #[serde(default)]
+    pub dbus_interface: bool,
     pub tcp_listen_address: Option<String>,
+    pub tcp_auth_token: Option<String>,
+    pub tcp_tls: Option<TlsConfig>,
+    pub metrics_listen_address: Option<String>,
+    pub http_listen_address: Option<String>,
+    pub http_auth_token: Option<String>,
+    pub mqtt: Option<MqttConfig>,
+    pub stats_sample_interval_ms: Option<u64>,
+    pub stats_history_seconds: Option<u64>,
+    /// Never write the config file. Settings changes are only kept until the daemon is restarted.
+    #[serde(default)]
+    pub immutable: bool,
 }
 
 impl Default for Daemon {
@@ -75,17 +100,92 @@ impl Default for Daemon {
             log_level: "info".to_owned(),
             admin_groups: DEFAULT_ADMIN_GROUPS.map(str::to_owned).to_vec(),
             disable_clocks_cleanup: false,
+            monitor_socket: false,
+            access_control: None,
+            dbus_interface: false,
             tcp_listen_address: None,
+            tcp_auth_token: None,
+            tcp_tls: None,
+            metrics_listen_address: None,
+            http_listen_address: None,
+            http_auth_token: None,
+            mqtt: None,
+            stats_sample_interval_ms: None,
+            stats_history_seconds: None,
+            immutable: false,
         }
     }
 }
 
+/// Per-user access policy for the unix socket, checked against the credentials of the connecting process.
+/// Users and groups can be specified either by name or by id.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct AccessControl {
+    /// Users who can change settings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_users: Vec<String>,
+    /// Groups whose members can change settings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_groups: Vec<String>,
+    /// Users who cannot access the daemon at all, even if they are in an allowed group
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_users: Vec<String>,
+    /// Groups whose members cannot access the daemon at all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_groups: Vec<String>,
+    /// Limits the GPUs that a user can change settings of. Users not listed here can change all GPUs.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub user_gpus: IndexMap<String, Vec<String>>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM-encoded certificate chain of the server
+    pub cert: PathBuf,
+    /// PEM-encoded private key of the server
+    pub key: PathBuf,
+    /// When set, clients are required to present a certificate signed by this CA
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Connection to an MQTT broker, which is used to publish stats and receive commands
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MqttConfig {
+    /// `mqtt://host:port`, or `mqtts://host:port` for TLS
+    pub broker_url: String,
+    /// Prefix of all topics, `lact` by default
+    pub topic_prefix: Option<String>,
+    /// `lact-<hostname>` by default
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// How often stats are published, in milliseconds
+    pub publish_interval_ms: Option<u64>,
+}
+
 #[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct Profile {
+    /// Name of the profile that this profile inherits its settings from
+    pub extends: Option<Rc<str>>,
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
     pub gpus: IndexMap<String, Gpu>,
     pub rule: Option<ProfileRule>,
+    /// Settings of the base profile that `gpus` was last resolved with
+    #[serde(skip)]
+    inherited: IndexMap<String, Gpu>,
+}
+
+impl Profile {
+    /// A profile that inherits all of its settings from the given profile
+    pub fn extending(base: Rc<str>) -> Self {
+        Self {
+            extends: Some(base),
+            ..Default::default()
+        }
+    }
 }
 
 #[skip_serializing_none]
@@ -196,13 +296,20 @@ pub fn default_fan_static_speed() -> f64 {
     0.5
 }
 
+/// Result of [`Config::save`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveOutcome {
+    Written,
+    /// The config is immutable, so the settings are only kept until the daemon is restarted
+    NotPersisted,
+}
+
 impl Config {
     pub fn load() -> anyhow::Result<Option<Self>> {
         let path = get_path();
         if path.exists() {
-            let raw_config = fs::read_to_string(path).context("Could not open config file")?;
-            let config =
-                serde_yaml::from_str(&raw_config).context("Could not deserialize config")?;
+            let raw_config = fs::read_to_string(&path).context("Could not open config file")?;
+            let config = Self::parse_with_fragments(&raw_config, &fragments::dir(&path))?;
             Ok(Some(config))
         } else {
             let parent = path.parent().unwrap();
@@ -211,25 +318,90 @@ impl Config {
         }
     }
 
-    pub fn save(&self, config_last_saved: &Cell<Instant>) -> anyhow::Result<()> {
+    /// Writes the config file. The inherited settings of profiles are updated beforehand.
+    pub fn save(&mut self, config_last_saved: &Cell<Instant>) -> anyhow::Result<SaveOutcome> {
+        self.resolve_profiles()?;
+
         let path = get_path();
+        if self.is_immutable() {
+            info!("config is immutable, settings are only applied until the daemon is restarted");
+            return Ok(SaveOutcome::NotPersisted);
+        }
         debug!("saving config to {path:?}");
-        let raw_config = serde_yaml::to_string(self)?;
+        let value = self.to_file_value(&fragments::dir(&path))?;
+        // Comments and formatting of the existing file are kept
+        let raw_config = match fs::read_to_string(&path) {
+            Ok(previous) => document::update(&previous, &value)?,
+            Err(_) => serde_yaml::to_string(&value)?,
+        };
 
-        fs::write(path, raw_config).context("Could not write config")?;
+        history::write(&path, &raw_config)?;
         config_last_saved.set(Instant::now());
 
-        Ok(())
+        Ok(SaveOutcome::Written)
     }
 
     pub fn load_or_create() -> anyhow::Result<Self> {
         if let Some(config) = Config::load()? {
             Ok(config)
         } else {
-            let config = Config::default();
-            config.save(&Cell::new(Instant::now()))?;
-            Ok(config)
+            Config::default().save(&Cell::new(Instant::now()))?;
+            // Fragments may already be present on a fresh install
+            Config::load()?.context("Config was not created")
+        }
+    }
+
+    /// If the config file should never be written, either because it is configured so
+    /// or because it is on a read-only location (such as the Nix store)
+    pub fn is_immutable(&self) -> bool {
+        let path = get_path();
+        self.daemon.immutable || (path.exists() && access(&path, AccessFlags::W_OK).is_err())
+    }
+
+    /// The contents of the main config file, without the settings that are inherited by profiles or provided by the fragments
+    fn to_file_value(&self, fragments_dir: &Path) -> anyhow::Result<serde_yaml::Value> {
+        let mut value = serde_yaml::to_value(self)?;
+        inheritance::strip_inherited(self, &mut value)?;
+        // Settings coming from the fragments are not duplicated into the main config file
+        match fragments::read(fragments_dir) {
+            Ok(overlay) => fragments::remove_overlay(&mut value, &overlay),
+            Err(err) => warn!("could not read config fragments: {err:#}"),
         }
+        Ok(value)
+    }
+
+    /// Returns an error if the settings of the GPU in the current profile are provided by a config fragment,
+    /// as they would be reverted to the ones in the fragment on the next load
+    pub fn check_gpu_editable(&self, id: &str) -> anyhow::Result<()> {
+        let overlay = fragments::read(&fragments::dir(&get_path()))?;
+        match &self.current_profile {
+            Some(profile) => fragments::check_editable(&overlay, "profiles", profile),
+            None => fragments::check_editable(&overlay, "gpus", id),
+        }
+    }
+
+    /// Returns an error if the profile is provided by a config fragment, see [`Config::check_gpu_editable`]
+    pub fn check_profile_editable(name: &str) -> anyhow::Result<()> {
+        let overlay = fragments::read(&fragments::dir(&get_path()))?;
+        fragments::check_editable(&overlay, "profiles", name)
+    }
+
+    /// Parses the main config file and merges the fragments from `config.d` on top of it
+    fn parse_with_fragments(raw_config: &str, fragments_dir: &Path) -> anyhow::Result<Self> {
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str(raw_config).context("Could not deserialize config")?;
+        fragments::merge(&mut value, fragments::read(fragments_dir)?);
+        Self::from_value(value)
+    }
+
+    /// Deserializes the config, filling in the settings that profiles inherit from their base profiles
+    pub fn from_value(mut value: serde_yaml::Value) -> anyhow::Result<Self> {
+        inheritance::resolve_value(&mut value)?;
+        let mut config: Self =
+            serde_yaml::from_value(value).context("Could not deserialize config")?;
+        inheritance::set_inherited(&mut config);
+        config.resolve_profiles()?;
+        Ok(config)
     }
 
     pub fn migrate_versions(&mut self) {
@@ -307,6 +479,32 @@ impl Config {
         }
     }
 
+    /// Takes the GPU settings and profiles from another config, such as a stored revision.
+    /// The daemon settings are kept, as most of them only take effect on startup.
+    pub fn restore_settings(&mut self, revision: Config) {
+        self.apply_settings_timer = revision.apply_settings_timer;
+        self.gpus = revision.gpus;
+        self.profiles = revision.profiles;
+        self.current_profile = revision.current_profile;
+        self.auto_switch_profiles = revision.auto_switch_profiles;
+    }
+
+    /// Get the settings of a GPU in the current profile, see [`find_gpu`]
+    pub fn gpu(&self, id: &str) -> anyhow::Result<Option<&Gpu>> {
+        Ok(find_gpu(self.gpus()?, id))
+    }
+
+    /// Get the entry of a GPU in the current profile for editing.
+    /// If the GPU is only matched by a selector, the settings of the selector are copied into a new entry with the id of the GPU.
+    pub fn gpu_entry(&mut self, id: &str) -> anyhow::Result<&mut Gpu> {
+        let gpus = self.gpus_mut()?;
+        if !gpus.contains_key(id) {
+            let gpu = find_gpu(gpus, id).cloned().unwrap_or_default();
+            gpus.insert(id.to_owned(), gpu);
+        }
+        Ok(&mut gpus[id])
+    }
+
     /// Get a specific profile
     pub fn profile(&self, profile: &str) -> anyhow::Result<&Profile> {
         self.profiles
@@ -318,7 +516,7 @@ impl Config {
     pub fn default_profile(&self) -> Profile {
         Profile {
             gpus: self.gpus.clone(),
-            rule: None,
+            ..Default::default()
         }
     }
 
@@ -329,6 +527,50 @@ impl Config {
     }
 }
 
+/// Finds the settings for a GPU. Keys in the GPU map can either be exact GPU ids or selectors,
+/// which use `*` as a wildcard (for example `1002:747E-*` matches every GPU with this model regardless of the PCI slot).
+///
+/// An entry with the exact id always takes precedence. Otherwise the most specific matching selector
+/// (with the most characters that are not wildcards) is used, and the first one wins if there are several.
+pub fn find_gpu<'a>(gpus: &'a IndexMap<String, Gpu>, id: &str) -> Option<&'a Gpu> {
+    if let Some(gpu) = gpus.get(id) {
+        return Some(gpu);
+    }
+
+    gpus.iter()
+        .filter(|(key, _)| is_selector(key) && selector_matches(key, id))
+        .rev()
+        .max_by_key(|(key, _)| key.len() - key.matches('*').count())
+        .map(|(_, gpu)| gpu)
+}
+
+pub fn is_selector(key: &str) -> bool {
+    key.contains('*')
+}
+
+pub fn selector_matches(selector: &str, id: &str) -> bool {
+    let selector = selector.to_ascii_uppercase();
+    let id = id.to_ascii_uppercase();
+
+    let parts: Vec<&str> = selector.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return selector == id;
+    }
+    if id.len() < first.len() + last.len() || !id.starts_with(first) || !id.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &id[first.len()..id.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
 pub fn start_watcher(config_last_saved: Rc<Cell<Instant>>) -> mpsc::UnboundedReceiver<Config> {
     let (config_tx, config_rx) = mpsc::unbounded_channel();
     let (event_tx, mut event_rx) = mpsc::channel(64);
@@ -355,6 +597,15 @@ pub fn start_watcher(config_last_saved: Rc<Cell<Instant>>) -> mpsc::UnboundedRec
                     if let EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_) =
                         event.kind
                     {
+                        // The directory also contains the config history and fragments in `config.d`
+                        if !event
+                            .paths
+                            .iter()
+                            .any(|path| fragments::affects_config(&config_path, path))
+                        {
+                            continue;
+                        }
+
                         if config_last_saved.get().elapsed()
                             < Duration::from_millis(SELF_CONFIG_EDIT_PERIOD_MILLIS)
                         {
@@ -435,11 +686,16 @@ fn default_apply_settings_timer() -> u64 {
 
 #[cfg(test)]
 mod tests {
-    use super::{ClocksConfiguration, Config, Daemon, FanControlSettings, Gpu};
+    use super::{
+        find_gpu, selector_matches, ClocksConfiguration, Config, Daemon, FanControlSettings, Gpu,
+        Profile, SaveOutcome,
+    };
     use crate::server::gpu_controller::fan_control::FanCurve;
     use indexmap::IndexMap;
     use insta::assert_yaml_snapshot;
     use lact_schema::{FanControlMode, PmfwOptions};
+    use std::{cell::Cell, fs, time::Instant};
+    use tempfile::tempdir;
 
     #[test]
     fn serde_de_full() {
@@ -486,6 +742,51 @@ mod tests {
         assert_yaml_snapshot!(deserialized_config);
     }
 
+    #[test]
+    fn immutable_save_is_not_persisted() {
+        let mut config = Config::default();
+        config.daemon.immutable = true;
+
+        let last_saved = Instant::now();
+        let config_last_saved = Cell::new(last_saved);
+        assert_eq!(
+            config.save(&config_last_saved).unwrap(),
+            SaveOutcome::NotPersisted
+        );
+        assert_eq!(config_last_saved.get(), last_saved);
+    }
+
+    #[test]
+    fn fragments_round_trip() {
+        let dir = tempdir().unwrap();
+        let fragments_dir = dir.path().join("config.d");
+        fs::create_dir(&fragments_dir).unwrap();
+        fs::write(
+            fragments_dir.join("fleet.yaml"),
+            "gpus:\n  fleet-gpu:\n    fan_control_enabled: false\n    power_cap: 80.0\n",
+        )
+        .unwrap();
+
+        let raw_config = serde_yaml::to_string(&Config::default()).unwrap();
+        let mut config = Config::parse_with_fragments(&raw_config, &fragments_dir).unwrap();
+        assert_eq!(config.gpus["fleet-gpu"].power_cap, Some(80.0));
+
+        config.gpus.insert(
+            "local-gpu".to_owned(),
+            Gpu {
+                power_cap: Some(100.0),
+                ..Default::default()
+            },
+        );
+
+        let value = config.to_file_value(&fragments_dir).unwrap();
+        assert!(value["gpus"].get("fleet-gpu").is_none());
+
+        let raw_config = serde_yaml::to_string(&value).unwrap();
+        let reloaded = Config::parse_with_fragments(&raw_config, &fragments_dir).unwrap();
+        assert_eq!(reloaded.gpus, config.gpus);
+    }
+
     #[test]
     fn clocks_configuration_applied() {
         let mut gpu = Gpu {
@@ -570,4 +871,83 @@ mod tests {
             Some(920),
         );
     }
+
+    #[test]
+    fn gpu_selectors() {
+        let id = "1002:747E-1DA2:E471-0000:03:00.0";
+        assert!(selector_matches("1002:747E-*", id));
+        assert!(selector_matches("1002:747e-*:*-*", id));
+        assert!(selector_matches("*-0000:03:00.0", id));
+        assert!(!selector_matches("1002:744C-*", id));
+        assert!(!selector_matches("1002:747E-1DA2:E471", id));
+
+        let gpu = |power_cap| Gpu {
+            power_cap: Some(power_cap),
+            ..Default::default()
+        };
+        let mut gpus: IndexMap<String, Gpu> = [
+            ("1002:*".to_owned(), gpu(100.0)),
+            ("1002:747E-*".to_owned(), gpu(200.0)),
+            ("1002:747E-*:*-*".to_owned(), gpu(250.0)),
+            ("1002:747E-*-*:*".to_owned(), gpu(260.0)),
+            ("10DE:*".to_owned(), gpu(300.0)),
+        ]
+        .into();
+
+        // The most specific selector wins, and the first one of equally specific selectors
+        assert_eq!(find_gpu(&gpus, id).unwrap().power_cap, Some(250.0));
+        assert_eq!(
+            find_gpu(&gpus, "1002:744C-1DA2:E471-0000:04:00.0")
+                .unwrap()
+                .power_cap,
+            Some(100.0)
+        );
+        assert!(find_gpu(&gpus, "8086:56A5-1849:6007-0000:05:00.0").is_none());
+
+        gpus.insert(id.to_owned(), gpu(150.0));
+        assert_eq!(find_gpu(&gpus, id).unwrap().power_cap, Some(150.0));
+    }
+
+    #[test]
+    fn gpu_entry_from_selector() {
+        let id = "1002:747E-1DA2:E471-0000:03:00.0";
+        let mut config = Config::default();
+        config.gpus.insert(
+            "1002:747E-*".to_owned(),
+            Gpu {
+                power_cap: Some(200.0),
+                ..Default::default()
+            },
+        );
+
+        config.gpu_entry(id).unwrap().fan_control_enabled = true;
+
+        let gpu = config.gpu(id).unwrap().unwrap();
+        assert_eq!(gpu.power_cap, Some(200.0));
+        assert!(gpu.fan_control_enabled);
+        assert!(!config.gpus["1002:747E-*"].fan_control_enabled);
+    }
+
+    #[test]
+    fn restore_settings_keeps_daemon() {
+        let mut config = Config::default();
+        config.daemon.tcp_listen_address = Some("127.0.0.1:12853".to_owned());
+        config.gpus.insert("gpu-1".to_owned(), Gpu::default());
+
+        let mut revision = Config::default();
+        revision.daemon.tcp_listen_address = None;
+        revision
+            .profiles
+            .insert("gaming".into(), Profile::default());
+        revision.current_profile = Some("gaming".into());
+
+        config.restore_settings(revision);
+        assert_eq!(
+            config.daemon.tcp_listen_address.as_deref(),
+            Some("127.0.0.1:12853")
+        );
+        assert!(config.gpus.is_empty());
+        assert_eq!(config.current_profile.as_deref(), Some("gaming"));
+        assert!(config.profiles.contains_key("gaming"));
+    }
 }
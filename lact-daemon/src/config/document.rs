@@ -0,0 +1,356 @@
+//! Updating the text of the config file in place, so that the comments, key order and formatting written by the user
+//! are kept when the config is saved.
+//!
+//! Entries with an unchanged value are copied from the existing file as-is. Changed mappings are updated key by key,
+//! and any other changed value is rendered again while keeping the comments around it.
+use serde_yaml::{Mapping, Value};
+use std::ops::Range;
+use tracing::warn;
+
+/// Produces the new contents of the config file based on its current contents
+pub(super) fn update(previous: &str, new_value: &Value) -> anyhow::Result<String> {
+    let plain = serde_yaml::to_string(new_value)?;
+
+    let (Ok(Value::Mapping(previous_map)), Value::Mapping(new_map)) =
+        (serde_yaml::from_str::<Value>(previous), new_value)
+    else {
+        warn!(
+            "the existing config file is not a mapping, its comments and formatting are not kept"
+        );
+        return Ok(plain);
+    };
+
+    let lines: Vec<&str> = previous.lines().collect();
+    let Some(root) = parse_block(&lines, 0..lines.len(), 0) else {
+        warn!("could not parse the layout of the existing config file, its comments and formatting are not kept");
+        return Ok(plain);
+    };
+
+    let mut output = Vec::new();
+    emit(&lines, &root, &previous_map, new_map, &mut output)?;
+    // Comments at the end of the file
+    output.extend(lines[root.trailer].iter().map(|line| (*line).to_owned()));
+
+    let mut contents = output.join("\n");
+    contents.push('\n');
+
+    // Fall back to the plain representation if the file uses syntax that is not handled here
+    if serde_yaml::from_str::<Value>(&contents).is_ok_and(|value| value == *new_value) {
+        Ok(contents)
+    } else {
+        warn!("the existing config file uses unsupported syntax, its comments and formatting are not kept");
+        Ok(plain)
+    }
+}
+
+struct Block {
+    indent: usize,
+    entries: Vec<Entry>,
+    trailer: Range<usize>,
+}
+
+struct Entry {
+    key: Value,
+    /// Comments and blank lines before the key
+    leading: Range<usize>,
+    key_line: usize,
+    /// End of the value, not including comments after it
+    end: usize,
+    comment: Option<String>,
+    children: Option<Block>,
+}
+
+fn parse_block(lines: &[&str], range: Range<usize>, indent: usize) -> Option<Block> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut leading_start = range.start;
+
+    for i in range.clone() {
+        let line = lines[i];
+        let (content, comment) = split_comment(line);
+        if content.trim().is_empty() || content.trim() == "---" {
+            continue;
+        }
+
+        let line_indent = content.len() - content.trim_start().len();
+        let is_sequence_item = content.trim_start().starts_with('-');
+
+        match entries.last_mut() {
+            // More indented lines and sequence items on the same level belong to the previous value
+            Some(entry) if line_indent > indent || (line_indent == indent && is_sequence_item) => {
+                entry.end = i + 1;
+            }
+            _ if line_indent != indent || is_sequence_item => return None,
+            _ => {
+                let (key, _) = split_key(content.trim_start())?;
+                entries.push(Entry {
+                    key,
+                    leading: leading_start..i,
+                    key_line: i,
+                    end: i + 1,
+                    comment: comment.map(str::to_owned),
+                    children: None,
+                });
+            }
+        }
+
+        leading_start = entries.last().map_or(range.start, |entry| entry.end);
+    }
+
+    for entry in &mut entries {
+        // Values on the same line as the key are never split further
+        let (content, _) = split_comment(lines[entry.key_line]);
+        let has_inline_value =
+            split_key(content.trim_start()).is_some_and(|(_, value)| !value.is_empty());
+        if has_inline_value {
+            continue;
+        }
+
+        let body = entry.key_line + 1..entry.end;
+        let child_indent = body.clone().find_map(|i| {
+            let (content, _) = split_comment(lines[i]);
+            let trimmed = content.trim_start();
+            (!trimmed.is_empty()).then(|| (content.len() - trimmed.len(), trimmed.starts_with('-')))
+        });
+        if let Some((child_indent, false)) = child_indent {
+            if child_indent > indent {
+                entry.children = parse_block(lines, body, child_indent);
+            }
+        }
+    }
+
+    let trailer_start = entries.last().map_or(range.start, |entry| entry.end);
+    Some(Block {
+        indent,
+        entries,
+        trailer: trailer_start..range.end,
+    })
+}
+
+fn emit(
+    lines: &[&str],
+    block: &Block,
+    previous: &Mapping,
+    new: &Mapping,
+    output: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    // Keys that were already in the file keep their position, new keys are added at the end
+    let existing = block
+        .entries
+        .iter()
+        .filter_map(|entry| Some(((&entry.key, new.get(&entry.key)?), Some(entry))));
+    let added = new
+        .iter()
+        .filter(|(key, _)| !block.entries.iter().any(|entry| entry.key == **key))
+        .map(|item| (item, None));
+
+    for ((key, value), entry) in existing.chain(added) {
+        let Some(entry) = entry else {
+            output.extend(render(key, value, block.indent)?);
+            continue;
+        };
+
+        let copy = |range: Range<usize>, output: &mut Vec<String>| {
+            output.extend(lines[range].iter().map(|line| (*line).to_owned()));
+        };
+
+        let previous_value = previous.get(key);
+        if previous_value == Some(value) {
+            copy(entry.leading.start..entry.end, output);
+            continue;
+        }
+
+        if let (Some(children), Some(Value::Mapping(previous_map)), Value::Mapping(new_map)) =
+            (&entry.children, previous_value, value)
+        {
+            copy(entry.leading.start..entry.key_line + 1, output);
+            emit(lines, children, previous_map, new_map, output)?;
+        } else {
+            copy(entry.leading.clone(), output);
+            let mut rendered = render(key, value, block.indent)?;
+            if let (Some(comment), [line]) = (&entry.comment, rendered.as_mut_slice()) {
+                line.push(' ');
+                line.push_str(comment);
+            }
+            output.extend(rendered);
+        }
+    }
+
+    Ok(())
+}
+
+fn render(key: &Value, value: &Value, indent: usize) -> anyhow::Result<Vec<String>> {
+    let mut map = Mapping::new();
+    map.insert(key.clone(), value.clone());
+    let rendered = serde_yaml::to_string(&map)?;
+    Ok(rendered
+        .lines()
+        .map(|line| format!("{:indent$}{line}", ""))
+        .collect())
+}
+
+/// Splits a line into its content and a trailing comment
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') if starts_scalar(previous) => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '#') if previous.is_whitespace() => {
+                return (line[..i].trim_end(), Some(&line[i..]));
+            }
+            _ => (),
+        }
+        previous = c;
+    }
+    (line.trim_end(), None)
+}
+
+/// Splits a mapping entry into its parsed key and the raw value text
+fn split_key(content: &str) -> Option<(Value, &str)> {
+    let mut quote = None;
+    let mut previous = ' ';
+    let chars: Vec<(usize, char)> = content.char_indices().collect();
+    for (n, (i, c)) in chars.iter().copied().enumerate() {
+        match (quote, c) {
+            (None, '\'' | '"') if starts_scalar(previous) => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, ':') if chars.get(n + 1).is_none_or(|(_, next)| *next == ' ') => {
+                let key = serde_yaml::from_str(&content[..i]).ok()?;
+                return Some((key, content[i + 1..].trim()));
+            }
+            (None, '{' | '[' | '?' | '&' | '*' | '!') if n == 0 => return None,
+            _ => (),
+        }
+        previous = c;
+    }
+    None
+}
+
+/// Quotes only have a meaning at the start of a scalar
+fn starts_scalar(previous: char) -> bool {
+    previous.is_whitespace() || matches!(previous, ':' | ',' | '[' | '{')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::update;
+    use pretty_assertions::assert_eq;
+    use serde_yaml::Value;
+
+    const CONFIG: &str = r"# Managed by hand, see the notes below
+daemon:
+  log_level: info
+  admin_groups:
+  - wheel
+  disable_clocks_cleanup: false
+
+apply_settings_timer: 5 # seconds
+gpus:
+  # Main card, undervolted because of the small case
+  1002:687F-1043:0555-0000:0b:00.0:
+    fan_control_enabled: true
+    fan_control_settings:
+      mode: curve
+      static_speed: 0.5
+      temperature_key: edge
+      interval_ms: 500
+      curve:
+        40: 0.2   # quiet at idle
+        80: 0.8
+    power_cap: 200.0 # keeps the VRM cool
+    max_core_clock: 1500
+current_profile: null
+
+# end of file
+";
+
+    fn updated(f: impl FnOnce(&mut Value)) -> String {
+        let mut value: Value = serde_yaml::from_str(CONFIG).unwrap();
+        f(&mut value);
+        let contents = update(CONFIG, &value).unwrap();
+        assert_eq!(serde_yaml::from_str::<Value>(&contents).unwrap(), value);
+        contents
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(updated(|_| ()), CONFIG);
+    }
+
+    #[test]
+    fn changed_value_keeps_comments() {
+        let contents = updated(|value| {
+            value["gpus"]["1002:687F-1043:0555-0000:0b:00.0"]["power_cap"] = Value::from(180.0);
+            value["apply_settings_timer"] = Value::from(10);
+        });
+        assert_eq!(
+            contents,
+            CONFIG
+                .replace("power_cap: 200.0 # keeps", "power_cap: 180.0 # keeps")
+                .replace("apply_settings_timer: 5 #", "apply_settings_timer: 10 #")
+        );
+    }
+
+    #[test]
+    fn added_and_removed_keys() {
+        let contents = updated(|value| {
+            let gpu = value["gpus"]["1002:687F-1043:0555-0000:0b:00.0"]
+                .as_mapping_mut()
+                .unwrap();
+            gpu.remove("max_core_clock");
+            gpu.insert("min_core_clock".into(), Value::from(500));
+            value["daemon"]
+                .as_mapping_mut()
+                .unwrap()
+                .insert("dbus_interface".into(), Value::from(true));
+        });
+        assert_eq!(
+            contents,
+            CONFIG
+                .replace(
+                    "  disable_clocks_cleanup: false\n",
+                    "  disable_clocks_cleanup: false\n  dbus_interface: true\n"
+                )
+                .replace("    max_core_clock: 1500\n", "    min_core_clock: 500\n")
+        );
+    }
+
+    #[test]
+    fn key_order_is_kept() {
+        let contents = updated(|value| {
+            let gpu = value["gpus"]["1002:687F-1043:0555-0000:0b:00.0"]
+                .as_mapping_mut()
+                .unwrap();
+            let fan_control_enabled = gpu.remove("fan_control_enabled").unwrap();
+            gpu.insert("fan_control_enabled".into(), fan_control_enabled);
+            gpu.insert("power_cap".into(), Value::from(150.0));
+        });
+        assert_eq!(
+            contents,
+            CONFIG.replace("power_cap: 200.0 # keeps", "power_cap: 150.0 # keeps")
+        );
+    }
+
+    #[test]
+    fn replaced_mapping() {
+        let contents = updated(|value| {
+            value["gpus"]["1002:687F-1043:0555-0000:0b:00.0"]["fan_control_settings"]["curve"] =
+                serde_yaml::from_str("{50: 0.4}").unwrap();
+        });
+        assert!(contents.contains("      curve:\n        50: 0.4\n    power_cap: 200.0"));
+        assert!(contents.starts_with("# Managed by hand"));
+        assert!(contents.contains("  # Main card, undervolted"));
+    }
+
+    #[test]
+    fn unsupported_syntax() {
+        let previous = "daemon: {log_level: info, admin_groups: [wheel]}\ngpus: {}\n";
+        let value: Value =
+            serde_yaml::from_str("daemon: {log_level: debug, admin_groups: [wheel]}").unwrap();
+        assert_eq!(
+            update(previous, &value).unwrap(),
+            serde_yaml::to_string(&value).unwrap()
+        );
+    }
+}
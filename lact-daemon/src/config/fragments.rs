@@ -0,0 +1,239 @@
+//! Config fragments in `config.d`, which are merged on top of the main config file.
+//!
+//! Fragments are applied in the order of their file names, and each fragment overrides the ones before it as well as
+//! `config.yaml`. Values are merged key by key on the top level and on the level below it, so that a fragment can add
+//! a GPU entry, a profile or a single daemon setting without replacing the rest. Anything deeper, like the settings of
+//! a single GPU, is replaced as a whole.
+use super::merge;
+use anyhow::{anyhow, Context};
+use lact_schema::ErrorKind;
+use serde_yaml::{Mapping, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const DIR_NAME: &str = "config.d";
+const MERGE_DEPTH: u8 = 2;
+
+pub(super) fn dir(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(DIR_NAME)
+}
+
+/// Reads all fragments into a single value that can be merged into the main config
+pub(super) fn read(dir: &Path) -> anyhow::Result<Value> {
+    let mut overlay = Value::Mapping(Mapping::new());
+
+    for path in fragment_paths(dir)? {
+        let raw_fragment = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read config fragment {}", path.display()))?;
+        let fragment: Value = serde_yaml::from_str(&raw_fragment)
+            .with_context(|| format!("Could not deserialize config fragment {}", path.display()))?;
+
+        match fragment {
+            Value::Null => (),
+            Value::Mapping(_) => merge(&mut overlay, fragment),
+            _ => {
+                return Err(anyhow!(
+                    "Config fragment {} is not a mapping",
+                    path.display()
+                ))
+            }
+        }
+    }
+
+    Ok(overlay)
+}
+
+pub(super) fn merge(base: &mut Value, overlay: Value) {
+    merge::merge(base, overlay, MERGE_DEPTH);
+}
+
+/// Removes the values provided by the fragments, so that they are not duplicated into the main config file.
+/// Values that are different from the ones in the fragments are kept.
+pub(super) fn remove_overlay(value: &mut Value, overlay: &Value) {
+    merge::remove(value, overlay, MERGE_DEPTH);
+}
+
+/// Entries of a section (such as a GPU in `gpus` or a profile in `profiles`) are replaced as a whole when loading,
+/// so changes to an entry provided by the fragments would be saved into the main config file and then reverted.
+pub(super) fn check_editable(overlay: &Value, section: &str, key: &str) -> anyhow::Result<()> {
+    if overlay
+        .get(section)
+        .and_then(|entries| entries.get(key))
+        .is_some()
+    {
+        return Err(ErrorKind::InvalidArgument.error(format!(
+            "'{section}.{key}' is provided by a config fragment in {DIR_NAME} and can only be changed there"
+        )));
+    }
+    Ok(())
+}
+
+/// If a filesystem event at the given path can change the loaded config
+pub(super) fn affects_config(config_path: &Path, event_path: &Path) -> bool {
+    let dir = dir(config_path);
+    event_path == config_path
+        || event_path == dir
+        || (event_path.parent() == Some(&dir) && is_fragment(event_path))
+}
+
+fn fragment_paths(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .context("Could not read config fragments")?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_fragment(path) && path.is_file())
+        .collect();
+    paths.sort_unstable();
+    Ok(paths)
+}
+
+fn is_fragment(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "yaml")
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| !name.starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{affects_config, check_editable, dir, merge, read, remove_overlay};
+    use lact_schema::ErrorKind;
+    use serde_yaml::Value;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const BASE: &str = r"
+daemon:
+  log_level: info
+  admin_groups:
+  - wheel
+gpus:
+  gpu-1:
+    fan_control_enabled: false
+    power_cap: 100.0
+current_profile: null
+";
+
+    #[test]
+    fn later_fragments_take_precedence() {
+        let dir = tempdir().unwrap();
+        let fragments_dir = dir.path().join("config.d");
+        fs::create_dir(&fragments_dir).unwrap();
+
+        fs::write(
+            fragments_dir.join("20-fans.yaml"),
+            "gpus:\n  gpu-1:\n    fan_control_enabled: true\n",
+        )
+        .unwrap();
+        fs::write(
+            fragments_dir.join("10-base.yaml"),
+            "daemon:\n  log_level: debug\ngpus:\n  gpu-1:\n    power_cap: 50.0\n  gpu-2:\n    power_cap: 80.0\n",
+        )
+        .unwrap();
+        fs::write(fragments_dir.join("30-empty.yaml"), "").unwrap();
+        fs::write(fragments_dir.join("notes.txt"), "not: yaml: at all").unwrap();
+
+        let mut config: Value = serde_yaml::from_str(BASE).unwrap();
+        merge(&mut config, read(&fragments_dir).unwrap());
+
+        let expected: Value = serde_yaml::from_str(
+            r"
+daemon:
+  log_level: debug
+  admin_groups:
+  - wheel
+gpus:
+  gpu-1:
+    fan_control_enabled: true
+  gpu-2:
+    power_cap: 80.0
+current_profile: null
+",
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn fragments_are_not_saved_into_the_config() {
+        let dir = tempdir().unwrap();
+        let fragments_dir = dir.path().join("config.d");
+        fs::create_dir(&fragments_dir).unwrap();
+        fs::write(
+            fragments_dir.join("fleet.yaml"),
+            "daemon:\n  tcp_listen_address: 0.0.0.0:12853\ngpus:\n  gpu-2:\n    power_cap: 80.0\n",
+        )
+        .unwrap();
+        let overlay = read(&fragments_dir).unwrap();
+
+        let base: Value = serde_yaml::from_str(BASE).unwrap();
+        let mut config = base.clone();
+        merge(&mut config, overlay.clone());
+        remove_overlay(&mut config, &overlay);
+        assert_eq!(config, base);
+
+        // Values changed after loading are kept
+        let mut config = base.clone();
+        merge(&mut config, overlay.clone());
+        config["daemon"]["tcp_listen_address"] = Value::from("127.0.0.1:12853");
+        remove_overlay(&mut config, &overlay);
+        assert_eq!(
+            config["daemon"]["tcp_listen_address"],
+            Value::from("127.0.0.1:12853")
+        );
+    }
+
+    #[test]
+    fn fragment_entries_are_not_editable() {
+        let overlay: Value =
+            serde_yaml::from_str("gpus:\n  gpu-2:\n    power_cap: 80.0\nprofiles:\n  fleet: {}\n")
+                .unwrap();
+
+        let err = check_editable(&overlay, "gpus", "gpu-2").unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::InvalidArgument);
+        assert!(check_editable(&overlay, "profiles", "fleet").is_err());
+
+        check_editable(&overlay, "gpus", "gpu-1").unwrap();
+        check_editable(&overlay, "profiles", "gpu-2").unwrap();
+    }
+
+    #[test]
+    fn invalid_fragment() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("list.yaml"), "- a\n- b\n").unwrap();
+        assert!(read(dir.path()).is_err());
+    }
+
+    #[test]
+    fn config_event_paths() {
+        let config_path = tempdir().unwrap().path().join("config.yaml");
+        let fragments_dir = dir(&config_path);
+
+        assert!(affects_config(&config_path, &config_path));
+        assert!(affects_config(&config_path, &fragments_dir));
+        assert!(affects_config(
+            &config_path,
+            &fragments_dir.join("fans.yaml")
+        ));
+        assert!(!affects_config(
+            &config_path,
+            &fragments_dir.join(".fans.yaml.swp")
+        ));
+        assert!(!affects_config(
+            &config_path,
+            &config_path.with_file_name("history").join("1.yaml")
+        ));
+        assert!(!affects_config(
+            &config_path,
+            &config_path.with_extension("yaml.tmp")
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+use super::{fragments, get_path, Config};
+use anyhow::Context;
+use lact_schema::{ConfigRevision, ErrorKind};
+use std::{
+    fs::{self, File},
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tracing::{debug, warn};
+
+const DIR_NAME: &str = "history";
+/// Number of previous config versions that are kept
+const MAX_REVISIONS: usize = 20;
+/// Permissions of a newly created config file, which may contain secrets
+const DEFAULT_MODE: u32 = 0o600;
+
+/// Replaces the config file through a temporary file, so that it is never left partially written.
+/// The previous contents are kept as a revision if they are different.
+pub(super) fn write(path: &Path, contents: &str) -> anyhow::Result<()> {
+    if fs::read_to_string(path).is_ok_and(|previous| previous != contents) {
+        if let Err(err) = store_revision(path) {
+            warn!("could not keep the previous version of the config: {err:#}");
+        }
+    }
+
+    // The config may contain secrets, so the permissions of the original file are kept
+    let mode = fs::metadata(path).map_or(DEFAULT_MODE, |metadata| {
+        metadata.permissions().mode() & 0o777
+    });
+
+    let tmp_path = path.with_extension("yaml.tmp");
+    // A leftover file would keep its own permissions
+    if tmp_path.exists() {
+        fs::remove_file(&tmp_path).context("Could not remove leftover temporary config")?;
+    }
+
+    let mut tmp_file = File::options()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp_path)
+        .context("Could not create config")?;
+    tmp_file
+        .write_all(contents.as_bytes())
+        .context("Could not write config")?;
+    tmp_file.sync_all().context("Could not sync config")?;
+    drop(tmp_file);
+
+    fs::rename(&tmp_path, path).context("Could not replace config")?;
+
+    // The rename is only durable once the directory is synced
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .context("Could not sync config directory")?;
+    }
+
+    Ok(())
+}
+
+pub fn list_revisions() -> anyhow::Result<Vec<ConfigRevision>> {
+    revisions_in(&history_dir(&get_path()))
+}
+
+pub fn load_revision(id: u64) -> anyhow::Result<Config> {
+    let path = get_path();
+    load_from(&history_dir(&path), id, &fragments::dir(&path))
+}
+
+fn history_dir(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(DIR_NAME)
+}
+
+fn revision_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id}.yaml"))
+}
+
+fn store_revision(path: &Path) -> anyhow::Result<()> {
+    let dir = history_dir(path);
+    fs::create_dir_all(&dir)?;
+
+    let ids = revision_ids(&dir)?;
+    let id = ids.last().map_or(1, |last_id| last_id + 1);
+    let new_path = revision_path(&dir, id);
+    debug!("storing previous config as {new_path:?}");
+
+    fs::copy(path, &new_path)?;
+    // The modification time of the previous file is when that version was saved
+    let modified = fs::metadata(path)?.modified()?;
+    File::options()
+        .write(true)
+        .open(&new_path)?
+        .set_modified(modified)?;
+
+    let excess = (ids.len() + 1).saturating_sub(MAX_REVISIONS);
+    for old_id in &ids[..excess] {
+        fs::remove_file(revision_path(&dir, *old_id))?;
+    }
+
+    Ok(())
+}
+
+/// Ids of the stored revisions, oldest first
+fn revision_ids(dir: &Path) -> anyhow::Result<Vec<u64>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut ids: Vec<u64> = fs::read_dir(dir)
+        .context("Could not read config history")?
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_suffix(".yaml")?
+                .parse()
+                .ok()
+        })
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+fn revisions_in(dir: &Path) -> anyhow::Result<Vec<ConfigRevision>> {
+    revision_ids(dir)?
+        .into_iter()
+        .map(|id| {
+            let modified = fs::metadata(revision_path(dir, id))?.modified()?;
+            let timestamp = modified.duration_since(UNIX_EPOCH).map_or(0, |duration| {
+                u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+            });
+            Ok(ConfigRevision { id, timestamp })
+        })
+        .collect()
+}
+
+/// The current fragments are applied on top of the revision, as only the main config file is kept in the history
+fn load_from(dir: &Path, id: u64, fragments_dir: &Path) -> anyhow::Result<Config> {
+    let path = revision_path(dir, id);
+    if !path.exists() {
+        return Err(ErrorKind::NotFound.error(format!("Config revision {id} not found")));
+    }
+
+    let raw_config = fs::read_to_string(path).context("Could not read config revision")?;
+    Config::parse_with_fragments(&raw_config, fragments_dir)
+        .context("Could not deserialize config revision")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        history_dir, load_from, revision_ids, revisions_in, write, DEFAULT_MODE, MAX_REVISIONS,
+    };
+    use crate::config::{fragments, Config};
+    use lact_schema::ErrorKind;
+    use std::{fs, os::unix::fs::PermissionsExt};
+    use tempfile::tempdir;
+
+    #[test]
+    fn keeps_previous_versions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+
+        let mut config = Config::default();
+        write(&path, &serde_yaml::to_string(&config).unwrap()).unwrap();
+        // Unchanged contents are not stored again
+        write(&path, &serde_yaml::to_string(&config).unwrap()).unwrap();
+        assert!(revisions_in(&history_dir(&path)).unwrap().is_empty());
+
+        config.apply_settings_timer = 10;
+        write(&path, &serde_yaml::to_string(&config).unwrap()).unwrap();
+
+        let revisions = revisions_in(&history_dir(&path)).unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].id, 1);
+
+        let restored = load_from(&history_dir(&path), 1, &fragments::dir(&path)).unwrap();
+        assert_eq!(restored, Config::default());
+        assert!(!path.with_extension("yaml.tmp").exists());
+
+        let err = load_from(&history_dir(&path), 2, &fragments::dir(&path)).unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+
+        // The first write has no previous version
+        for i in 0..MAX_REVISIONS + 6 {
+            write(&path, &i.to_string()).unwrap();
+        }
+
+        let ids = revision_ids(&history_dir(&path)).unwrap();
+        assert_eq!(ids, (6..=25).collect::<Vec<u64>>());
+        assert_eq!(
+            fs::read_to_string(history_dir(&path).join("25.yaml")).unwrap(),
+            "24"
+        );
+    }
+
+    #[test]
+    fn keeps_permissions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let mode = |path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        write(&path, "1").unwrap();
+        assert_eq!(mode(&path), DEFAULT_MODE);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        // A leftover temporary file does not leak its permissions
+        fs::write(path.with_extension("yaml.tmp"), "").unwrap();
+        fs::set_permissions(
+            path.with_extension("yaml.tmp"),
+            fs::Permissions::from_mode(0o666),
+        )
+        .unwrap();
+
+        write(&path, "2").unwrap();
+        assert_eq!(mode(&path), 0o640);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "2");
+    }
+}
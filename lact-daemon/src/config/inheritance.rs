@@ -0,0 +1,383 @@
+//! Profiles can extend another profile with `extends`, in which case the config file only contains the GPU settings
+//! that differ from the base profile. The settings are resolved field by field: GPU entries are merged by their id,
+//! the settings of a GPU by their name, and nested settings such as `fan_control_settings`, `pmfw_options` or
+//! clock offsets by their keys. Anything deeper, like the fan curve, is replaced as a whole.
+//!
+//! A setting that the base profile has but the extending profile does not is stored as an explicit `null`,
+//! so that it stays cleared when the profile is resolved again.
+use super::{merge, Config, Profile};
+use anyhow::{anyhow, Context};
+use lact_schema::ErrorKind;
+use serde_yaml::{Mapping, Value};
+use std::{collections::HashSet, rc::Rc};
+
+/// GPU map -> GPU settings -> nested settings
+const MERGE_DEPTH: u8 = 3;
+
+/// Fills in the inherited settings of the profiles before the config is deserialized
+pub(super) fn resolve_value(config: &mut Value) -> anyhow::Result<()> {
+    let Some(Value::Mapping(profiles)) = config.get_mut("profiles") else {
+        return Ok(());
+    };
+
+    let entries: Vec<(String, Option<String>)> = profiles
+        .iter()
+        .filter_map(|(name, profile)| {
+            let base = profile.get("extends").and_then(Value::as_str);
+            Some((name.as_str()?.to_owned(), base.map(str::to_owned)))
+        })
+        .collect();
+    let entries: Vec<(&str, Option<&str>)> = entries
+        .iter()
+        .map(|(name, base)| (name.as_str(), base.as_deref()))
+        .collect();
+
+    for (name, base) in resolution_order(&entries)? {
+        let mut gpus = profiles
+            .get(base)
+            .and_then(|profile| profile.get("gpus"))
+            .cloned()
+            .unwrap_or_else(|| Value::Mapping(Mapping::new()));
+
+        let profile = profiles
+            .get_mut(name)
+            .context("Profile disappeared during resolution")?;
+        if let Some(overrides) = profile.get("gpus") {
+            merge::merge(&mut gpus, overrides.clone(), MERGE_DEPTH);
+        }
+        remove_nulls(&mut gpus, MERGE_DEPTH);
+        profile["gpus"] = gpus;
+    }
+
+    Ok(())
+}
+
+/// Remembers the settings that the profiles were resolved with by [`resolve_value`],
+/// so that the settings a profile leaves unset are not taken from its base again
+pub(super) fn set_inherited(config: &mut Config) {
+    let bases: Vec<(Rc<str>, Rc<str>)> = config
+        .profiles
+        .iter()
+        .filter_map(|(name, profile)| Some((name.clone(), profile.extends.clone()?)))
+        .collect();
+
+    for (name, base) in bases {
+        if let Some(inherited) = config.profiles.get(&base).map(|base| base.gpus.clone()) {
+            config.profiles[&name].inherited = inherited;
+        }
+    }
+}
+
+/// Replaces the settings of profiles that extend another profile with only the settings they override
+pub(super) fn strip_inherited(config: &Config, value: &mut Value) -> anyhow::Result<()> {
+    for (name, profile) in &config.profiles {
+        if profile.extends.is_none() {
+            continue;
+        }
+        let Some(Value::Mapping(profile_value)) = value
+            .get_mut("profiles")
+            .and_then(|profiles| profiles.get_mut(name.as_ref()))
+        else {
+            continue;
+        };
+
+        let overrides = overrides(profile)?;
+        if overrides.as_mapping().is_some_and(Mapping::is_empty) {
+            profile_value.remove("gpus");
+        } else {
+            profile_value.insert("gpus".into(), overrides);
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Updates the inherited settings of profiles, for example after their base profile has been changed.
+    /// The settings that a profile overrides are kept.
+    pub fn resolve_profiles(&mut self) -> anyhow::Result<()> {
+        let entries: Vec<(Rc<str>, Option<Rc<str>>)> = self
+            .profiles
+            .iter()
+            .map(|(name, profile)| (name.clone(), profile.extends.clone()))
+            .collect();
+        let entries: Vec<(&str, Option<&str>)> = entries
+            .iter()
+            .map(|(name, base)| (name.as_ref(), base.as_deref()))
+            .collect();
+
+        for (name, base) in resolution_order(&entries)? {
+            let inherited = self.profiles[base].gpus.clone();
+            let profile = &mut self.profiles[name];
+
+            let mut gpus = serde_yaml::to_value(&inherited)?;
+            merge::merge(&mut gpus, overrides(profile)?, MERGE_DEPTH);
+            remove_nulls(&mut gpus, MERGE_DEPTH);
+            profile.gpus = serde_yaml::from_value(gpus)
+                .with_context(|| format!("Could not resolve the settings of profile '{name}'"))?;
+            profile.inherited = inherited;
+        }
+
+        Ok(())
+    }
+}
+
+/// GPU settings of a profile that are different from the ones it inherited
+fn overrides(profile: &Profile) -> anyhow::Result<Value> {
+    let mut gpus = serde_yaml::to_value(&profile.gpus)?;
+    let inherited = serde_yaml::to_value(&profile.inherited)?;
+    mark_cleared(&mut gpus, &inherited, MERGE_DEPTH);
+    merge::remove(&mut gpus, &inherited, MERGE_DEPTH);
+
+    remove_empty(&mut gpus);
+    Ok(gpus)
+}
+
+/// Adds a `null` for the inherited settings that are not set in `value`.
+/// Unset optional settings are not serialized, so they would be inherited again otherwise.
+fn mark_cleared(value: &mut Value, inherited: &Value, depth: u8) {
+    if let (Value::Mapping(map), Value::Mapping(inherited)) = (value, inherited) {
+        if depth == 0 {
+            return;
+        }
+        for (key, inherited_value) in inherited {
+            if inherited_value.is_null() {
+                continue;
+            }
+            match map.get_mut(key) {
+                Some(value) => mark_cleared(value, inherited_value, depth - 1),
+                None => {
+                    map.insert(key.clone(), Value::Null);
+                }
+            }
+        }
+    }
+}
+
+/// Removes the settings that were cleared with a `null` after merging, so that they get their default value
+fn remove_nulls(value: &mut Value, depth: u8) {
+    if let Value::Mapping(map) = value {
+        if depth == 0 {
+            return;
+        }
+        map.retain(|_, nested| !nested.is_null());
+        for nested in map.values_mut() {
+            remove_nulls(nested, depth - 1);
+        }
+    }
+}
+
+/// Leaves out GPUs and nested settings without any overridden values
+fn remove_empty(value: &mut Value) {
+    if let Value::Mapping(map) = value {
+        for nested in map.values_mut() {
+            remove_empty(nested);
+        }
+        map.retain(|_, nested| !nested.as_mapping().is_some_and(Mapping::is_empty));
+    }
+}
+
+/// Order in which the profiles extending another profile have to be resolved, so that each base profile is resolved
+/// before the profiles extending it. Returns pairs of the profile and its base.
+fn resolution_order<'a>(
+    profiles: &[(&'a str, Option<&'a str>)],
+) -> anyhow::Result<Vec<(&'a str, &'a str)>> {
+    let mut resolved: HashSet<&str> = profiles
+        .iter()
+        .filter(|(_, base)| base.is_none())
+        .map(|(name, _)| *name)
+        .collect();
+    let mut pending: Vec<(&str, &str)> = profiles
+        .iter()
+        .filter_map(|(name, base)| Some((*name, (*base)?)))
+        .collect();
+
+    if let Some((name, base)) = pending
+        .iter()
+        .find(|(_, base)| !profiles.iter().any(|(name, _)| name == base))
+    {
+        return Err(ErrorKind::InvalidArgument.error(format!(
+            "Profile '{name}' extends profile '{base}', which does not exist"
+        )));
+    }
+
+    let mut order = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let count = pending.len();
+        pending.retain(|(name, base)| {
+            if resolved.contains(base) {
+                order.push((*name, *base));
+                resolved.insert(name);
+                false
+            } else {
+                true
+            }
+        });
+
+        if pending.len() == count {
+            let names: Vec<&str> = pending.iter().map(|(name, _)| *name).collect();
+            return Err(anyhow!(
+                "Profiles {} extend each other in a loop",
+                names.join(", ")
+            ));
+        }
+    }
+
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolution_order;
+    use crate::config::{Config, Gpu};
+    use pretty_assertions::assert_eq;
+
+    const CONFIG: &str = r"
+daemon:
+  log_level: info
+  admin_groups: []
+profiles:
+  base:
+    gpus:
+      gpu-1:
+        fan_control_enabled: true
+        fan_control_settings:
+          mode: curve
+          static_speed: 0.5
+          temperature_key: edge
+          interval_ms: 500
+          curve:
+            40: 0.3
+            80: 1.0
+        power_cap: 200.0
+        max_core_clock: 2000
+  game:
+    extends: quiet
+    gpus:
+      gpu-1:
+        max_core_clock: 2500
+  quiet:
+    extends: base
+    gpus:
+      gpu-1:
+        fan_control_settings:
+          static_speed: 0.2
+        power_cap: 150.0
+";
+
+    fn load(raw: &str) -> anyhow::Result<Config> {
+        Config::from_value(serde_yaml::from_str(raw).unwrap())
+    }
+
+    #[test]
+    fn resolve_field_by_field() {
+        let config = load(CONFIG).unwrap();
+        let game = &config.profiles["game"].gpus["gpu-1"];
+        let settings = game.fan_control_settings.as_ref().unwrap();
+
+        assert!(game.fan_control_enabled);
+        assert!((settings.static_speed - 0.2).abs() < f64::EPSILON);
+        assert_eq!(settings.curve.0.len(), 2);
+        assert_eq!(game.power_cap, Some(150.0));
+        assert_eq!(game.clocks_configuration.max_core_clock, Some(2500));
+    }
+
+    #[test]
+    fn base_changes_are_inherited() {
+        let mut config = load(CONFIG).unwrap();
+
+        let base = config.profiles["base"].gpus.get_mut("gpu-1").unwrap();
+        base.power_cap = Some(180.0);
+        base.clocks_configuration.max_core_clock = Some(2100);
+        config.profiles["base"]
+            .gpus
+            .insert("gpu-2".to_owned(), Gpu::default());
+        config.profiles["game"]
+            .gpus
+            .get_mut("gpu-1")
+            .unwrap()
+            .fan_control_enabled = false;
+        config.resolve_profiles().unwrap();
+
+        let quiet = &config.profiles["quiet"].gpus;
+        assert_eq!(quiet["gpu-1"].power_cap, Some(150.0));
+        assert_eq!(
+            quiet["gpu-1"].clocks_configuration.max_core_clock,
+            Some(2100)
+        );
+        assert!(quiet.contains_key("gpu-2"));
+
+        let game = &config.profiles["game"].gpus["gpu-1"];
+        assert!(!game.fan_control_enabled);
+        assert_eq!(game.clocks_configuration.max_core_clock, Some(2500));
+    }
+
+    #[test]
+    fn only_overrides_are_stored() {
+        let mut config = load(CONFIG).unwrap();
+        config.profiles["base"]
+            .gpus
+            .get_mut("gpu-1")
+            .unwrap()
+            .power_cap = Some(180.0);
+        config.resolve_profiles().unwrap();
+
+        let mut value = serde_yaml::to_value(&config).unwrap();
+        super::strip_inherited(&config, &mut value).unwrap();
+
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            r"
+extends: quiet
+gpus:
+  gpu-1:
+    max_core_clock: 2500
+",
+        )
+        .unwrap();
+        assert_eq!(value["profiles"]["game"], expected);
+        assert_eq!(
+            load(&serde_yaml::to_string(&value).unwrap()).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn cleared_settings_stay_cleared() {
+        let mut config = load(CONFIG).unwrap();
+        let quiet = config.profiles["quiet"].gpus.get_mut("gpu-1").unwrap();
+        quiet.power_cap = None;
+        quiet.fan_control_settings = None;
+        config.resolve_profiles().unwrap();
+
+        for name in ["quiet", "game"] {
+            let gpu = &config.profiles[name].gpus["gpu-1"];
+            assert_eq!(gpu.power_cap, None, "{name}");
+            assert_eq!(gpu.fan_control_settings, None, "{name}");
+        }
+        assert_eq!(config.profiles["base"].gpus["gpu-1"].power_cap, Some(200.0));
+
+        let mut value = serde_yaml::to_value(&config).unwrap();
+        super::strip_inherited(&config, &mut value).unwrap();
+        assert_eq!(
+            value["profiles"]["quiet"]["gpus"]["gpu-1"].get("power_cap"),
+            Some(&serde_yaml::Value::Null)
+        );
+        assert_eq!(
+            load(&serde_yaml::to_string(&value).unwrap()).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn invalid_bases() {
+        let err = resolution_order(&[("a", Some("b"))]).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+
+        let err = resolution_order(&[("a", Some("b")), ("b", Some("a")), ("c", None)]).unwrap_err();
+        assert!(err.to_string().contains("loop"));
+
+        assert_eq!(
+            resolution_order(&[("c", Some("b")), ("b", Some("a")), ("a", None)]).unwrap(),
+            vec![("b", "a"), ("c", "b")]
+        );
+    }
+}
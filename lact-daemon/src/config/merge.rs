@@ -0,0 +1,39 @@
+//! Merging of YAML values, used for config fragments and profile inheritance
+use serde_yaml::Value;
+
+/// Merges `overlay` into `base`. Mappings are merged key by key up to the given depth,
+/// anything below it (as well as any value that is not a mapping) is replaced as a whole.
+pub(super) fn merge(base: &mut Value, overlay: Value, depth: u8) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) if depth > 0 => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value, depth - 1),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// The reverse of [`merge`]: removes the entries of `value` that are the same as in `overlay`.
+/// Returns if the whole value is provided by the overlay.
+pub(super) fn remove(value: &mut Value, overlay: &Value, depth: u8) -> bool {
+    match (value, overlay) {
+        (Value::Mapping(map), Value::Mapping(overlay)) if depth > 0 => {
+            for (key, overlay_value) in overlay {
+                if map
+                    .get_mut(key)
+                    .is_some_and(|value| remove(value, overlay_value, depth - 1))
+                {
+                    map.remove(key);
+                }
+            }
+            false
+        }
+        (value, overlay) => value == overlay,
+    }
+}
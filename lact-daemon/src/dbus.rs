@@ -0,0 +1,589 @@
+use crate::server::{
+    access::{self, Access, Peer},
+    handle_single_request,
+    handler::Handler,
+    ConnectionOptions,
+};
+use futures::StreamExt;
+use lact_schema::{DeviceListEntry, ProfilesInfo, Response};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot, Notify},
+    time::{interval, MissedTickBehavior},
+};
+use tracing::{debug, error, info};
+use zbus::{
+    fdo, interface,
+    message::Header,
+    names::{BusName, UniqueName},
+    object_server::{InterfaceRef, SignalEmitter},
+    Connection,
+};
+
+const BUS_NAME: &str = "io.github.lact_linux.Daemon";
+const OBJECT_PATH: &str = "/io/github/lact_linux/Daemon";
+const CALL_CHANNEL_SIZE: usize = 16;
+/// How often stats signals are emitted while there are subscribers
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A request forwarded from the D-Bus interface to the handler,
+/// which has to run on the local task set
+struct Call {
+    payload: String,
+    peer: Peer,
+    read_only: bool,
+    response_tx: oneshot::Sender<anyhow::Result<Vec<u8>>>,
+}
+
+/// Clients that receive `Stats` signals, with the GPUs they are subscribed to, by unique bus name
+#[derive(Default)]
+struct StatsSubscribers {
+    clients: Mutex<BTreeMap<String, BTreeSet<String>>>,
+    changed: Notify,
+}
+
+impl StatsSubscribers {
+    fn add(&self, client: String, id: String) {
+        self.lock().entry(client).or_default().insert(id);
+        self.changed.notify_one();
+    }
+
+    /// Removes a single subscription, or all subscriptions of the client when `id` is `None`
+    fn remove(&self, client: &str, id: Option<&str>) {
+        let mut clients = self.lock();
+        if let Some(ids) = clients.get_mut(client) {
+            if let Some(id) = id {
+                ids.remove(id);
+            }
+            if id.is_none() || ids.is_empty() {
+                clients.remove(client);
+            }
+        }
+    }
+
+    /// Subscribed clients by GPU id
+    fn by_gpu(&self) -> BTreeMap<String, Vec<String>> {
+        let mut gpus = BTreeMap::<String, Vec<String>>::new();
+        for (client, ids) in self.lock().iter() {
+            for id in ids {
+                gpus.entry(id.clone()).or_default().push(client.clone());
+            }
+        }
+        gpus
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, BTreeSet<String>>> {
+        self.clients.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct DaemonInterface {
+    call_tx: mpsc::Sender<Call>,
+    stats_subscribers: Arc<StatsSubscribers>,
+}
+
+impl DaemonInterface {
+    /// Makes a request on behalf of the sender of the message
+    async fn dispatch(
+        &self,
+        payload: Value,
+        header: &Header<'_>,
+        connection: &Connection,
+    ) -> fdo::Result<Value> {
+        let peer = caller_peer(header, connection).await?;
+        self.send_call(payload.to_string(), peer, false).await
+    }
+
+    /// Makes a read-only request which does not depend on the caller, used for properties
+    async fn dispatch_read_only<T: DeserializeOwned>(&self, payload: Value) -> fdo::Result<T> {
+        let data = self
+            .send_call(payload.to_string(), Peer::Local, true)
+            .await?;
+        serde_json::from_value(data).map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+
+    async fn send_call(&self, payload: String, peer: Peer, read_only: bool) -> fdo::Result<Value> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let call = Call {
+            payload,
+            peer,
+            read_only,
+            response_tx,
+        };
+        self.call_tx
+            .send(call)
+            .await
+            .map_err(|_| fdo::Error::Failed("Daemon is not running".to_owned()))?;
+
+        let response = response_rx
+            .await
+            .map_err(|_| fdo::Error::Failed("Request was dropped".to_owned()))?
+            .map_err(|err| fdo::Error::Failed(format!("{err:#}")))?;
+
+        match serde_json::from_slice(&response) {
+            Ok(Response::Ok(data)) => Ok(data),
+            Ok(Response::Error(err)) => Err(fdo::Error::Failed(err.to_string())),
+            Err(err) => Err(fdo::Error::Failed(err.to_string())),
+        }
+    }
+}
+
+/// Methods return the same JSON data as the socket API.
+/// Requests without a dedicated method can be made with `Request`.
+#[interface(name = "io.github.lact_linux.Daemon")]
+impl DaemonInterface {
+    /// Makes any request of the socket API, with `args` being a JSON value (or an empty string if there are none)
+    async fn request(
+        &self,
+        command: &str,
+        args: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let payload = if args.is_empty() {
+            json!({ "command": command })
+        } else {
+            let args: Value = serde_json::from_str(args)
+                .map_err(|err| fdo::Error::InvalidArgs(format!("Invalid args: {err}")))?;
+            json!({ "command": command, "args": args })
+        };
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.to_string())
+    }
+
+    async fn list_devices(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let payload = json!({ "command": "list_devices" });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.to_string())
+    }
+
+    async fn system_info(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let payload = json!({ "command": "system_info" });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.to_string())
+    }
+
+    async fn device_info(
+        &self,
+        id: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let payload = json!({ "command": "device_info", "args": { "id": id } });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.to_string())
+    }
+
+    async fn device_stats(
+        &self,
+        id: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let payload = json!({ "command": "device_stats", "args": { "id": id } });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.to_string())
+    }
+
+    async fn device_clocks_info(
+        &self,
+        id: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let payload = json!({ "command": "device_clocks_info", "args": { "id": id } });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.to_string())
+    }
+
+    async fn list_profiles(
+        &self,
+        include_state: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let payload =
+            json!({ "command": "list_profiles", "args": { "include_state": include_state } });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.to_string())
+    }
+
+    /// An empty `name` selects the default profile
+    async fn set_profile(
+        &self,
+        name: &str,
+        auto_switch: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<()> {
+        let name = (!name.is_empty()).then_some(name);
+        let payload = json!({ "command": "set_profile", "args": { "name": name, "auto_switch": auto_switch } });
+        self.dispatch(payload, &header, connection).await?;
+        Ok(())
+    }
+
+    /// A negative `cap` resets the power cap to the default.
+    /// Returns the number of seconds before the change is reverted unless it is confirmed.
+    async fn set_power_cap(
+        &self,
+        id: &str,
+        cap: f64,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<u64> {
+        let cap = (cap >= 0.0).then_some(cap);
+        let payload = json!({ "command": "set_power_cap", "args": { "id": id, "cap": cap } });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.as_u64().unwrap_or_default())
+    }
+
+    /// Returns the number of seconds before the change is reverted unless it is confirmed
+    async fn set_performance_level(
+        &self,
+        id: &str,
+        performance_level: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<u64> {
+        let payload = json!({
+            "command": "set_performance_level",
+            "args": { "id": id, "performance_level": performance_level },
+        });
+        let data = self.dispatch(payload, &header, connection).await?;
+        Ok(data.as_u64().unwrap_or_default())
+    }
+
+    /// Confirms (or reverts, when `confirm` is false) the last settings change
+    async fn confirm_pending_config(
+        &self,
+        confirm: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<()> {
+        let command = if confirm { "confirm" } else { "revert" };
+        let payload =
+            json!({ "command": "confirm_pending_config", "args": { "command": command } });
+        self.dispatch(payload, &header, connection).await?;
+        Ok(())
+    }
+
+    /// Starts emitting `Stats` signals for the GPU, until the caller unsubscribes or disconnects from the bus
+    async fn subscribe_stats(
+        &self,
+        id: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<()> {
+        // Checks that the GPU exists and that the caller is allowed to read its stats
+        let payload = json!({ "command": "device_stats", "args": { "id": id } });
+        self.dispatch(payload, &header, connection).await?;
+
+        let sender = sender_name(&header)?;
+        self.stats_subscribers
+            .add(sender.to_string(), id.to_owned());
+        Ok(())
+    }
+
+    // The header has to be taken by value by interface methods
+    #[allow(clippy::needless_pass_by_value)]
+    fn unsubscribe_stats(&self, id: &str, #[zbus(header)] header: Header<'_>) -> fdo::Result<()> {
+        let sender = sender_name(&header)?;
+        self.stats_subscribers.remove(sender.as_str(), Some(id));
+        Ok(())
+    }
+
+    /// Pairs of GPU id and name
+    #[zbus(property)]
+    async fn devices(&self) -> fdo::Result<Vec<(String, String)>> {
+        let devices: Vec<DeviceListEntry> = self
+            .dispatch_read_only(json!({ "command": "list_devices" }))
+            .await?;
+        Ok(devices
+            .into_iter()
+            .map(|device| (device.id, device.name.unwrap_or_default()))
+            .collect())
+    }
+
+    /// Empty when the default profile is used
+    #[zbus(property)]
+    async fn current_profile(&self) -> fdo::Result<String> {
+        let profiles = self.profiles_info().await?;
+        Ok(profiles.current_profile.unwrap_or_default())
+    }
+
+    #[zbus(property)]
+    async fn auto_switch_profiles(&self) -> fdo::Result<bool> {
+        let profiles = self.profiles_info().await?;
+        Ok(profiles.auto_switch)
+    }
+
+    /// Emitted when the active profile changes. `name` is empty for the default profile.
+    #[zbus(signal)]
+    async fn profile_changed(emitter: &SignalEmitter<'_>, name: &str) -> zbus::Result<()>;
+
+    /// Emitted every second with the stats of each GPU that a client subscribed to with `SubscribeStats`,
+    /// in the same JSON format as `DeviceStats`. Only sent to the subscribed clients.
+    #[zbus(signal)]
+    async fn stats(emitter: &SignalEmitter<'_>, id: &str, stats: &str) -> zbus::Result<()>;
+}
+
+impl DaemonInterface {
+    async fn profiles_info(&self) -> fdo::Result<ProfilesInfo> {
+        self.dispatch_read_only(json!({ "command": "list_profiles" }))
+            .await
+    }
+}
+
+fn sender_name<'a>(header: &'a Header<'_>) -> fdo::Result<&'a UniqueName<'a>> {
+    header
+        .sender()
+        .ok_or_else(|| fdo::Error::Failed("Message has no sender".to_owned()))
+}
+
+async fn caller_peer(header: &Header<'_>, connection: &Connection) -> fdo::Result<Peer> {
+    let sender = sender_name(header)?;
+    let bus_name = BusName::from(sender.to_owned());
+
+    let dbus = fdo::DBusProxy::new(connection).await?;
+    let uid = dbus.get_connection_unix_user(bus_name.clone()).await?;
+    let pid = dbus
+        .get_connection_unix_process_id(bus_name)
+        .await
+        .ok()
+        .and_then(|pid| i32::try_from(pid).ok());
+
+    Ok(Peer::from_uid(uid, pid))
+}
+
+/// Serves the D-Bus interface on the system bus.
+/// Callers are given the same permissions as they would have on the unix socket.
+pub async fn serve(handler: Handler) {
+    let (call_tx, call_rx) = mpsc::channel(CALL_CHANNEL_SIZE);
+    let stats_subscribers = Arc::new(StatsSubscribers::default());
+    let interface = DaemonInterface {
+        call_tx,
+        stats_subscribers: stats_subscribers.clone(),
+    };
+
+    let connection = match connect(interface).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            error!("could not serve D-Bus interface: {err:#}");
+            return;
+        }
+    };
+    info!("serving D-Bus interface {BUS_NAME} at {OBJECT_PATH}");
+
+    tokio::task::spawn_local(run_calls(handler.clone(), call_rx));
+
+    let subscribers = stats_subscribers.clone();
+    let bus_connection = connection.clone();
+    tokio::task::spawn_local(async move {
+        if let Err(err) = remove_disconnected_subscribers(&bus_connection, &subscribers).await {
+            error!("could not watch D-Bus clients: {err}");
+        }
+    });
+
+    match connection
+        .object_server()
+        .interface::<_, DaemonInterface>(OBJECT_PATH)
+        .await
+    {
+        Ok(interface_ref) => emit_signals(&handler, &interface_ref, &stats_subscribers).await,
+        Err(err) => error!("could not get D-Bus interface reference: {err}"),
+    }
+}
+
+async fn connect(interface: DaemonInterface) -> zbus::Result<Connection> {
+    Box::pin(
+        zbus::connection::Builder::system()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, interface)?
+            .build(),
+    )
+    .await
+}
+
+async fn run_calls(handler: Handler, mut call_rx: mpsc::Receiver<Call>) {
+    while let Some(call) = call_rx.recv().await {
+        let access = if call.read_only {
+            Access::ReadOnly
+        } else {
+            let config = handler.config.read().await;
+            match &config.daemon.access_control {
+                Some(policy) => access::evaluate(policy, &config.daemon.admin_groups, &call.peer),
+                None => access::evaluate_admin_groups(&config.daemon.admin_groups, &call.peer),
+            }
+        };
+
+        let handler = handler.clone();
+        tokio::task::spawn_local(async move {
+            let options = ConnectionOptions {
+                auth_token: None,
+                access,
+                peer: call.peer,
+            };
+            let response = handle_single_request(&call.payload, &handler, options).await;
+            if call.response_tx.send(response).is_err() {
+                debug!("D-Bus caller went away before receiving a response");
+            }
+        });
+    }
+}
+
+/// Drops the stats subscriptions of clients that disconnected from the bus
+async fn remove_disconnected_subscribers(
+    connection: &Connection,
+    subscribers: &StatsSubscribers,
+) -> zbus::Result<()> {
+    let dbus = fdo::DBusProxy::new(connection).await?;
+    let mut owner_changes = dbus.receive_name_owner_changed().await?;
+
+    while let Some(change) = owner_changes.next().await {
+        let args = change.args()?;
+        if args.new_owner.is_none() {
+            subscribers.remove(args.name.as_str(), None);
+        }
+    }
+    Ok(())
+}
+
+/// Signals profile changes as they happen, and stats of the subscribed GPUs while there are any subscribers
+async fn emit_signals(
+    handler: &Handler,
+    interface_ref: &InterfaceRef<DaemonInterface>,
+    subscribers: &StatsSubscribers,
+) {
+    let emitter = interface_ref.signal_emitter();
+    let mut profile_rx = handler.subscribe_current_profile();
+
+    let mut interval = interval(STATS_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let gpus = subscribers.by_gpu();
+
+        select! {
+            result = profile_rx.changed() => {
+                if result.is_err() {
+                    break;
+                }
+                let name = profile_rx.borrow_and_update().clone();
+                let name = name.as_deref().unwrap_or_default();
+                if let Err(err) = DaemonInterface::profile_changed(emitter, name).await {
+                    error!("could not emit profile change signal: {err}");
+                }
+                let interface = interface_ref.get().await;
+                if let Err(err) = interface.current_profile_changed(emitter).await {
+                    error!("could not emit profile property change: {err}");
+                }
+            }
+            _ = interval.tick(), if !gpus.is_empty() => {
+                for (id, clients) in &gpus {
+                    match handler.get_gpu_stats(id).await {
+                        Ok(stats) => {
+                            let stats =
+                                serde_json::to_string(&stats).expect("Stats are always serializable");
+                            emit_stats(emitter, clients, id, &stats).await;
+                        }
+                        Err(err) => debug!("could not get stats of GPU {id} for D-Bus: {err:#}"),
+                    }
+                }
+            }
+            // Subscriptions were added, so the stats may have to be emitted
+            () = subscribers.changed.notified() => (),
+        }
+    }
+}
+
+/// Sends the stats only to the clients subscribed to them, as they may not be allowed to read stats of other GPUs
+async fn emit_stats(emitter: &SignalEmitter<'_>, clients: &[String], id: &str, stats: &str) {
+    for client in clients {
+        let destination = match UniqueName::try_from(client.as_str()) {
+            Ok(name) => BusName::from(name.into_owned()),
+            Err(err) => {
+                error!("invalid D-Bus client name {client}: {err}");
+                continue;
+            }
+        };
+        let emitter = emitter.to_owned().set_destination(destination);
+        if let Err(err) = DaemonInterface::stats(&emitter, id, stats).await {
+            error!("could not emit stats signal: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatsSubscribers;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn stats_subscribers() {
+        let subscribers = StatsSubscribers::default();
+        subscribers.add(":1.10".to_owned(), "gpu-1".to_owned());
+        subscribers.add(":1.10".to_owned(), "gpu-2".to_owned());
+        subscribers.add(":1.11".to_owned(), "gpu-1".to_owned());
+        // Subscribing twice has no effect
+        subscribers.add(":1.11".to_owned(), "gpu-1".to_owned());
+
+        assert_eq!(
+            BTreeMap::from([
+                (
+                    "gpu-1".to_owned(),
+                    vec![":1.10".to_owned(), ":1.11".to_owned()]
+                ),
+                ("gpu-2".to_owned(), vec![":1.10".to_owned()]),
+            ]),
+            subscribers.by_gpu()
+        );
+
+        subscribers.remove(":1.10", Some("gpu-1"));
+        subscribers.remove(":1.11", Some("gpu-2"));
+        assert_eq!(
+            BTreeMap::from([
+                ("gpu-1".to_owned(), vec![":1.11".to_owned()]),
+                ("gpu-2".to_owned(), vec![":1.10".to_owned()]),
+            ]),
+            subscribers.by_gpu()
+        );
+
+        // The client is dropped entirely once it has no subscriptions left
+        subscribers.remove(":1.11", Some("gpu-1"));
+        assert!(!subscribers.lock().contains_key(":1.11"));
+    }
+
+    #[test]
+    fn disconnected_client_is_removed() {
+        let subscribers = StatsSubscribers::default();
+        subscribers.add(":1.10".to_owned(), "gpu-1".to_owned());
+        subscribers.add(":1.10".to_owned(), "gpu-2".to_owned());
+        subscribers.add(":1.11".to_owned(), "gpu-2".to_owned());
+
+        subscribers.remove(":1.10", None);
+        assert_eq!(
+            BTreeMap::from([("gpu-2".to_owned(), vec![":1.11".to_owned()])]),
+            subscribers.by_gpu()
+        );
+
+        subscribers.remove(":1.11", None);
+        assert!(subscribers.by_gpu().is_empty());
+        // Removing a client that is not subscribed is fine
+        subscribers.remove(":1.12", None);
+    }
+}
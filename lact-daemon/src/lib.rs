@@ -3,6 +3,8 @@
 
 mod bindings;
 mod config;
+mod dbus;
+mod mqtt;
 mod server;
 mod socket;
 mod suspend;
@@ -13,7 +15,7 @@ use anyhow::Context;
 use config::Config;
 use futures::future::select_all;
 use server::system;
-use server::{handle_stream, handler::Handler, Server};
+use server::{handle_stream, handler::Handler, ConnectionOptions, Server};
 use std::cell::Cell;
 use std::sync::Arc;
 use std::time::Instant;
@@ -75,9 +77,16 @@ pub fn run() -> anyhow::Result<()> {
                 let server = Server::new(config).await?;
                 let handler = server.handler.clone();
 
+                if handler.config.read().await.daemon.dbus_interface {
+                    tokio::task::spawn_local(dbus::serve(handler.clone()));
+                }
+                if let Some(mqtt_config) = handler.config.read().await.daemon.mqtt.clone() {
+                    tokio::task::spawn_local(mqtt::run(handler.clone(), mqtt_config));
+                }
                 tokio::task::spawn_local(listen_config_changes(handler.clone()));
                 tokio::task::spawn_local(listen_exit_signals(handler.clone()));
                 tokio::task::spawn_local(listen_device_events(handler.clone()));
+                tokio::task::spawn_local(handler.clone().record_stats_history());
                 tokio::task::spawn_local(suspend::listen_events(handler));
 
                 server.run().await;
@@ -104,7 +113,9 @@ pub fn run_embedded(stream: StdUnixStream) -> anyhow::Result<()> {
                 let handler = Handler::new(config).await?;
                 let stream = UnixStream::try_from(stream)?;
 
-                handle_stream(stream, handler).await
+                tokio::task::spawn_local(handler.clone().record_stats_history());
+
+                handle_stream(stream, handler, ConnectionOptions::default()).await
             })
             .await
     })
@@ -130,8 +141,7 @@ async fn listen_config_changes(handler: Handler) {
     let mut rx = config::start_watcher(handler.config_last_saved.clone());
     while let Some(new_config) = rx.recv().await {
         info!("config file was changed, reloading");
-        *handler.config.write().await = new_config;
-        match handler.apply_current_config().await {
+        match handler.reload_config(new_config).await {
             Ok(()) => {
                 info!("configuration reloaded");
             }
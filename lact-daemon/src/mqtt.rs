@@ -0,0 +1,320 @@
+use crate::{
+    config::MqttConfig,
+    server::{access::Peer, audit_log, handle_single_request, handler::Handler, ConnectionOptions},
+};
+use anyhow::{anyhow, Context};
+use lact_schema::Response;
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, Publish, QoS};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+const DEFAULT_TOPIC_PREFIX: &str = "lact";
+const DEFAULT_PUBLISH_INTERVAL_MS: u64 = 5000;
+const REQUEST_CHANNEL_SIZE: usize = 64;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Names of the topics used by the daemon, under a common prefix
+#[derive(Clone)]
+struct Topics {
+    prefix: String,
+}
+
+impl Topics {
+    /// `online` or `offline`, retained
+    fn status(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    /// Name of the active profile, empty for the default profile. Retained.
+    fn profile(&self) -> String {
+        format!("{}/profile", self.prefix)
+    }
+
+    fn stats(&self, id: &str) -> String {
+        format!("{}/gpus/{id}/stats", self.prefix)
+    }
+
+    fn set_profile(&self) -> String {
+        format!("{}/set/profile", self.prefix)
+    }
+
+    fn set_power_cap_filter(&self) -> String {
+        format!("{}/gpus/+/set/power_cap", self.prefix)
+    }
+
+    /// Parses a topic that commands are received on
+    fn command<'a>(&self, topic: &'a str) -> Option<Command<'a>> {
+        let rest = topic.strip_prefix(&self.prefix)?.strip_prefix('/')?;
+        if rest == "set/profile" {
+            return Some(Command::SetProfile);
+        }
+        let id = rest.strip_prefix("gpus/")?.strip_suffix("/set/power_cap")?;
+        (!id.is_empty() && !id.contains('/')).then_some(Command::SetPowerCap { id })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    SetProfile,
+    SetPowerCap { id: &'a str },
+}
+
+/// Publishes stats and the current profile to an MQTT broker, and handles commands sent to it.
+/// Reconnects to the broker as long as the daemon is running.
+pub async fn run(handler: Handler, config: MqttConfig) {
+    let topics = Topics {
+        prefix: config
+            .topic_prefix
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TOPIC_PREFIX.to_owned()),
+    };
+
+    let options = match mqtt_options(&config, &topics) {
+        Ok(options) => options,
+        Err(err) => {
+            error!("invalid MQTT config: {err:#}");
+            return;
+        }
+    };
+    let (host, port) = options.broker_address();
+    info!(
+        "publishing to MQTT broker at {host}:{port} under '{}'",
+        topics.prefix
+    );
+
+    let (client, event_loop) = AsyncClient::new(options, REQUEST_CHANNEL_SIZE);
+    let period = Duration::from_millis(
+        config
+            .publish_interval_ms
+            .unwrap_or(DEFAULT_PUBLISH_INTERVAL_MS)
+            .max(1),
+    );
+
+    tokio::join!(
+        poll_events(&handler, &client, event_loop, &topics),
+        publish_stats(&handler, &client, &topics, period),
+    );
+}
+
+fn mqtt_options(config: &MqttConfig, topics: &Topics) -> anyhow::Result<MqttOptions> {
+    let mut url = Url::parse(&config.broker_url).context("Invalid broker URL")?;
+    if !url.query_pairs().any(|(key, _)| key == "client_id") {
+        let client_id = config.client_id.clone().unwrap_or_else(default_client_id);
+        url.query_pairs_mut().append_pair("client_id", &client_id);
+    }
+
+    let mut options = MqttOptions::try_from(url).context("Invalid broker URL")?;
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(
+        topics.status(),
+        OFFLINE,
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(
+            username.clone(),
+            config.password.clone().unwrap_or_default(),
+        );
+    }
+    Ok(options)
+}
+
+fn default_client_id() -> String {
+    match nix::unistd::gethostname() {
+        Ok(hostname) => format!("lact-{}", hostname.to_string_lossy()),
+        Err(_) => "lact".to_owned(),
+    }
+}
+
+/// Drives the connection to the broker, which also sends out everything that was published
+async fn poll_events(
+    handler: &Handler,
+    client: &AsyncClient,
+    mut event_loop: EventLoop,
+    topics: &Topics,
+) {
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("connected to MQTT broker");
+                // The event loop has to keep running for these to be sent, so they must not wait for space in the channel
+                let current_profile = handler.config.read().await.current_profile.clone();
+                let result = client
+                    .try_publish(topics.status(), QoS::AtLeastOnce, true, ONLINE)
+                    .and_then(|()| {
+                        client.try_publish(
+                            topics.profile(),
+                            QoS::AtLeastOnce,
+                            true,
+                            current_profile.as_deref().unwrap_or_default(),
+                        )
+                    })
+                    .and_then(|()| client.try_subscribe(topics.set_profile(), QoS::AtLeastOnce))
+                    .and_then(|()| {
+                        client.try_subscribe(topics.set_power_cap_filter(), QoS::AtLeastOnce)
+                    });
+                if let Err(err) = result {
+                    error!("could not subscribe to MQTT command topics: {err}");
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let handler = handler.clone();
+                let topics = topics.clone();
+                tokio::task::spawn_local(async move {
+                    if let Err(err) = handle_command(&handler, &topics, &publish).await {
+                        warn!("MQTT command on '{}' failed: {err:#}", publish.topic);
+                    }
+                });
+            }
+            Ok(_) => (),
+            Err(err) => {
+                warn!("MQTT connection error: {err}, reconnecting in {RECONNECT_DELAY:?}");
+                sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+async fn publish_stats(handler: &Handler, client: &AsyncClient, topics: &Topics, period: Duration) {
+    let mut current_profile = handler.config.read().await.current_profile.clone();
+
+    let mut interval = interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let new_profile = handler.config.read().await.current_profile.clone();
+        if new_profile != current_profile {
+            current_profile = new_profile;
+            let name = current_profile.as_deref().unwrap_or_default();
+            if let Err(err) = client.try_publish(topics.profile(), QoS::AtLeastOnce, true, name) {
+                debug!("could not publish profile change: {err}");
+            }
+        }
+
+        for (id, _) in handler.list_gpu_pci_info().await {
+            match handler.get_gpu_stats(&id).await {
+                Ok(stats) => {
+                    let stats = serde_json::to_vec(&stats).expect("Stats are always serializable");
+                    // Stats are dropped while the broker is not reachable instead of being queued up
+                    if let Err(err) =
+                        client.try_publish(topics.stats(&id), QoS::AtMostOnce, false, stats)
+                    {
+                        debug!("could not publish stats of GPU {id}: {err}");
+                    }
+                }
+                Err(err) => debug!("could not get stats of GPU {id} for MQTT: {err:#}"),
+            }
+        }
+    }
+}
+
+async fn handle_command(
+    handler: &Handler,
+    topics: &Topics,
+    publish: &Publish,
+) -> anyhow::Result<()> {
+    let command = topics
+        .command(&publish.topic)
+        .ok_or_else(|| anyhow!("Unknown topic"))?;
+    let payload = std::str::from_utf8(&publish.payload)
+        .context("Payload is not valid UTF-8")?
+        .trim();
+
+    match command {
+        Command::SetProfile => {
+            let name = (!payload.is_empty()).then_some(payload);
+            request(
+                handler,
+                &json!({ "command": "set_profile", "args": { "name": name } }),
+            )
+            .await?;
+        }
+        Command::SetPowerCap { id } => {
+            let cap = parse_power_cap(payload)?;
+            info!("{} requested power cap {cap:?} for GPU {id}", Peer::Mqtt);
+            // There is no way to confirm the change remotely, so it is confirmed right away
+            audit_log::CALLER
+                .scope(
+                    Peer::Mqtt.to_string(),
+                    handler.set_power_cap_confirmed(id, cap),
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Watts, or an empty payload (or `default`) to reset the power cap
+fn parse_power_cap(payload: &str) -> anyhow::Result<Option<f64>> {
+    if payload.is_empty() || payload == "default" {
+        return Ok(None);
+    }
+    let cap = payload
+        .parse::<f64>()
+        .with_context(|| format!("Invalid power cap '{payload}'"))?;
+    if !cap.is_finite() || cap <= 0.0 {
+        return Err(anyhow!(
+            "Invalid power cap '{payload}', it has to be a positive number"
+        ));
+    }
+    Ok(Some(cap))
+}
+
+async fn request(handler: &Handler, payload: &Value) -> anyhow::Result<Value> {
+    let options = ConnectionOptions {
+        peer: Peer::Mqtt,
+        ..Default::default()
+    };
+    let response = handle_single_request(&payload.to_string(), handler, options).await?;
+    match serde_json::from_slice(&response)? {
+        Response::Ok(data) => Ok(data),
+        Response::Error(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_power_cap, Command, Topics};
+
+    #[test]
+    fn command_topics() {
+        let topics = Topics {
+            prefix: "home/lact".to_owned(),
+        };
+        assert_eq!(
+            topics.command("home/lact/set/profile"),
+            Some(Command::SetProfile)
+        );
+        assert_eq!(
+            topics.command("home/lact/gpus/1002:687F-1043:0555-0000:0b:00.0/set/power_cap"),
+            Some(Command::SetPowerCap {
+                id: "1002:687F-1043:0555-0000:0b:00.0"
+            })
+        );
+        assert_eq!(topics.command("home/lact/gpus//set/power_cap"), None);
+        assert_eq!(topics.command("home/lact/gpus/a/b/set/power_cap"), None);
+        assert_eq!(topics.command("home/lactx/set/profile"), None);
+        assert_eq!(topics.command("other/set/profile"), None);
+    }
+
+    #[test]
+    fn power_cap_payload() {
+        assert_eq!(parse_power_cap("").unwrap(), None);
+        assert_eq!(parse_power_cap("default").unwrap(), None);
+        assert_eq!(parse_power_cap("150.5").unwrap(), Some(150.5));
+        assert!(parse_power_cap("high").is_err());
+        for payload in ["nan", "inf", "-inf", "0", "-100"] {
+            assert!(parse_power_cap(payload).is_err(), "{payload} was accepted");
+        }
+    }
+}
@@ -1,31 +1,82 @@
+pub(crate) mod access;
+pub(crate) mod audit_log;
+mod gateway;
 pub mod gpu_controller;
 pub mod handler;
+mod metrics;
 mod profiles;
+mod stats_history;
+mod stats_sampler;
+mod subscriptions;
 pub(crate) mod system;
+mod tls;
+mod validation;
 mod vulkan;
 
-use self::handler::Handler;
-use crate::{config::Config, socket};
+use self::{
+    access::{Access, Peer},
+    handler::Handler,
+    subscriptions::Subscriptions,
+};
+use crate::{
+    config::{AccessControl, Config},
+    socket,
+};
 use anyhow::Context;
-use futures::future::join_all;
-use lact_schema::{Pong, Request, Response};
-use serde::Serialize;
-use std::fmt::Debug;
+use futures::{future::join_all, stream::FuturesUnordered, StreamExt};
+use lact_schema::{
+    api_schema::ApiSchema,
+    request::{features, AuthToken, COMMANDS, PROTOCOL_REVISION},
+    Capabilities, ErrorKind, Pong, Request, Response, ResponseEnvelope, GIT_COMMIT,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    future::Future,
+    rc::Rc,
+    time::Duration,
+};
 use tokio::{
     io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
     net::{TcpListener, UnixListener},
+    select,
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+    time::sleep,
 };
-use tracing::{error, info, instrument, trace};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, instrument, trace, warn};
+
+const EVENT_CHANNEL_SIZE: usize = 16;
+/// Reading from a connection pauses while this many of its requests are being handled
+const MAX_IN_FLIGHT_REQUESTS: usize = 32;
+const AUTH_FAILURE_DELAY_MS: u64 = 1000;
 
 pub struct Server {
     pub handler: Handler,
     unix_listener: UnixListener,
+    monitor_listener: Option<UnixListener>,
+    access_control: Option<Rc<AccessControl>>,
+    admin_groups: Rc<[String]>,
     tcp_listener: Option<TcpListener>,
+    tcp_auth_token: Option<Rc<str>>,
+    tls_acceptor: Option<TlsAcceptor>,
+    metrics_listener: Option<TcpListener>,
+    http_listener: Option<TcpListener>,
+    http_auth_token: Option<Rc<str>>,
 }
 
 impl Server {
     pub async fn new(config: Config) -> anyhow::Result<Self> {
-        let unix_listener = socket::listen(&config.daemon.admin_groups)?;
+        let access_control = config.daemon.access_control.clone().map(Rc::new);
+        let admin_groups = Rc::from(config.daemon.admin_groups.as_slice());
+        let unix_listener = socket::listen(&config.daemon.admin_groups, access_control.is_some())?;
+        let monitor_listener = if config.daemon.monitor_socket {
+            Some(socket::listen_monitor()?)
+        } else {
+            None
+        };
 
         let tcp_listener = if let Some(address) = &config.daemon.tcp_listen_address {
             let listener = TcpListener::bind(address)
@@ -37,48 +88,127 @@ impl Server {
             info!("TCP listener disabled");
             None
         };
+        let tcp_auth_token = config.daemon.tcp_auth_token.as_deref().map(Rc::from);
+        let tls_acceptor = config
+            .daemon
+            .tcp_tls
+            .as_ref()
+            .map(tls::acceptor)
+            .transpose()
+            .context("Could not set up TLS for the TCP listener")?;
+        if tcp_listener.is_some() {
+            if tcp_auth_token.is_none() {
+                warn!("TCP listener has no auth token configured, any client that can reach it has full access");
+            }
+            if tls_acceptor.is_none() {
+                warn!("TCP listener has no TLS configured, traffic is sent in plain text");
+            }
+        }
+
+        let metrics_listener = if let Some(address) = &config.daemon.metrics_listen_address {
+            let listener = TcpListener::bind(address)
+                .await
+                .with_context(|| format!("Could not bind metrics listener to {address}"))?;
+            Some(listener)
+        } else {
+            None
+        };
+
+        let http_listener = if let Some(address) = &config.daemon.http_listen_address {
+            let listener = TcpListener::bind(address)
+                .await
+                .with_context(|| format!("Could not bind HTTP listener to {address}"))?;
+            Some(listener)
+        } else {
+            None
+        };
+        let http_auth_token = config.daemon.http_auth_token.as_deref().map(Rc::from);
+        if http_listener.is_some() && http_auth_token.is_none() {
+            warn!(
+                "HTTP listener has no auth token configured, all requests to it will be rejected"
+            );
+        }
 
         let handler = Handler::new(config).await?;
 
         Ok(Self {
             handler,
             unix_listener,
+            monitor_listener,
+            access_control,
+            admin_groups,
             tcp_listener,
+            tcp_auth_token,
+            tls_acceptor,
+            metrics_listener,
+            http_listener,
+            http_auth_token,
         })
     }
 
     pub async fn run(self) {
         let mut tasks = vec![];
 
-        let unix_handler = self.handler.clone();
-        let unix_task = tokio::task::spawn_local(async move {
-            loop {
-                match self.unix_listener.accept().await {
-                    Ok((stream, _)) => {
-                        let handler = unix_handler.clone();
-                        tokio::task::spawn_local(async move {
-                            if let Err(error) = handle_stream(stream, handler).await {
-                                error!("{error}");
-                            }
-                        });
-                    }
-                    Err(error) => {
-                        error!("failed to handle connection: {error}");
-                    }
-                }
-            }
-        });
-        tasks.push(unix_task);
+        tasks.push(spawn_unix_listener(
+            self.unix_listener,
+            self.handler.clone(),
+            ConnectionOptions::default(),
+            self.access_control.clone(),
+            self.admin_groups.clone(),
+        ));
+
+        if let Some(monitor_listener) = self.monitor_listener {
+            let options = ConnectionOptions {
+                access: Access::ReadOnly,
+                ..Default::default()
+            };
+            tasks.push(spawn_unix_listener(
+                monitor_listener,
+                self.handler.clone(),
+                options,
+                self.access_control.clone(),
+                self.admin_groups,
+            ));
+        }
+
+        if let Some(metrics_listener) = self.metrics_listener {
+            let metrics_task =
+                tokio::task::spawn_local(metrics::listen(metrics_listener, self.handler.clone()));
+            tasks.push(metrics_task);
+        }
+
+        if let Some(http_listener) = self.http_listener {
+            let http_task = tokio::task::spawn_local(gateway::listen(
+                http_listener,
+                self.handler.clone(),
+                self.http_auth_token,
+            ));
+            tasks.push(http_task);
+        }
 
         if let Some(tcp_listener) = self.tcp_listener {
             let tcp_task = tokio::task::spawn_local(async move {
                 loop {
                     match tcp_listener.accept().await {
-                        Ok((stream, _)) => {
+                        Ok((stream, addr)) => {
                             let handler = self.handler.clone();
+                            let options = ConnectionOptions {
+                                auth_token: self.tcp_auth_token.clone(),
+                                peer: Peer::Tcp(addr),
+                                ..Default::default()
+                            };
+                            let tls_acceptor = self.tls_acceptor.clone();
                             tokio::task::spawn_local(async move {
-                                if let Err(error) = handle_stream(stream, handler).await {
-                                    error!("{error}");
+                                let result = match tls_acceptor {
+                                    Some(acceptor) => match acceptor.accept(stream).await {
+                                        Ok(stream) => handle_stream(stream, handler, options).await,
+                                        Err(error) => Err(anyhow::Error::new(error)
+                                            .context("TLS handshake failed")),
+                                    },
+                                    None => handle_stream(stream, handler, options).await,
+                                };
+                                if let Err(error) = result {
+                                    error!("{error:#}");
                                 }
                             });
                         }
@@ -95,113 +225,719 @@ impl Server {
     }
 }
 
-#[instrument(level = "debug", skip(stream, handler))]
+/// `access_control` is checked against the credentials of every connecting process,
+/// and can only further restrict the access given in `options`
+fn spawn_unix_listener(
+    listener: UnixListener,
+    handler: Handler,
+    options: ConnectionOptions,
+    access_control: Option<Rc<AccessControl>>,
+    admin_groups: Rc<[String]>,
+) -> JoinHandle<()> {
+    tokio::task::spawn_local(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let handler = handler.clone();
+                    let mut options = options.clone();
+                    options.peer = Peer::from_unix_stream(&stream);
+
+                    if let Some(policy) = &access_control {
+                        let policy_access = access::evaluate(policy, &admin_groups, &options.peer);
+                        if matches!(options.access, Access::Full { .. })
+                            || matches!(policy_access, Access::Denied)
+                        {
+                            options.access = policy_access;
+                        }
+                    }
+                    if matches!(options.access, Access::Denied) {
+                        warn!("refusing connection from {}", options.peer);
+                        continue;
+                    }
+
+                    tokio::task::spawn_local(async move {
+                        if let Err(error) = handle_stream(stream, handler, options).await {
+                            error!("{error}");
+                        }
+                    });
+                }
+                Err(error) => {
+                    error!("failed to handle connection: {error}");
+                }
+            }
+        }
+    })
+}
+
+/// Restrictions applied to a client connection
+#[derive(Clone, Default)]
+pub struct ConnectionOptions {
+    /// Token the client has to authenticate with before making any other requests
+    pub auth_token: Option<Rc<str>>,
+    pub access: Access,
+    pub peer: Peer,
+}
+
+/// State of a single client connection
+struct Connection {
+    subscriptions: Subscriptions,
+    options: ConnectionOptions,
+    authenticated: Cell<bool>,
+    /// Optional protocol features that the client announced with `Hello`
+    features: RefCell<Vec<String>>,
+}
+
+impl Connection {
+    /// Connection for requests that are made one at a time and are authenticated by other means, such as through D-Bus.
+    /// Stats subscriptions are not available, as there is nowhere to send the events to.
+    fn standalone(options: ConnectionOptions) -> Self {
+        let (event_tx, _) = mpsc::channel(1);
+        Self {
+            subscriptions: Subscriptions::new(event_tx),
+            authenticated: Cell::new(true),
+            options,
+            features: RefCell::default(),
+        }
+    }
+
+    fn uses_feature(&self, feature: &str) -> bool {
+        self.features.borrow().iter().any(|name| name == feature)
+    }
+
+    async fn authenticate(&self, token: AuthToken<'_>) -> anyhow::Result<()> {
+        if let Some(expected_token) = &self.options.auth_token {
+            if !constant_time_eq(expected_token.as_bytes(), token.0.as_bytes()) {
+                // Slow down brute-forcing
+                sleep(Duration::from_millis(AUTH_FAILURE_DELAY_MS)).await;
+                return Err(ErrorKind::PermissionDenied.error("Invalid authentication token"));
+            }
+        }
+        self.authenticated.set(true);
+        Ok(())
+    }
+}
+
+/// Requests are handled concurrently, and the ones with an id are answered as soon as they complete.
+/// Requests without an id are handled one at a time, so that their responses arrive in order.
+/// Until the connection is authenticated, all requests are handled one at a time as well.
+#[instrument(level = "debug", skip(stream, handler, options))]
 pub async fn handle_stream<T: AsyncRead + AsyncWrite + Unpin>(
     stream: T,
     handler: Handler,
+    options: ConnectionOptions,
 ) -> anyhow::Result<()> {
-    let mut stream = BufReader::new(stream);
-
-    let mut buf = String::new();
-    while stream.read_line(&mut buf).await? != 0 {
-        trace!("handling request: {}", buf.trim_end());
-
-        let maybe_request = serde_json::from_str(&buf);
-        let response = match maybe_request {
-            Ok(request) => match handle_request(request, &handler).await {
-                Ok(response) => response,
-                Err(error) => serde_json::to_vec(&Response::<()>::from(error))?,
-            },
-            Err(error) => serde_json::to_vec(&Response::<()>::from(
-                anyhow::Error::new(error).context("Failed to deserialize"),
-            ))?,
-        };
+    let (event_tx, event_rx) = mpsc::channel(EVENT_CHANNEL_SIZE);
+    let connection = Connection {
+        subscriptions: Subscriptions::new(event_tx),
+        authenticated: Cell::new(options.auth_token.is_none()),
+        options,
+        features: RefCell::default(),
+    };
+
+    let result = serve_stream(stream, &connection, event_rx, |line, id| {
+        let (handler, connection) = (&handler, &connection);
+        async move { handle_payload(&line, id, handler, connection).await }
+    })
+    .await;
 
-        stream.write_all(&response).await?;
-        stream.write_all(b"\n").await?;
+    connection.subscriptions.clear();
+    result
+}
+
+/// Reads requests from the stream and writes back their responses along with the events of the connection.
+/// `handle` is given the request payload and its id.
+async fn serve_stream<T, F, Fut>(
+    stream: T,
+    connection: &Connection,
+    mut event_rx: mpsc::Receiver<Vec<u8>>,
+    handle: F,
+) -> anyhow::Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(String, Option<u64>) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<u8>>>,
+{
+    let mut lines = BufReader::new(stream).lines();
+    let sequential_lock = Mutex::new(());
+    let mut in_flight = FuturesUnordered::new();
 
-        buf.clear();
+    let result: anyhow::Result<()> = async {
+        let mut reading = true;
+        // Requests that are still being handled are answered after the client stops sending new ones
+        while reading || !in_flight.is_empty() {
+            // `next_line`, `recv` and `next` are all cancellation safe
+            let payload = select! {
+                line = lines.next_line(), if reading && in_flight.len() < MAX_IN_FLIGHT_REQUESTS => {
+                    match line? {
+                        Some(line) => {
+                            let envelope = parse_envelope(&line);
+                            let id = envelope.as_ref().and_then(|envelope| envelope.id);
+                            // Requests made before authentication succeeds must not run alongside it
+                            let sequential = id.is_none()
+                                || !connection.authenticated.get()
+                                || envelope.is_some_and(|envelope| {
+                                    envelope.command.as_deref() == Some("authenticate")
+                                });
+
+                            let (handle, sequential_lock) = (&handle, &sequential_lock);
+                            in_flight.push(async move {
+                                let _guard = if sequential {
+                                    Some(sequential_lock.lock().await)
+                                } else {
+                                    None
+                                };
+                                handle(line, id).await
+                            });
+                        }
+                        None => reading = false,
+                    }
+                    continue;
+                }
+                Some(response) = in_flight.next() => response?,
+                Some(event) = event_rx.recv() => event,
+            };
+
+            let stream = lines.get_mut();
+            stream.write_all(&payload).await?;
+            stream.write_all(b"\n").await?;
+        }
+        Ok(())
     }
+    .await;
+
+    drop(in_flight);
+    result
+}
+
+/// Handles a single request made outside of a client connection, such as through D-Bus.
+pub async fn handle_single_request(
+    payload: &str,
+    handler: &Handler,
+    options: ConnectionOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let connection = Connection::standalone(options);
+    let response = handle_payload(payload, request_id(payload), handler, &connection).await;
+    connection.subscriptions.clear();
+    response
+}
+
+/// Optional fields of the request envelope, which are not part of the request itself
+#[derive(Deserialize)]
+struct RequestEnvelope {
+    id: Option<u64>,
+    command: Option<String>,
+}
+
+fn parse_envelope(payload: &str) -> Option<RequestEnvelope> {
+    serde_json::from_str(payload).ok()
+}
 
-    Ok(())
+fn request_id(payload: &str) -> Option<u64> {
+    parse_envelope(payload).and_then(|envelope| envelope.id)
 }
 
-#[instrument(level = "debug", skip(handler))]
-async fn handle_request<'a>(request: Request<'a>, handler: &'a Handler) -> anyhow::Result<Vec<u8>> {
+async fn handle_payload(
+    payload: &str,
+    id: Option<u64>,
+    handler: &Handler,
+    connection: &Connection,
+) -> anyhow::Result<Vec<u8>> {
+    let result = match serde_json::from_str(payload) {
+        Ok(request) => dispatch(request, id, handler, connection).await,
+        Err(error) => Err(deserialize_error(error)),
+    };
+    match result {
+        Ok(response) => Ok(response),
+        Err(error) => error_response(id, error),
+    }
+}
+
+/// Handles the request on behalf of the peer of the connection
+async fn dispatch<'a>(
+    request: Request<'a>,
+    id: Option<u64>,
+    handler: &'a Handler,
+    connection: &Connection,
+) -> anyhow::Result<Vec<u8>> {
+    audit_log::CALLER
+        .scope(
+            connection.options.peer.to_string(),
+            handle_request(request, id, handler, connection),
+        )
+        .await
+}
+
+fn deserialize_error(error: serde_json::Error) -> anyhow::Error {
+    anyhow::Error::new(error).context(ErrorKind::InvalidArgument.context("Failed to deserialize"))
+}
+
+#[instrument(level = "debug", skip(handler, connection))]
+#[allow(clippy::too_many_lines)]
+async fn handle_request<'a>(
+    request: Request<'a>,
+    envelope_id: Option<u64>,
+    handler: &'a Handler,
+    connection: &Connection,
+) -> anyhow::Result<Vec<u8>> {
+    if !connection.authenticated.get() && !matches!(request, Request::Authenticate { .. }) {
+        return Err(ErrorKind::AuthenticationRequired.error("Authentication required"));
+    }
+    let pending_gpu_id = handler.pending_config_gpu_id()?;
+    connection
+        .options
+        .access
+        .check(&request, pending_gpu_id.as_deref())?;
+    if !request.is_read_only() {
+        info!("{} requested {request:?}", connection.options.peer);
+    }
+
+    let subscriptions = &connection.subscriptions;
     match request {
-        Request::Ping => ok_response(ping()),
-        Request::SystemInfo => ok_response(system::info().await?),
-        Request::ListDevices => ok_response(handler.list_devices().await),
-        Request::DeviceInfo { id } => ok_response(handler.get_device_info(id).await?),
-        Request::DeviceStats { id } => ok_response(handler.get_gpu_stats(id).await?),
-        Request::DeviceClocksInfo { id } => ok_response(handler.get_clocks_info(id).await?),
+        Request::Ping => ok_response(envelope_id, ping()),
+        Request::Authenticate { token } => {
+            ok_response(envelope_id, connection.authenticate(token).await?)
+        }
+        Request::Hello {
+            client_version,
+            features,
+        } => {
+            let capabilities = hello(
+                client_version.as_deref(),
+                &features,
+                &connection.options.peer,
+            );
+            *connection.features.borrow_mut() = features;
+            ok_response(envelope_id, capabilities)
+        }
+        Request::ApiSchema => ok_response(envelope_id, ApiSchema::generate()),
+        Request::SystemInfo => ok_response(
+            envelope_id,
+            system::info(handler.config.read().await.is_immutable()).await?,
+        ),
+        Request::ListDevices => ok_response(envelope_id, handler.list_devices().await),
+        Request::DeviceInfo { id } => ok_response(envelope_id, handler.get_device_info(id).await?),
+        Request::DeviceStats { id } => ok_response(envelope_id, handler.get_gpu_stats(id).await?),
+        Request::SubscribeStats { id, interval_ms } => ok_response(
+            envelope_id,
+            subscriptions
+                .subscribe_stats(handler, id, interval_ms)
+                .await?,
+        ),
+        Request::UnsubscribeStats { id } => {
+            ok_response(envelope_id, subscriptions.unsubscribe_stats(id)?)
+        }
+        Request::SubscribeProfile => {
+            subscriptions.subscribe_profile(handler);
+            ok_response(envelope_id, ())
+        }
+        Request::DeviceStatsHistory {
+            id,
+            since,
+            resolution,
+        } => ok_response(
+            envelope_id,
+            handler.get_gpu_stats_history(id, since, resolution).await?,
+        ),
+        Request::DeviceClocksInfo { id } => {
+            ok_response(envelope_id, handler.get_clocks_info(id).await?)
+        }
         Request::DevicePowerProfileModes { id } => {
-            ok_response(handler.get_power_profile_modes(id).await?)
+            ok_response(envelope_id, handler.get_power_profile_modes(id).await?)
+        }
+        Request::SetFanControl(opts) => {
+            ok_response(envelope_id, handler.set_fan_control(opts).await?)
+        }
+        Request::ResetPmfw { id } => ok_response(envelope_id, handler.reset_pmfw(id).await?),
+        Request::SetPowerCap { id, cap } => {
+            ok_response(envelope_id, handler.set_power_cap(id, cap).await?)
         }
-        Request::SetFanControl(opts) => ok_response(handler.set_fan_control(opts).await?),
-        Request::ResetPmfw { id } => ok_response(handler.reset_pmfw(id).await?),
-        Request::SetPowerCap { id, cap } => ok_response(handler.set_power_cap(id, cap).await?),
         Request::SetPerformanceLevel {
             id,
             performance_level,
-        } => ok_response(handler.set_performance_level(id, performance_level).await?),
+        } => ok_response(
+            envelope_id,
+            handler.set_performance_level(id, performance_level).await?,
+        ),
         Request::SetClocksValue { id, command } => {
-            ok_response(handler.set_clocks_value(id, command).await?)
-        }
-        Request::BatchSetClocksValue { id, commands } => {
-            ok_response(handler.batch_set_clocks_value(id, commands).await?)
+            ok_response(envelope_id, handler.set_clocks_value(id, command).await?)
         }
+        Request::BatchSetClocksValue { id, commands } => ok_response(
+            envelope_id,
+            handler.batch_set_clocks_value(id, commands).await?,
+        ),
         Request::SetPowerProfileMode {
             id,
             index,
             custom_heuristics,
         } => ok_response(
+            envelope_id,
             handler
                 .set_power_profile_mode(id, index, custom_heuristics)
                 .await?,
         ),
-        Request::GetPowerStates { id } => ok_response(handler.get_power_states(id).await?),
-        Request::SetEnabledPowerStates { id, kind, states } => {
-            ok_response(handler.set_enabled_power_states(id, kind, states).await?)
+        Request::GetPowerStates { id } => {
+            ok_response(envelope_id, handler.get_power_states(id).await?)
         }
-        Request::VbiosDump { id } => ok_response(handler.vbios_dump(id).await?),
+        Request::SetEnabledPowerStates { id, kind, states } => ok_response(
+            envelope_id,
+            handler.set_enabled_power_states(id, kind, states).await?,
+        ),
+        Request::VbiosDump { id } => ok_response(envelope_id, handler.vbios_dump(id).await?),
         Request::ListProfiles { include_state } => {
-            ok_response(handler.list_profiles(include_state).await)
+            ok_response(envelope_id, handler.list_profiles(include_state).await)
         }
         Request::SetProfile { name, auto_switch } => ok_response(
+            envelope_id,
             handler
                 .set_profile(name.map(Into::into), auto_switch)
                 .await?,
         ),
         Request::CreateProfile { name, base } => {
-            ok_response(handler.create_profile(name, base).await?)
+            ok_response(envelope_id, handler.create_profile(name, base).await?)
+        }
+        Request::DeleteProfile { name } => {
+            ok_response(envelope_id, handler.delete_profile(name).await?)
         }
-        Request::DeleteProfile { name } => ok_response(handler.delete_profile(name).await?),
-        Request::MoveProfile { name, new_position } => {
-            ok_response(handler.move_profile(&name, new_position).await?)
+        Request::MoveProfile { name, new_position } => ok_response(
+            envelope_id,
+            handler.move_profile(&name, new_position).await?,
+        ),
+        Request::EvaluateProfileRule { rule } => {
+            ok_response(envelope_id, handler.evaluate_profile_rule(&rule)?)
         }
-        Request::EvaluateProfileRule { rule } => ok_response(handler.evaluate_profile_rule(&rule)?),
         Request::SetProfileRule { name, rule } => {
-            ok_response(handler.set_profile_rule(&name, rule).await?)
+            ok_response(envelope_id, handler.set_profile_rule(&name, rule).await?)
         }
-        Request::EnableOverdrive => ok_response(system::enable_overdrive().await?),
-        Request::DisableOverdrive => ok_response(system::disable_overdrive().await?),
-        Request::GenerateSnapshot => ok_response(handler.generate_snapshot().await?),
+        Request::EnableOverdrive => ok_response(envelope_id, system::enable_overdrive().await?),
+        Request::DisableOverdrive => ok_response(envelope_id, system::disable_overdrive().await?),
+        Request::GenerateSnapshot => ok_response(envelope_id, handler.generate_snapshot().await?),
         Request::ConfirmPendingConfig(command) => {
-            ok_response(handler.confirm_pending_config(command)?)
+            let status = handler.confirm_pending_config(command).await?;
+            if connection.uses_feature(features::SAVE_STATUS) {
+                ok_response(envelope_id, status)
+            } else {
+                ok_response(envelope_id, ())
+            }
         }
         Request::RestConfig => {
             handler.reset_config().await;
-            ok_response(())
+            ok_response(envelope_id, ())
+        }
+        Request::AuditLog {
+            gpu_id,
+            since,
+            limit,
+        } => ok_response(envelope_id, handler.get_audit_log(gpu_id, since, limit)?),
+        Request::ValidateConfig { yaml } => {
+            ok_response(envelope_id, handler.validate_config(&yaml).await)
+        }
+        Request::ListConfigRevisions => ok_response(envelope_id, handler.list_config_revisions()?),
+        Request::RestoreConfigRevision { id } => {
+            ok_response(envelope_id, handler.restore_config_revision(id).await?)
         }
     }
 }
 
-fn ok_response<T: Serialize + Debug>(data: T) -> anyhow::Result<Vec<u8>> {
+fn ok_response<T: Serialize + Debug>(id: Option<u64>, data: T) -> anyhow::Result<Vec<u8>> {
     trace!("responding with {data:?}");
-    Ok(serde_json::to_vec(&Response::Ok(data))?)
+    Ok(serde_json::to_vec(&ResponseEnvelope {
+        id,
+        response: Response::Ok(data),
+    })?)
+}
+
+fn error_response(id: Option<u64>, error: anyhow::Error) -> anyhow::Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&ResponseEnvelope {
+        id,
+        response: Response::<()>::from(error),
+    })?)
+}
+
+/// Compares the values without exiting early, so the time taken does not reveal how much of the value matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 fn ping() -> Pong {
     Pong
 }
+
+fn hello(client_version: Option<&str>, client_features: &[String], peer: &Peer) -> Capabilities {
+    debug!(
+        "{peer} connected with client version {}",
+        client_version.unwrap_or("unknown")
+    );
+    for feature in client_features {
+        if !features::ALL.contains(&feature.as_str()) {
+            debug!("{peer} uses unsupported feature '{feature}'");
+        }
+    }
+
+    Capabilities {
+        daemon_version: system::DAEMON_VERSION.to_owned(),
+        daemon_commit: Some(GIT_COMMIT.to_owned()),
+        protocol_revision: PROTOCOL_REVISION,
+        commands: COMMANDS
+            .iter()
+            .map(|command| (*command).to_owned())
+            .collect(),
+        features: features::ALL
+            .iter()
+            .map(|feature| (*feature).to_owned())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        handle_stream, serve_stream, subscriptions::Subscriptions, Connection, ConnectionOptions,
+        MAX_IN_FLIGHT_REQUESTS,
+    };
+    use crate::tests::test_handler;
+    use lact_schema::{request::AuthToken, ErrorKind, Request, Response};
+    use serde_json::Value;
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+        time::Duration,
+    };
+    use tokio::{
+        io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines},
+        sync::{mpsc, Semaphore},
+        task::LocalSet,
+        time::sleep,
+    };
+
+    /// Contains characters that have to be escaped in JSON
+    const TOKEN: &str = "se\"cr\\et\u{e9}";
+
+    /// Sends the requests one at a time on a connection that requires `TOKEN`, and returns the responses
+    async fn exchange(requests: &[String]) -> Vec<Response<Value>> {
+        let handler = test_handler().await;
+        let options = ConnectionOptions {
+            auth_token: Some(Rc::from(TOKEN)),
+            ..Default::default()
+        };
+        let (client, server) = duplex(64 * 1024);
+        tokio::task::spawn_local(handle_stream(server, handler, options));
+
+        let mut lines = BufReader::new(client).lines();
+        let mut responses = Vec::new();
+        for request in requests {
+            let stream = lines.get_mut();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            stream.write_all(b"\n").await.unwrap();
+
+            let response = lines.next_line().await.unwrap().unwrap();
+            responses.push(serde_json::from_str(&response).unwrap());
+        }
+        responses
+    }
+
+    fn authenticate(token: &str) -> String {
+        serde_json::to_string(&Request::Authenticate {
+            token: AuthToken(token.into()),
+        })
+        .unwrap()
+    }
+
+    fn error_kind(response: &Response<Value>) -> Option<ErrorKind> {
+        match response {
+            Response::Ok(_) => None,
+            Response::Error(err) => Some(err.kind),
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_before_authentication_are_rejected() {
+        LocalSet::new()
+            .run_until(async {
+                let responses = exchange(&[
+                    r#"{"command": "ping"}"#.to_owned(),
+                    r#"{"command": "list_devices"}"#.to_owned(),
+                    authenticate(TOKEN),
+                    r#"{"command": "ping"}"#.to_owned(),
+                ])
+                .await;
+
+                let kinds: Vec<_> = responses.iter().map(error_kind).collect();
+                assert_eq!(
+                    kinds,
+                    [
+                        Some(ErrorKind::AuthenticationRequired),
+                        Some(ErrorKind::AuthenticationRequired),
+                        None,
+                        None
+                    ]
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn wrong_token_is_rejected() {
+        LocalSet::new()
+            .run_until(async {
+                let responses =
+                    exchange(&[authenticate("secret"), r#"{"command": "ping"}"#.to_owned()]).await;
+
+                assert_eq!(error_kind(&responses[0]), Some(ErrorKind::PermissionDenied));
+                assert_eq!(
+                    error_kind(&responses[1]),
+                    Some(ErrorKind::AuthenticationRequired)
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn escaped_token_is_accepted() {
+        LocalSet::new()
+            .run_until(async {
+                let request = authenticate(TOKEN);
+                assert!(request.contains(r#"se\"cr\\et"#));
+
+                let responses = exchange(&[request, r#"{"command": "ping"}"#.to_owned()]).await;
+                assert_eq!(error_kind(&responses[0]), None);
+                assert_eq!(error_kind(&responses[1]), None);
+            })
+            .await;
+    }
+
+    /// Client end of a connection whose requests are handled by a stand-in for the handler.
+    /// Requests with the `block` command wait until a permit is added to `gate`.
+    struct FakeConnection {
+        lines: Lines<BufReader<DuplexStream>>,
+        started_rx: mpsc::UnboundedReceiver<Option<u64>>,
+        gate: Rc<Semaphore>,
+    }
+
+    impl FakeConnection {
+        fn new(authenticated: bool) -> Self {
+            let (started_tx, started_rx) = mpsc::unbounded_channel();
+            let gate = Rc::new(Semaphore::new(0));
+            let (client, server) = duplex(64 * 1024);
+
+            let handler_gate = gate.clone();
+            tokio::task::spawn_local(async move {
+                let (event_tx, event_rx) = mpsc::channel(1);
+                let connection = Connection {
+                    subscriptions: Subscriptions::new(event_tx),
+                    options: ConnectionOptions::default(),
+                    authenticated: Cell::new(authenticated),
+                    features: RefCell::default(),
+                };
+                serve_stream(server, &connection, event_rx, |line, id| {
+                    let (started_tx, gate) = (started_tx.clone(), handler_gate.clone());
+                    async move {
+                        started_tx.send(id).unwrap();
+                        if line.contains("block") {
+                            gate.acquire().await.unwrap().forget();
+                        }
+                        Ok(format!(r#"{{"id":{}}}"#, id.unwrap()).into_bytes())
+                    }
+                })
+                .await
+                .unwrap();
+            });
+
+            Self {
+                lines: BufReader::new(client).lines(),
+                started_rx,
+                gate,
+            }
+        }
+
+        async fn send(&mut self, id: u64, command: &str) {
+            let request = format!("{{\"id\":{id},\"command\":\"{command}\"}}\n");
+            self.lines
+                .get_mut()
+                .write_all(request.as_bytes())
+                .await
+                .unwrap();
+        }
+
+        async fn started(&mut self) -> u64 {
+            self.started_rx.recv().await.unwrap().unwrap()
+        }
+
+        /// Checks that no other request starts being handled
+        async fn assert_none_started(&mut self) {
+            sleep(Duration::from_millis(50)).await;
+            assert!(self.started_rx.try_recv().is_err());
+        }
+
+        async fn response_id(&mut self) -> u64 {
+            let line = self.lines.next_line().await.unwrap().unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()["id"]
+                .as_u64()
+                .unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_are_answered_out_of_order() {
+        LocalSet::new()
+            .run_until(async {
+                let mut connection = FakeConnection::new(true);
+                connection.send(1, "block").await;
+                connection.send(2, "ping").await;
+
+                assert_eq!(connection.response_id().await, 2);
+                connection.gate.add_permits(1);
+                assert_eq!(connection.response_id().await, 1);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_are_limited() {
+        LocalSet::new()
+            .run_until(async {
+                let mut connection = FakeConnection::new(true);
+                let count = MAX_IN_FLIGHT_REQUESTS as u64;
+                for id in 0..=count {
+                    connection.send(id, "block").await;
+                }
+
+                for id in 0..count {
+                    assert_eq!(connection.started().await, id);
+                }
+                connection.assert_none_started().await;
+
+                // Finishing one request makes room for the next one
+                connection.gate.add_permits(1);
+                connection.response_id().await;
+                assert_eq!(connection.started().await, count);
+
+                connection.gate.add_permits(MAX_IN_FLIGHT_REQUESTS);
+                for _ in 0..count {
+                    connection.response_id().await;
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn requests_before_authentication_are_sequential() {
+        LocalSet::new()
+            .run_until(async {
+                let mut connection = FakeConnection::new(false);
+                connection.send(1, "block").await;
+                connection.send(2, "ping").await;
+
+                assert_eq!(connection.started().await, 1);
+                connection.assert_none_started().await;
+
+                connection.gate.add_permits(1);
+                assert_eq!(connection.response_id().await, 1);
+                assert_eq!(connection.started().await, 2);
+                assert_eq!(connection.response_id().await, 2);
+            })
+            .await;
+    }
+}
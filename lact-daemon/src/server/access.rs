@@ -0,0 +1,344 @@
+use crate::config::AccessControl;
+use lact_schema::{ErrorKind, Request};
+use nix::unistd::{Gid, Group, Uid, User};
+use std::{fmt, net::SocketAddr, rc::Rc};
+use tokio::net::UnixStream;
+use tracing::warn;
+
+/// The client on the other end of a connection
+#[derive(Clone, Debug, Default)]
+pub enum Peer {
+    /// Embedded daemon in the same process
+    #[default]
+    Local,
+    Unix {
+        uid: u32,
+        gid: u32,
+        pid: Option<i32>,
+        user: Option<String>,
+    },
+    Tcp(SocketAddr),
+    /// Command received through the MQTT broker
+    Mqtt,
+    /// Unix socket client whose credentials could not be read
+    Unknown,
+}
+
+impl Peer {
+    pub fn from_unix_stream(stream: &UnixStream) -> Self {
+        match stream.peer_cred() {
+            Ok(cred) => {
+                let user = User::from_uid(Uid::from_raw(cred.uid()))
+                    .ok()
+                    .flatten()
+                    .map(|user| user.name);
+                Self::Unix {
+                    uid: cred.uid(),
+                    gid: cred.gid(),
+                    pid: cred.pid(),
+                    user,
+                }
+            }
+            Err(err) => {
+                warn!("could not read unix socket peer credentials: {err}");
+                Self::Unknown
+            }
+        }
+    }
+
+    /// Peer identified only by its user id, such as a D-Bus client
+    pub fn from_uid(uid: u32, pid: Option<i32>) -> Self {
+        let user = User::from_uid(Uid::from_raw(uid)).ok().flatten();
+        Self::Unix {
+            uid,
+            gid: user.as_ref().map_or(uid, |user| user.gid.as_raw()),
+            pid,
+            user: user.map(|user| user.name),
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Local => f.write_str("local client"),
+            Peer::Unix {
+                uid,
+                gid,
+                pid,
+                user,
+            } => {
+                write!(f, "uid {uid}")?;
+                if let Some(user) = user {
+                    write!(f, " ({user})")?;
+                }
+                write!(f, ", gid {gid}")?;
+                if let Some(pid) = pid {
+                    write!(f, ", pid {pid}")?;
+                }
+                Ok(())
+            }
+            Peer::Tcp(addr) => write!(f, "TCP peer {addr}"),
+            Peer::Mqtt => f.write_str("MQTT broker"),
+            Peer::Unknown => f.write_str("unknown peer"),
+        }
+    }
+}
+
+/// What a connection is allowed to do
+#[derive(Clone, Debug)]
+pub enum Access {
+    Denied,
+    ReadOnly,
+    /// Can make all requests. When `gpus` is specified, settings can only be changed on the given GPUs.
+    Full {
+        gpus: Option<Rc<[String]>>,
+    },
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Self::Full { gpus: None }
+    }
+}
+
+impl Access {
+    /// `pending_gpu_id` is the GPU with an unconfirmed settings change, which is what confirming or reverting applies to
+    pub fn check(&self, request: &Request, pending_gpu_id: Option<&str>) -> anyhow::Result<()> {
+        match self {
+            Access::Denied => return Err(ErrorKind::PermissionDenied.error("Permission denied")),
+            Access::ReadOnly => {
+                if !request.is_read_only() {
+                    return Err(ErrorKind::PermissionDenied.error(
+                        "Permission denied: this connection only allows read-only requests",
+                    ));
+                }
+            }
+            Access::Full { gpus: Some(gpus) } if !request.is_read_only() => {
+                let id = match request {
+                    Request::ConfirmPendingConfig(_) => pending_gpu_id,
+                    _ => request.gpu_id(),
+                };
+                match id {
+                    Some(id) if gpus.iter().any(|gpu| gpu == id) => (),
+                    Some(id) => {
+                        return Err(ErrorKind::PermissionDenied
+                            .error(format!("Permission denied: not allowed to change GPU {id}")))
+                    }
+                    None => {
+                        return Err(ErrorKind::PermissionDenied.error(
+                            "Permission denied: only allowed to change settings of specific GPUs",
+                        ))
+                    }
+                }
+            }
+            Access::Full { .. } => (),
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates the policy for the given peer. Root always has full access.
+/// Members of the admin groups are allowed in addition to the users and groups from the policy.
+pub fn evaluate(policy: &AccessControl, admin_groups: &[String], peer: &Peer) -> Access {
+    match peer {
+        Peer::Local | Peer::Tcp(_) | Peer::Mqtt | Peer::Unix { uid: 0, .. } => Access::default(),
+        // The socket is accessible by everyone when access control is used
+        Peer::Unknown => Access::ReadOnly,
+        Peer::Unix { uid, gid, user, .. } => {
+            evaluate_user(policy, admin_groups, *uid, user.as_deref(), |group| {
+                is_group_member(group, *gid, user.as_deref())
+            })
+        }
+    }
+}
+
+/// Gives full access to root and members of the admin groups, and read-only access to everyone else.
+/// Used for clients that do not go through the permissions of the unix socket.
+pub fn evaluate_admin_groups(admin_groups: &[String], peer: &Peer) -> Access {
+    match peer {
+        Peer::Local | Peer::Tcp(_) | Peer::Mqtt | Peer::Unix { uid: 0, .. } => Access::default(),
+        Peer::Unknown => Access::ReadOnly,
+        Peer::Unix { gid, user, .. } => {
+            if admin_groups
+                .iter()
+                .any(|group| is_group_member(group, *gid, user.as_deref()))
+            {
+                Access::default()
+            } else {
+                Access::ReadOnly
+            }
+        }
+    }
+}
+
+fn evaluate_user(
+    policy: &AccessControl,
+    admin_groups: &[String],
+    uid: u32,
+    user: Option<&str>,
+    in_group: impl Fn(&str) -> bool,
+) -> Access {
+    let uid = uid.to_string();
+    let user_matches = |name: &String| *name == uid || Some(name.as_str()) == user;
+    let group_matches = |name: &String| in_group(name);
+
+    if policy.deny_users.iter().any(user_matches) || policy.deny_groups.iter().any(group_matches) {
+        return Access::Denied;
+    }
+
+    if policy.allow_users.iter().any(user_matches)
+        || policy.allow_groups.iter().any(group_matches)
+        || admin_groups.iter().any(group_matches)
+    {
+        let gpus = policy
+            .user_gpus
+            .iter()
+            .find(|(name, _)| user_matches(name))
+            .map(|(_, gpus)| Rc::from(gpus.as_slice()));
+        return Access::Full { gpus };
+    }
+
+    Access::ReadOnly
+}
+
+/// Whether the group is the primary group of the user or lists the user as a member
+fn is_group_member(group: &str, gid: u32, user: Option<&str>) -> bool {
+    let group = match group.parse() {
+        Ok(id) => Group::from_gid(Gid::from_raw(id)),
+        Err(_) => Group::from_name(group),
+    };
+    match group {
+        Ok(Some(group)) => {
+            group.gid.as_raw() == gid
+                || user.is_some_and(|user| group.mem.iter().any(|member| member == user))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, evaluate_admin_groups, evaluate_user, Access, Peer};
+    use crate::config::AccessControl;
+    use indexmap::IndexMap;
+    use lact_schema::{request::ConfirmCommand, Request};
+
+    fn policy() -> AccessControl {
+        AccessControl {
+            allow_users: vec!["alice".to_owned(), "1002".to_owned()],
+            allow_groups: vec!["gpu-admins".to_owned()],
+            deny_users: vec!["mallory".to_owned()],
+            deny_groups: vec![],
+            user_gpus: IndexMap::from([("alice".to_owned(), vec!["gpu-1".to_owned()])]),
+        }
+    }
+
+    fn in_gpu_admins(group: &str) -> bool {
+        group == "gpu-admins"
+    }
+
+    #[test]
+    fn evaluate_policy() {
+        let policy = policy();
+
+        let access = evaluate_user(&policy, &[], 1000, Some("bob"), |_| false);
+        assert!(matches!(access, Access::ReadOnly));
+
+        let access = evaluate_user(&policy, &[], 1000, Some("bob"), in_gpu_admins);
+        assert!(matches!(access, Access::Full { gpus: None }));
+
+        let access = evaluate_user(&policy, &[], 1002, None, |_| false);
+        assert!(matches!(access, Access::Full { gpus: None }));
+
+        let access = evaluate_user(&policy, &[], 1003, Some("mallory"), in_gpu_admins);
+        assert!(matches!(access, Access::Denied));
+    }
+
+    #[test]
+    fn admin_groups_are_allowed() {
+        let admin_groups = ["wheel".to_owned()];
+        let in_wheel = |group: &str| group == "wheel";
+
+        let access = evaluate_user(&policy(), &admin_groups, 1000, Some("bob"), in_wheel);
+        assert!(matches!(access, Access::Full { gpus: None }));
+
+        let access = evaluate_user(&policy(), &admin_groups, 1000, Some("bob"), |_| false);
+        assert!(matches!(access, Access::ReadOnly));
+
+        // Denying still takes precedence
+        let access = evaluate_user(&policy(), &admin_groups, 1003, Some("mallory"), in_wheel);
+        assert!(matches!(access, Access::Denied));
+    }
+
+    #[test]
+    fn gpu_restricted_access() {
+        let access = evaluate_user(&policy(), &[], 1001, Some("alice"), |_| false);
+
+        assert!(access
+            .check(&Request::DeviceStats { id: "gpu-2" }, None)
+            .is_ok());
+        assert!(access
+            .check(
+                &Request::SetPowerCap {
+                    id: "gpu-1",
+                    cap: None
+                },
+                None
+            )
+            .is_ok());
+        assert!(access
+            .check(
+                &Request::SetPowerCap {
+                    id: "gpu-2",
+                    cap: None
+                },
+                None
+            )
+            .is_err());
+        assert!(access
+            .check(
+                &Request::ConfirmPendingConfig(ConfirmCommand::Confirm),
+                Some("gpu-1")
+            )
+            .is_ok());
+        // Pending changes of other GPUs cannot be confirmed or reverted
+        assert!(access
+            .check(
+                &Request::ConfirmPendingConfig(ConfirmCommand::Revert),
+                Some("gpu-2")
+            )
+            .is_err());
+        assert!(access.check(&Request::EnableOverdrive, None).is_err());
+        assert!(access
+            .check(
+                &Request::AuditLog {
+                    gpu_id: Some("gpu-1"),
+                    since: None,
+                    limit: None
+                },
+                None
+            )
+            .is_ok());
+        assert!(access
+            .check(
+                &Request::AuditLog {
+                    gpu_id: None,
+                    since: None,
+                    limit: None
+                },
+                None
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn unknown_peer_is_read_only() {
+        for access in [
+            evaluate(&policy(), &[], &Peer::Unknown),
+            evaluate_admin_groups(&["wheel".to_owned()], &Peer::Unknown),
+        ] {
+            assert!(matches!(access, Access::ReadOnly));
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::Context;
+use lact_schema::{AuditEvent, AuditLogEntry, ConfigChange};
+use nix::unistd::getuid;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info, warn};
+
+const FILE_NAME: &str = "audit.jsonl";
+/// Size after which the log is moved to a backup file and started over.
+/// Only a single backup is kept, so the log takes up at most twice this size.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// Caller recorded for changes that were not made by a client request
+const DAEMON_CALLER: &str = "daemon";
+
+tokio::task_local! {
+    /// Identity of the client whose request is currently being handled
+    pub static CALLER: String;
+}
+
+pub fn current_caller() -> String {
+    CALLER
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| DAEMON_CALLER.to_owned())
+}
+
+/// Persistent log of settings changes, stored as one JSON entry per line
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    max_file_size: u64,
+}
+
+impl AuditLog {
+    /// When `path` is `None`, nothing is recorded
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            max_file_size: MAX_FILE_SIZE,
+        }
+    }
+
+    pub fn record(&self, caller: String, event: AuditEvent) {
+        let entry = AuditLogEntry {
+            timestamp: unix_timestamp_ms(),
+            caller,
+            event,
+        };
+        info!("audit: {entry:?}");
+
+        if let Some(path) = &self.path {
+            if let Err(err) = append_entry(path, &entry, self.max_file_size) {
+                error!("could not write audit log entry: {err:#}");
+            }
+        }
+    }
+
+    /// Returns the matching entries, oldest first. When `limit` is set, only the latest entries are returned.
+    pub fn query(
+        &self,
+        gpu_id: Option<&str>,
+        since: Option<u64>,
+        limit: Option<usize>,
+    ) -> anyhow::Result<Vec<AuditLogEntry>> {
+        let Some(path) = &self.path else {
+            return Ok(vec![]);
+        };
+
+        let mut contents = String::new();
+        for path in [backup_path(path), path.clone()] {
+            if path.exists() {
+                contents.push_str(&fs::read_to_string(path).context("Could not read audit log")?);
+            }
+        }
+
+        let mut entries: Vec<AuditLogEntry> = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    warn!("skipping invalid audit log entry: {err}");
+                    None
+                }
+            })
+            .filter(|entry: &AuditLogEntry| since.is_none_or(|since| entry.timestamp >= since))
+            .filter(|entry| gpu_id.is_none_or(|id| entry.event.gpu_id() == Some(id)))
+            .collect();
+
+        if let Some(limit) = limit {
+            let skip = entries.len().saturating_sub(limit);
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+}
+
+fn append_entry(path: &Path, entry: &AuditLogEntry, max_file_size: u64) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= max_file_size) {
+        fs::rename(path, backup_path(path)).context("Could not rotate audit log")?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("jsonl.1")
+}
+
+pub fn default_path() -> PathBuf {
+    if getuid().is_root() {
+        PathBuf::from("/var/lib/lact").join(FILE_NAME)
+    } else {
+        let state_dir = PathBuf::from(env::var("XDG_STATE_HOME").unwrap_or_else(|_| {
+            let home = env::var("HOME").expect("$HOME variable is not set");
+            format!("{home}/.local/state")
+        }));
+        state_dir.join("lact").join(FILE_NAME)
+    }
+}
+
+pub fn unix_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| {
+            u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Lists the values that differ between the serialized representations, sorted by path
+pub fn diff<T: Serialize>(before: &T, after: &T) -> Vec<ConfigChange> {
+    let before = serde_json::to_value(before).unwrap_or(Value::Null);
+    let after = serde_json::to_value(after).unwrap_or(Value::Null);
+
+    let mut changes = Vec::new();
+    diff_values(String::new(), Some(&before), Some(&after), &mut changes);
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+fn diff_values(
+    path: String,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    changes: &mut Vec<ConfigChange>,
+) {
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let removed_keys = before.keys().filter(|key| !after.contains_key(*key));
+            for key in after.keys().chain(removed_keys) {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff_values(key_path, before.get(key), after.get(key), changes);
+            }
+        }
+        (before, after) if before != after => changes.push(ConfigChange {
+            path,
+            before: before.map(Value::to_string),
+            after: after.map(Value::to_string),
+        }),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, AuditLog};
+    use crate::config;
+    use lact_schema::{AuditEvent, ConfigChange, SettingsOutcome};
+    use std::fs;
+
+    #[test]
+    fn diff_gpu_config() {
+        let before = config::Gpu {
+            power_cap: Some(200.0),
+            ..Default::default()
+        };
+        let mut after = config::Gpu {
+            power_cap: Some(150.0),
+            ..Default::default()
+        };
+        after.clocks_configuration.max_core_clock = Some(2000);
+
+        let changes = diff(&before, &after);
+        assert_eq!(
+            vec![
+                ConfigChange {
+                    path: "max_core_clock".to_owned(),
+                    before: None,
+                    after: Some("2000".to_owned()),
+                },
+                ConfigChange {
+                    path: "power_cap".to_owned(),
+                    before: Some("200.0".to_owned()),
+                    after: Some("150.0".to_owned()),
+                },
+            ],
+            changes
+        );
+        assert!(diff(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn record_and_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(Some(dir.path().join("audit.jsonl")));
+
+        for (gpu_id, outcome) in [
+            ("gpu-1", SettingsOutcome::Confirmed),
+            ("gpu-2", SettingsOutcome::TimedOut),
+            ("gpu-1", SettingsOutcome::Reverted),
+        ] {
+            log.record(
+                "uid 1000".to_owned(),
+                AuditEvent::GpuSettings {
+                    gpu_id: gpu_id.to_owned(),
+                    outcome,
+                    changes: vec![],
+                },
+            );
+        }
+
+        assert_eq!(3, log.query(None, None, None).unwrap().len());
+
+        let entries = log.query(Some("gpu-1"), None, None).unwrap();
+        assert_eq!(2, entries.len());
+        assert!(matches!(
+            entries[1].event,
+            AuditEvent::GpuSettings {
+                outcome: SettingsOutcome::Reverted,
+                ..
+            }
+        ));
+
+        let entries = log.query(None, None, Some(1)).unwrap();
+        assert_eq!("gpu-1", entries[0].event.gpu_id().unwrap());
+    }
+
+    #[test]
+    fn rotates_large_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = AuditLog::new(Some(dir.path().join("audit.jsonl")));
+        log.max_file_size = 1;
+
+        for gpu_id in ["gpu-1", "gpu-2", "gpu-3"] {
+            log.record(
+                "uid 1000".to_owned(),
+                AuditEvent::GpuSettings {
+                    gpu_id: gpu_id.to_owned(),
+                    outcome: SettingsOutcome::Confirmed,
+                    changes: vec![],
+                },
+            );
+        }
+
+        // Only the current file and a single backup are kept
+        let entries = log.query(None, None, None).unwrap();
+        let ids: Vec<_> = entries
+            .iter()
+            .map(|entry| entry.event.gpu_id().unwrap())
+            .collect();
+        assert_eq!(vec!["gpu-2", "gpu-3"], ids);
+        assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
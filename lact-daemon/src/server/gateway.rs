@@ -0,0 +1,530 @@
+use super::{
+    access::Peer, constant_time_eq, deserialize_error, dispatch, error_response, handler::Handler,
+    Connection, ConnectionOptions, AUTH_FAILURE_DELAY_MS,
+};
+use futures::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{self, HeaderValue},
+    server::conn::http1,
+    service::service_fn,
+    StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use lact_schema::{ErrorKind, Event, Request};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{convert::Infallible, rc::Rc, time::Duration};
+use tokio::{
+    net::TcpListener,
+    select,
+    time::{interval, sleep, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+use tracing::{debug, error, info};
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const MAX_BODY_SIZE: usize = 64 * 1024;
+const DEFAULT_EVENTS_INTERVAL_MS: u64 = 1000;
+const MIN_EVENTS_INTERVAL_MS: u64 = 50;
+
+type HttpRequest = hyper::Request<Incoming>;
+type HttpResponse = hyper::Response<Full<Bytes>>;
+
+/// Method, path and command of every REST endpoint.
+/// Path segments starting with `:` are passed to the command as arguments with the same name,
+/// along with the query parameters and the fields of the JSON request body.
+const ROUTES: &[(&str, &str, &str)] = &[
+    ("GET", "/schema", "api_schema"),
+    ("GET", "/system", "system_info"),
+    ("GET", "/devices", "list_devices"),
+    ("GET", "/devices/:id", "device_info"),
+    ("GET", "/devices/:id/stats", "device_stats"),
+    ("GET", "/devices/:id/stats/history", "device_stats_history"),
+    ("GET", "/devices/:id/clocks", "device_clocks_info"),
+    ("PUT", "/devices/:id/clocks", "batch_set_clocks_value"),
+    (
+        "GET",
+        "/devices/:id/power_profile_modes",
+        "device_power_profile_modes",
+    ),
+    (
+        "PUT",
+        "/devices/:id/power_profile_mode",
+        "set_power_profile_mode",
+    ),
+    ("GET", "/devices/:id/power_states", "get_power_states"),
+    (
+        "PUT",
+        "/devices/:id/power_states",
+        "set_enabled_power_states",
+    ),
+    ("PUT", "/devices/:id/power_cap", "set_power_cap"),
+    (
+        "PUT",
+        "/devices/:id/performance_level",
+        "set_performance_level",
+    ),
+    ("PUT", "/devices/:id/fan_control", "set_fan_control"),
+    ("POST", "/devices/:id/reset_pmfw", "reset_pmfw"),
+    ("GET", "/devices/:id/vbios", "vbios_dump"),
+    ("GET", "/profiles", "list_profiles"),
+    ("POST", "/profiles", "create_profile"),
+    ("PUT", "/profiles/current", "set_profile"),
+    ("POST", "/profiles/evaluate_rule", "evaluate_profile_rule"),
+    ("DELETE", "/profiles/:name", "delete_profile"),
+    ("PUT", "/profiles/:name/position", "move_profile"),
+    ("PUT", "/profiles/:name/rule", "set_profile_rule"),
+    ("POST", "/pending_config/:command", "confirm_pending_config"),
+    ("GET", "/audit_log", "audit_log"),
+    ("POST", "/snapshot", "generate_snapshot"),
+    ("POST", "/overdrive/enable", "enable_overdrive"),
+    ("POST", "/overdrive/disable", "disable_overdrive"),
+    ("POST", "/config/reset", "rest_config"),
+    ("POST", "/config/validate", "validate_config"),
+    ("GET", "/config/revisions", "list_config_revisions"),
+    (
+        "POST",
+        "/config/revisions/restore",
+        "restore_config_revision",
+    ),
+];
+
+/// Commands without arguments, which have to be sent without an `args` field
+const UNIT_COMMANDS: &[&str] = &[
+    "api_schema",
+    "system_info",
+    "list_devices",
+    "generate_snapshot",
+    "enable_overdrive",
+    "disable_overdrive",
+    "rest_config",
+    "list_config_revisions",
+];
+
+/// Serves the API as REST-style endpoints, and events on the `/events` WebSocket
+pub async fn listen(listener: TcpListener, handler: Handler, auth_token: Option<Rc<str>>) {
+    info!("serving HTTP API on {:?}", listener.local_addr());
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let handler = handler.clone();
+                let options = ConnectionOptions {
+                    auth_token: auth_token.clone(),
+                    peer: Peer::Tcp(addr),
+                    ..Default::default()
+                };
+                tokio::task::spawn_local(async move {
+                    let service = service_fn(|request| {
+                        let handler = handler.clone();
+                        let options = options.clone();
+                        async move { Ok::<_, Infallible>(handle(request, handler, options).await) }
+                    });
+                    if let Err(err) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .with_upgrades()
+                        .await
+                    {
+                        debug!("could not serve HTTP connection: {err}");
+                    }
+                });
+            }
+            Err(err) => error!("failed to handle HTTP connection: {err}"),
+        }
+    }
+}
+
+async fn handle(
+    request: HttpRequest,
+    handler: Handler,
+    options: ConnectionOptions,
+) -> HttpResponse {
+    let headers = request.headers();
+    let origin = headers
+        .get(header::ORIGIN)
+        .map(|value| value.to_str().unwrap_or_default());
+    let host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok());
+    if !is_same_origin(origin, host) {
+        return api_error(
+            ErrorKind::PermissionDenied.error("Cross-origin requests are not allowed"),
+        );
+    }
+
+    let query = query_args(request.uri().query());
+
+    let expected_token = options.auth_token.as_deref().unwrap_or_default();
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| query.get("token").and_then(Value::as_str));
+    // Without a configured token, every request is rejected
+    let authorized = !expected_token.is_empty()
+        && token.is_some_and(|token| constant_time_eq(expected_token.as_bytes(), token.as_bytes()));
+    if !authorized {
+        // Slow down brute-forcing
+        sleep(Duration::from_millis(AUTH_FAILURE_DELAY_MS)).await;
+        return api_error(ErrorKind::AuthenticationRequired.error("Missing or invalid auth token"));
+    }
+
+    let path = request.uri().path().to_owned();
+    if path == "/events" {
+        return events(request, handler, &query).await;
+    }
+
+    let (command, path_args) = match find_route(request.method().as_str(), &path) {
+        Ok(route) => route,
+        Err(status) => return empty(status),
+    };
+
+    let mut args = match read_body(request).await {
+        Ok(body) => body,
+        Err(err) => return api_error(err),
+    };
+    args.extend(query.into_iter().filter(|(name, _)| name != "token"));
+    args.extend(path_args);
+
+    let mut payload = Map::new();
+    payload.insert("command".to_owned(), command.into());
+    if !UNIT_COMMANDS.contains(&command) {
+        payload.insert("args".to_owned(), args.into());
+    }
+    let payload = Value::Object(payload);
+
+    let connection = Connection::standalone(options);
+    let result = match Request::deserialize(&payload) {
+        Ok(request) => dispatch(request, None, &handler, &connection).await,
+        Err(err) => Err(deserialize_error(err)),
+    };
+    match result {
+        Ok(body) => json(StatusCode::OK, body),
+        Err(err) => api_error(err),
+    }
+}
+
+/// Returns the command and the path arguments, or the status to respond with if there is no matching route
+fn find_route(method: &str, path: &str) -> Result<(&'static str, Map<String, Value>), StatusCode> {
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    let mut status = StatusCode::NOT_FOUND;
+
+    for (route_method, route_path, command) in ROUTES {
+        let route_segments: Vec<&str> = route_path.split('/').collect();
+        if route_segments.len() != segments.len() {
+            continue;
+        }
+
+        let mut args = Map::new();
+        let matches =
+            route_segments.iter().zip(&segments).all(
+                |(route_segment, segment)| match route_segment.strip_prefix(':') {
+                    Some(name) => {
+                        let value = percent_decode_str(segment).decode_utf8_lossy();
+                        args.insert(name.to_owned(), Value::String(value.into_owned()));
+                        true
+                    }
+                    None => route_segment == segment,
+                },
+            );
+
+        if matches {
+            if *route_method == method {
+                return Ok((command, args));
+            }
+            status = StatusCode::METHOD_NOT_ALLOWED;
+        }
+    }
+
+    Err(status)
+}
+
+/// Query values that look like numbers or booleans are passed as such, everything else as strings
+fn query_args(query: Option<&str>) -> Map<String, Value> {
+    form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+        .map(|(name, value)| {
+            let value = match serde_json::from_str(&value) {
+                Ok(value @ (Value::Number(_) | Value::Bool(_))) => value,
+                _ => Value::String(value.into_owned()),
+            };
+            (name.into_owned(), value)
+        })
+        .collect()
+}
+
+/// Browsers send an `Origin` header with requests made by websites. Requests from other origins are rejected,
+/// so that websites opened by the user can't make requests on their behalf.
+fn is_same_origin(origin: Option<&str>, host: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    let authority = origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"));
+    authority
+        .zip(host)
+        .is_some_and(|(authority, host)| authority.eq_ignore_ascii_case(host))
+}
+
+/// Websites can only send other content types (such as form data) without a CORS preflight request
+fn is_json_content_type(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(JSON_CONTENT_TYPE))
+}
+
+async fn read_body(request: HttpRequest) -> anyhow::Result<Map<String, Value>> {
+    let is_json = is_json_content_type(
+        request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()),
+    );
+    let body = Limited::new(request.into_body(), MAX_BODY_SIZE)
+        .collect()
+        .await
+        .map_err(|err| ErrorKind::InvalidArgument.error(format!("Could not read body: {err}")))?
+        .to_bytes();
+    if body.is_empty() {
+        return Ok(Map::new());
+    }
+    if !is_json {
+        return Err(ErrorKind::InvalidArgument.error(format!(
+            "Body has to be sent with content type {JSON_CONTENT_TYPE}"
+        )));
+    }
+
+    serde_json::from_slice(&body).map_err(|err| {
+        anyhow::Error::new(err)
+            .context(ErrorKind::InvalidArgument.context("Body has to be a JSON object"))
+    })
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    /// Only send stats of this GPU
+    gpu_id: Option<String>,
+    interval_ms: Option<u64>,
+}
+
+/// Upgrades the connection to a WebSocket, which receives the stats of all GPUs periodically
+/// and the name of the current profile when the connection is opened and whenever it changes
+async fn events(
+    request: HttpRequest,
+    handler: Handler,
+    query: &Map<String, Value>,
+) -> HttpResponse {
+    let query = match EventsQuery::deserialize(&Value::Object(query.clone())) {
+        Ok(query) => query,
+        Err(err) => return api_error(deserialize_error(err)),
+    };
+    let Some(key) = request.headers().get(header::SEC_WEBSOCKET_KEY) else {
+        return api_error(ErrorKind::InvalidArgument.error("Expected a WebSocket upgrade request"));
+    };
+    let accept_key = derive_accept_key(key.as_bytes());
+
+    if let Some(id) = &query.gpu_id {
+        if let Err(err) = handler.get_gpu_stats(id).await {
+            return api_error(err);
+        }
+    }
+
+    let period = Duration::from_millis(
+        query
+            .interval_ms
+            .unwrap_or(DEFAULT_EVENTS_INTERVAL_MS)
+            .max(MIN_EVENTS_INTERVAL_MS),
+    );
+    tokio::task::spawn_local(async move {
+        match hyper::upgrade::on(request).await {
+            Ok(upgraded) => {
+                let socket =
+                    WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None)
+                        .await;
+                if let Err(err) = stream_events(socket, &handler, query.gpu_id, period).await {
+                    debug!("event stream closed: {err}");
+                }
+            }
+            Err(err) => debug!("could not upgrade connection to a WebSocket: {err}"),
+        }
+    });
+
+    let mut response = empty(StatusCode::SWITCHING_PROTOCOLS);
+    let headers = response.headers_mut();
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+    headers.insert(
+        header::SEC_WEBSOCKET_ACCEPT,
+        HeaderValue::from_str(&accept_key).expect("Accept key is always a valid header value"),
+    );
+    response
+}
+
+async fn stream_events(
+    socket: WebSocketStream<TokioIo<hyper::upgrade::Upgraded>>,
+    handler: &Handler,
+    gpu_id: Option<String>,
+    period: Duration,
+) -> anyhow::Result<()> {
+    let (mut sink, mut incoming) = socket.split();
+    let mut current_profile = None;
+
+    let mut interval = interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        select! {
+            _ = interval.tick() => {
+                let profile = handler.config.read().await.current_profile.clone();
+                if current_profile.as_ref() != Some(&profile) {
+                    let event = Event::ProfileChanged {
+                        profile: profile.as_deref().map(str::to_owned),
+                    };
+                    sink.send(event_message(&event)).await?;
+                    current_profile = Some(profile);
+                }
+
+                for device in handler.list_devices().await {
+                    if gpu_id.as_ref().is_some_and(|gpu_id| *gpu_id != device.id) {
+                        continue;
+                    }
+                    match handler.get_gpu_stats(&device.id).await {
+                        Ok(stats) => {
+                            let event = Event::DeviceStats {
+                                id: device.id,
+                                stats,
+                            };
+                            sink.send(event_message(&event)).await?;
+                        }
+                        Err(err) => debug!("could not get stats of GPU {} for events: {err:#}", device.id),
+                    }
+                }
+            }
+            // Incoming messages are not used, but have to be read for pings and close frames to be handled
+            message = incoming.next() => match message {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => (),
+                Some(Err(err)) => return Err(err.into()),
+            },
+        }
+    }
+}
+
+fn event_message(event: &Event) -> Message {
+    Message::text(serde_json::to_string(event).expect("Event is always serializable"))
+}
+
+/// Errors have the same body as on the socket, with a status code based on the kind of the error
+fn api_error(error: anyhow::Error) -> HttpResponse {
+    let status = match ErrorKind::of(&error) {
+        ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        ErrorKind::InvalidArgument => StatusCode::BAD_REQUEST,
+        ErrorKind::Unsupported => StatusCode::NOT_IMPLEMENTED,
+        ErrorKind::PendingConfirmation => StatusCode::CONFLICT,
+        ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        ErrorKind::AuthenticationRequired => StatusCode::UNAUTHORIZED,
+        ErrorKind::HardwareWriteFailed | ErrorKind::Other => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    match error_response(None, error) {
+        Ok(body) => json(status, body),
+        Err(err) => {
+            error!("could not serialize error response: {err:#}");
+            empty(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn json(status: StatusCode, body: Vec<u8>) -> HttpResponse {
+    let mut response = hyper::Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(JSON_CONTENT_TYPE),
+    );
+    response
+}
+
+fn empty(status: StatusCode) -> HttpResponse {
+    let mut response = hyper::Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_route, is_json_content_type, is_same_origin, query_args, ROUTES, UNIT_COMMANDS,
+    };
+    use hyper::StatusCode;
+    use lact_schema::request::COMMANDS;
+    use serde_json::json;
+
+    #[test]
+    fn routes() {
+        for (_, _, command) in ROUTES {
+            assert!(COMMANDS.contains(command), "unknown command {command}");
+        }
+
+        let (command, args) = find_route("GET", "/devices/1002:687F-0000:0b:00.0/stats").unwrap();
+        assert_eq!(command, "device_stats");
+        assert_eq!(json!(args), json!({"id": "1002:687F-0000:0b:00.0"}));
+
+        let (command, args) = find_route("PUT", "/profiles/My%20profile/rule/").unwrap();
+        assert_eq!(command, "set_profile_rule");
+        assert_eq!(json!(args), json!({"name": "My profile"}));
+
+        let (command, _) = find_route("PUT", "/profiles/current").unwrap();
+        assert_eq!(command, "set_profile");
+        let (command, _) = find_route("POST", "/profiles").unwrap();
+        assert_eq!(command, "create_profile");
+        assert!(UNIT_COMMANDS.contains(&find_route("GET", "/devices").unwrap().0));
+
+        assert_eq!(
+            find_route("POST", "/devices/123/stats").unwrap_err(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            find_route("GET", "/devices/123/unknown").unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn query_values() {
+        let args = query_args(Some(
+            "since=1700000000000&include_state=true&gpu_id=1002%3A687F",
+        ));
+        assert_eq!(
+            json!(args),
+            json!({"since": 1_700_000_000_000_u64, "include_state": true, "gpu_id": "1002:687F"})
+        );
+        assert!(query_args(None).is_empty());
+    }
+
+    #[test]
+    fn cross_site_requests() {
+        let host = Some("127.0.0.1:12855");
+        assert!(is_same_origin(None, host));
+        assert!(is_same_origin(Some("http://127.0.0.1:12855"), host));
+        assert!(!is_same_origin(Some("http://127.0.0.1:8080"), host));
+        assert!(!is_same_origin(Some("https://example.com"), host));
+        assert!(!is_same_origin(Some("null"), host));
+        assert!(!is_same_origin(Some("http://127.0.0.1:12855"), None));
+
+        assert!(is_json_content_type(Some("application/json")));
+        assert!(is_json_content_type(Some(
+            "Application/JSON; charset=utf-8"
+        )));
+        assert!(!is_json_content_type(Some("text/plain")));
+        assert!(!is_json_content_type(Some(
+            "application/x-www-form-urlencoded"
+        )));
+        assert!(!is_json_content_type(None));
+    }
+}
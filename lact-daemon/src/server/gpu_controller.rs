@@ -18,10 +18,18 @@ use crate::{
 use amdgpu_sysfs::gpu_handle::power_profile_mode::PowerProfileModesTable;
 use anyhow::Context;
 use futures::{future::LocalBoxFuture, FutureExt};
-use lact_schema::{ClocksInfo, DeviceInfo, DeviceStats, GpuPciInfo, PciInfo, PowerStates};
+use lact_schema::{
+    ClocksInfo, ConfigWrite, DeviceInfo, DeviceStats, GpuPciInfo, PciInfo, PowerStates,
+};
 use libdrm_amdgpu_sys::LibDrmAmdgpu;
 use nvml_wrapper::Nvml;
-use std::{cell::LazyCell, collections::HashMap, fs, path::PathBuf, rc::Rc};
+use std::{
+    cell::{LazyCell, RefCell},
+    collections::HashMap,
+    fmt, fs,
+    path::PathBuf,
+    rc::Rc,
+};
 use tokio::{sync::Notify, task::JoinHandle};
 use tracing::{error, warn};
 
@@ -33,11 +41,36 @@ pub trait GpuController {
 
     fn get_info(&self) -> LocalBoxFuture<'_, DeviceInfo>;
 
-    fn apply_config<'a>(
+    /// Applies the config, making all hardware writes through `writer`
+    fn apply_config_with<'a>(
         &'a self,
         config: &'a config::Gpu,
+        writer: &'a ConfigWriter,
     ) -> LocalBoxFuture<'a, anyhow::Result<()>>;
 
+    fn apply_config<'a>(
+        &'a self,
+        config: &'a config::Gpu,
+    ) -> LocalBoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.apply_config_with(config, &ConfigWriter::hardware())
+                .await
+        })
+    }
+
+    /// Lists the writes that [`GpuController::apply_config`] would make, by applying the config with a dry run writer.
+    /// Returns an error in the same cases where applying the config would fail.
+    fn plan_config<'a>(
+        &'a self,
+        config: &'a config::Gpu,
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<ConfigWrite>>> {
+        Box::pin(async move {
+            let writer = ConfigWriter::dry_run();
+            self.apply_config_with(config, &writer).await?;
+            Ok(writer.into_writes())
+        })
+    }
+
     fn get_stats(&self, gpu_config: Option<&config::Gpu>) -> DeviceStats;
 
     fn get_clocks_info(&self, gpu_config: Option<&config::Gpu>) -> anyhow::Result<ClocksInfo>;
@@ -83,6 +116,73 @@ impl CommonControllerInfo {
     }
 }
 
+/// Makes the hardware writes when applying a config.
+/// In a dry run, the writes are only recorded, which is used to validate a config without changing anything.
+pub struct ConfigWriter {
+    /// `None` when writing to the hardware
+    dry_run: Option<RefCell<Vec<ConfigWrite>>>,
+}
+
+impl ConfigWriter {
+    pub fn hardware() -> Self {
+        Self { dry_run: None }
+    }
+
+    pub fn dry_run() -> Self {
+        Self {
+            dry_run: Some(RefCell::default()),
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.is_some()
+    }
+
+    /// Calls `write`, which writes `value` to `target` (a sysfs file or a driver function).
+    /// In a dry run, the write is recorded instead and `None` is returned.
+    pub fn write<T, E>(
+        &self,
+        target: impl fmt::Display,
+        value: impl fmt::Display,
+        write: impl FnOnce() -> Result<T, E>,
+    ) -> anyhow::Result<Option<T>>
+    where
+        anyhow::Error: From<E>,
+    {
+        self.write_all(target, [value], write)
+    }
+
+    /// Same as [`ConfigWriter::write`], for functions that write several values to the same target one after another
+    pub fn write_all<T, E, V: fmt::Display>(
+        &self,
+        target: impl fmt::Display,
+        values: impl IntoIterator<Item = V>,
+        write: impl FnOnce() -> Result<T, E>,
+    ) -> anyhow::Result<Option<T>>
+    where
+        anyhow::Error: From<E>,
+    {
+        match &self.dry_run {
+            Some(writes) => {
+                let target = target.to_string();
+                writes
+                    .borrow_mut()
+                    .extend(values.into_iter().map(|value| ConfigWrite {
+                        target: target.clone(),
+                        value: value.to_string(),
+                    }));
+                Ok(None)
+            }
+            None => Ok(Some(write()?)),
+        }
+    }
+
+    /// Writes that were recorded in a dry run
+    pub fn into_writes(self) -> Vec<ConfigWrite> {
+        self.dry_run.map(RefCell::into_inner).unwrap_or_default()
+    }
+}
+
 pub(crate) fn init_controller(
     path: PathBuf,
     pci_db: &pciid_parser::Database,
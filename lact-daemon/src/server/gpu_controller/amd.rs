@@ -1,5 +1,6 @@
 use super::{
-    fan_control::FanCurve, CommonControllerInfo, FanControlHandle, GpuController, VENDOR_AMD,
+    fan_control::FanCurve, CommonControllerInfo, ConfigWriter, FanControlHandle, GpuController,
+    VENDOR_AMD,
 };
 use crate::{
     config::{self, ClocksConfiguration, FanControlSettings},
@@ -19,8 +20,8 @@ use amdgpu_sysfs::{
 use anyhow::{anyhow, Context};
 use futures::{future::LocalBoxFuture, FutureExt};
 use lact_schema::{
-    ClocksInfo, ClockspeedStats, DeviceInfo, DeviceStats, DrmInfo, FanStats, IntelDrmInfo,
-    LinkInfo, PmfwInfo, PowerState, PowerStates, PowerStats, VoltageStats, VramStats,
+    ClocksInfo, ClockspeedStats, DeviceInfo, DeviceStats, DrmInfo, ErrorKind, FanStats,
+    IntelDrmInfo, LinkInfo, PmfwInfo, PowerState, PowerStates, PowerStats, VoltageStats, VramStats,
 };
 use libdrm_amdgpu_sys::AMDGPU::{GpuMetrics, ThrottleStatus, ThrottlerBit};
 use libdrm_amdgpu_sys::{LibDrmAmdgpu, AMDGPU::SENSOR_INFO::SENSOR_TYPE};
@@ -28,6 +29,7 @@ use std::{
     cell::RefCell,
     cmp,
     collections::{HashMap, HashSet},
+    fmt,
     path::PathBuf,
     rc::Rc,
     time::Duration,
@@ -94,66 +96,77 @@ impl AmdGpuController {
         self.handle.hw_monitors.first().map(f)
     }
 
+    /// Writes a value to a file in `gpu_od/fan_ctrl`, which has to be committed afterwards
+    fn write_fan_ctrl<V: fmt::Display>(
+        &self,
+        writer: &ConfigWriter,
+        file: &str,
+        values: impl IntoIterator<Item = V>,
+        write: impl FnOnce() -> Result<CommitHandle, Error>,
+    ) -> anyhow::Result<PendingCommit> {
+        let path = self.handle.get_path().join("gpu_od/fan_ctrl").join(file);
+        let handle = writer.write_all(path.display(), values, write)?;
+        Ok(PendingCommit { path, handle })
+    }
+
+    fn write_fan_curve(
+        &self,
+        writer: &ConfigWriter,
+        curve: &PmfwCurve,
+    ) -> anyhow::Result<PendingCommit> {
+        let points = curve
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, (temperature, speed))| format!("{i} {temperature} {speed}"));
+
+        self.write_fan_ctrl(writer, "fan_curve", points, || {
+            self.handle.set_fan_curve(curve)
+        })
+        .context("Could not set fan curve")
+    }
+
     async fn set_static_fan_control(
         &self,
+        writer: &ConfigWriter,
         static_speed: f64,
-    ) -> anyhow::Result<Option<CommitHandle>> {
+    ) -> anyhow::Result<Option<PendingCommit>> {
         // Stop existing task to set static speed
-        self.stop_fan_control(false).await?;
+        self.stop_fan_control(writer, false).await?;
 
         // Use PMFW curve functionality for static speed when it is available
         if let Ok(current_curve) = self.handle.get_fan_curve() {
             if let Ok(true) = self.handle.get_fan_zero_rpm_enable() {
-                if let Err(err) = self.handle.set_fan_zero_rpm_enable(false) {
+                if let Err(err) = self.write_fan_ctrl(writer, "fan_zero_rpm_enable", [0], || {
+                    self.handle.set_fan_zero_rpm_enable(false)
+                }) {
                     error!("could not disable zero RPM mode for static fan control: {err}");
                 }
             }
 
-            let allowed_ranges = current_curve.allowed_ranges.clone().ok_or_else(|| {
-                anyhow!("The GPU does not allow setting custom fan values (is overdrive enabled?)")
-            })?;
-            let min_temperature = allowed_ranges.temperature_range.start();
-            let max_temperature = allowed_ranges.temperature_range.end();
-
-            #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
-            let custom_pwm = (f64::from(*allowed_ranges.speed_range.end()) * static_speed) as u8;
-            let static_pwm = cmp::max(*allowed_ranges.speed_range.start(), custom_pwm);
-
-            let mut points = vec![(*min_temperature, static_pwm)];
-            for _ in 1..current_curve.points.len() {
-                points.push((*max_temperature, static_pwm));
-            }
-
-            let new_curve = PmfwCurve {
-                points: points.into_boxed_slice(),
-                allowed_ranges: Some(allowed_ranges),
-            };
+            let new_curve = static_pmfw_curve(&current_curve, static_speed)?;
 
             debug!("setting static curve {new_curve:?}");
 
-            let commit_handle = self
-                .handle
-                .set_fan_curve(&new_curve)
-                .context("Could not set fan curve")?;
-
-            Ok(Some(commit_handle))
+            self.write_fan_curve(writer, &new_curve).map(Some)
         } else {
-            let hw_mon = self
-                .handle
-                .hw_monitors
-                .first()
-                .cloned()
-                .context("This GPU has no monitor")?;
-
-            hw_mon
-                .set_fan_control_method(FanControlMethod::Manual)
+            let hw_mon = self.first_hw_mon()?;
+
+            writer
+                .write(
+                    hw_mon.get_path().join("pwm1_enable").display(),
+                    FanControlMethod::Manual as u32,
+                    || hw_mon.set_fan_control_method(FanControlMethod::Manual),
+                )
                 .context("Could not set fan control method")?;
 
             #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
             let static_pwm = (f64::from(u8::MAX) * static_speed) as u8;
 
-            hw_mon
-                .set_fan_pwm(static_pwm)
+            writer
+                .write(hw_mon.get_path().join("pwm1").display(), static_pwm, || {
+                    hw_mon.set_fan_pwm(static_pwm)
+                })
                 .context("could not set fan speed")?;
 
             debug!("set fan speed to {}", static_speed);
@@ -164,9 +177,10 @@ impl AmdGpuController {
 
     async fn start_curve_fan_control(
         &self,
+        writer: &ConfigWriter,
         curve: FanCurve,
         settings: FanControlSettings,
-    ) -> anyhow::Result<Option<CommitHandle>> {
+    ) -> anyhow::Result<Option<PendingCommit>> {
         // Use the PMFW curve functionality when it is available
         // Otherwise, fall back to manual fan control via a task
         if let Ok(current_curve) = self.handle.get_fan_curve() {
@@ -176,25 +190,22 @@ impl AmdGpuController {
 
             debug!("setting pmfw curve {new_curve:?}");
 
-            let commit_handle = self
-                .handle
-                .set_fan_curve(&new_curve)
-                .context("Could not set fan curve")?;
-
-            Ok(Some(commit_handle))
+            self.write_fan_curve(writer, &new_curve).map(Some)
         } else {
-            self.start_curve_fan_control_task(curve, settings).await?;
+            self.start_curve_fan_control_task(writer, curve, settings)
+                .await?;
             Ok(None)
         }
     }
 
     async fn start_curve_fan_control_task(
         &self,
+        writer: &ConfigWriter,
         curve: FanCurve,
         settings: FanControlSettings,
     ) -> anyhow::Result<()> {
         // Stop existing task to re-apply new curve
-        self.stop_fan_control(false).await?;
+        self.stop_fan_control(writer, false).await?;
 
         let hw_mon = self
             .handle
@@ -205,13 +216,13 @@ impl AmdGpuController {
 
         let temps = hw_mon.get_temps();
         match temps.len() {
-            0 => return Err(anyhow!("GPU has no temperature reporting")),
+            0 => return Err(ErrorKind::Unsupported.error("GPU has no temperature reporting")),
             1 => {
                 warn!("GPU has only one temperature sensor, 'temperature_key' setting will be ignored");
             }
             _ => {
                 if !temps.contains_key(&settings.temperature_key) {
-                    return Err(anyhow!(
+                    return Err(ErrorKind::InvalidArgument.error(format!(
                         "Sensor with name {} not found, available sensors: {}",
                         settings.temperature_key,
                         temps
@@ -219,15 +230,24 @@ impl AmdGpuController {
                             .map(String::as_str)
                             .collect::<Vec<&str>>()
                             .join(",")
-                    ));
+                    )));
                 }
             }
         }
 
-        hw_mon
-            .set_fan_control_method(FanControlMethod::Manual)
+        writer
+            .write(
+                hw_mon.get_path().join("pwm1_enable").display(),
+                FanControlMethod::Manual as u32,
+                || hw_mon.set_fan_control_method(FanControlMethod::Manual),
+            )
             .context("Could not set fan control method")?;
 
+        // The fan speed is only written by the task
+        if writer.is_dry_run() {
+            return Ok(());
+        }
+
         let mut notify_guard = self
             .fan_control_handle
             .try_borrow_mut()
@@ -351,29 +371,42 @@ impl AmdGpuController {
         Ok(())
     }
 
-    async fn stop_fan_control(&self, reset_mode: bool) -> anyhow::Result<()> {
-        let maybe_notify = self
-            .fan_control_handle
-            .try_borrow_mut()
-            .map_err(|err| anyhow!("Lock error: {err}"))?
-            .take();
-        if let Some((notify, handle)) = maybe_notify {
-            notify.notify_one();
-            handle.await?;
+    async fn stop_fan_control(
+        &self,
+        writer: &ConfigWriter,
+        reset_mode: bool,
+    ) -> anyhow::Result<()> {
+        if !writer.is_dry_run() {
+            let maybe_notify = self
+                .fan_control_handle
+                .try_borrow_mut()
+                .map_err(|err| anyhow!("Lock error: {err}"))?
+                .take();
+            if let Some((notify, handle)) = maybe_notify {
+                notify.notify_one();
+                handle.await?;
+            }
         }
 
         if reset_mode {
             if self.handle.get_fan_curve().is_ok() {
-                if let Err(err) = self.handle.reset_fan_curve() {
+                let path = self.handle.get_path().join("gpu_od/fan_ctrl/fan_curve");
+                if let Err(err) =
+                    writer.write(path.display(), "r", || self.handle.reset_fan_curve())
+                {
                     warn!("could not reset fan curve: {err:#}");
                 }
             }
 
-            if let Some(hw_mon) = self.handle.hw_monitors.first().cloned() {
+            if let Some(hw_mon) = self.handle.hw_monitors.first() {
                 if let Ok(current_control) = hw_mon.get_fan_control_method() {
                     if !matches!(current_control, FanControlMethod::Auto) {
-                        hw_mon
-                            .set_fan_control_method(FanControlMethod::Auto)
+                        writer
+                            .write(
+                                hw_mon.get_path().join("pwm1_enable").display(),
+                                FanControlMethod::Auto as u32,
+                                || hw_mon.set_fan_control_method(FanControlMethod::Auto),
+                            )
                             .context("Could not set fan control back to automatic")?;
                     }
                 }
@@ -422,6 +455,48 @@ impl AmdGpuController {
             .collect()
     }
 
+    /// Commands that are written to `pp_power_profile_mode` to set the heuristics of the custom profile
+    fn custom_heuristics_commands(
+        &self,
+        components: &[Vec<Option<i32>>],
+    ) -> anyhow::Result<Vec<String>> {
+        let table = self.handle.get_power_profile_modes()?;
+        let (index, custom_profile) = table
+            .modes
+            .iter()
+            .find(|(_, profile)| profile.is_custom())
+            .context("Could not find a custom power profile")?;
+
+        if custom_profile.components.len() != components.len() {
+            return Err(ErrorKind::InvalidArgument.error(format!(
+                "Expected {} power profile components, got {}",
+                custom_profile.components.len(),
+                components.len()
+            )));
+        }
+
+        let commands = components
+            .iter()
+            .enumerate()
+            .map(|(component_index, heuristics)| {
+                let prefix = if components.len() == 1 {
+                    index.to_string()
+                } else {
+                    format!("{index} {component_index}")
+                };
+                let values = heuristics.iter().map(|heuristic| match heuristic {
+                    Some(value) => value.to_string(),
+                    None => "-".to_owned(),
+                });
+                std::iter::once(prefix)
+                    .chain(values)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        Ok(commands)
+    }
+
     fn first_hw_mon(&self) -> anyhow::Result<&HwMon> {
         self.handle
             .hw_monitors
@@ -754,11 +829,20 @@ impl GpuController for AmdGpuController {
     }
 
     #[allow(clippy::too_many_lines)]
-    fn apply_config<'a>(
+    fn apply_config_with<'a>(
         &'a self,
         config: &'a config::Gpu,
+        writer: &'a ConfigWriter,
     ) -> LocalBoxFuture<'a, anyhow::Result<()>> {
         Box::pin(async {
+            let device_path = self.handle.get_path();
+            let performance_level_path = device_path.join("power_dpm_force_performance_level");
+            let set_performance_level = |level: PerformanceLevel| {
+                writer.write(performance_level_path.display(), level, || {
+                    self.handle.set_power_force_performance_level(level)
+                })
+            };
+
             if let Some(cap) = config.power_cap {
                 let hw_mon = self.first_hw_mon()?;
 
@@ -773,28 +857,26 @@ impl GpuController for AmdGpuController {
                 let mut original_performance_level = None;
                 if current_usage > cap {
                     if let Ok(performance_level) = self.handle.get_power_force_performance_level() {
-                        if self
-                            .handle
-                            .set_power_force_performance_level(PerformanceLevel::Low)
-                            .is_ok()
-                        {
-                            debug!(
-                            "waiting for the GPU to clock down before applying a new power limit"
-                        );
-
-                            match timeout(
-                                Duration::from_secs(GPU_CLOCKDOWN_TIMEOUT_SECS),
-                                wait_until_lowest_clock_level(&self.handle),
-                            )
-                            .await
-                            {
-                                Ok(()) => {
-                                    debug!("GPU clocked down successfully");
-                                }
-                                Err(_) => {
-                                    warn!(
+                        if set_performance_level(PerformanceLevel::Low).is_ok() {
+                            if !writer.is_dry_run() {
+                                debug!(
+                                "waiting for the GPU to clock down before applying a new power limit"
+                            );
+
+                                match timeout(
+                                    Duration::from_secs(GPU_CLOCKDOWN_TIMEOUT_SECS),
+                                    wait_until_lowest_clock_level(&self.handle),
+                                )
+                                .await
+                                {
+                                    Ok(()) => {
+                                        debug!("GPU clocked down successfully");
+                                    }
+                                    Err(_) => {
+                                        warn!(
                                         "GPU did not clock down after {GPU_CLOCKDOWN_TIMEOUT_SECS}"
                                     );
+                                    }
                                 }
                             }
 
@@ -806,15 +888,18 @@ impl GpuController for AmdGpuController {
                 // Due to possible driver bug, RX 7900 XTX really doesn't like when we set the same value again.
                 // But, also in general we want to avoid setting same value twice
                 if Ok(cap) != hw_mon.get_power_cap() {
-                    hw_mon
-                        .set_power_cap(cap)
+                    writer
+                        .write(
+                            hw_mon.get_path().join("power1_cap").display(),
+                            power_cap_microwatts(cap),
+                            || hw_mon.set_power_cap(cap),
+                        )
                         .with_context(|| format!("Failed to set power cap: {cap}"))?;
                 }
 
                 // Reapply old power level
                 if let Some(level) = original_performance_level {
-                    self.handle
-                        .set_power_force_performance_level(level)
+                    set_performance_level(level)
                         .context("Could not reapply original performance level")?;
                 }
             } else if let Ok(hw_mon) = self.first_hw_mon() {
@@ -822,9 +907,15 @@ impl GpuController for AmdGpuController {
                     // Due to possible driver bug, RX 7900 XTX really doesn't like when we set the same value again.
                     // But, also in general we want to avoid setting same value twice
                     if Ok(default_cap) != hw_mon.get_power_cap() {
-                        hw_mon.set_power_cap(default_cap).with_context(|| {
-                            format!("Failed to set power cap to default cap: {default_cap}")
-                        })?;
+                        writer
+                            .write(
+                                hw_mon.get_path().join("power1_cap").display(),
+                                power_cap_microwatts(default_cap),
+                                || hw_mon.set_power_cap(default_cap),
+                            )
+                            .with_context(|| {
+                                format!("Failed to set power cap to default cap: {default_cap}")
+                            })?;
                     }
                 }
             }
@@ -832,47 +923,53 @@ impl GpuController for AmdGpuController {
             let mut commit_handles = Vec::new();
 
             // Reset the clocks table in case the settings get reverted back to not having a clocks value configured
-            self.handle.reset_clocks_table().ok();
+            let clocks_table_path = device_path.join("pp_od_clk_voltage");
+            writer
+                .write(clocks_table_path.display(), "r", || {
+                    self.handle.reset_clocks_table()
+                })
+                .ok();
 
             if self.is_steam_deck() {
                 // Van Gogh/Sephiroth only allow clock settings to be used with manual performance mode
-                self.handle
-                    .set_power_force_performance_level(PerformanceLevel::Manual)
-                    .ok();
+                set_performance_level(PerformanceLevel::Manual).ok();
             } else {
                 // Reset performance level to work around some GPU quirks (found to be an issue on RDNA2)
-                self.handle
-                    .set_power_force_performance_level(PerformanceLevel::Auto)
-                    .ok();
+                set_performance_level(PerformanceLevel::Auto).ok();
             }
 
             if config.is_core_clocks_used() {
                 match self.handle.get_clocks_table() {
-                    Ok(original_table) => {
+                    Ok(mut original_table) => {
+                        // The table was not actually reset in a dry run,
+                        // so compare against one without any user-set values instead
+                        if writer.is_dry_run() {
+                            if let ClocksTableGen::Vega20(table) = &mut original_table {
+                                table.clear();
+                            }
+                        }
+
                         let mut table = original_table.clone();
                         config
                             .clocks_configuration
                             .apply_to_table(&mut table)
                             .context("Failed to apply clocks configuration to table")?;
 
-                        debug!(
-                            "writing clocks commands: {:#?}",
-                            table
-                                .get_commands(&original_table)
-                                .context("Failed to get table commands")?
-                        );
+                        let commands = table
+                            .get_commands(&original_table)
+                            .context("Failed to get table commands")?;
+                        debug!("writing clocks commands: {commands:#?}");
 
-                        let handle = self
-                            .handle
-                            .set_clocks_table(&table)
+                        let handle = writer
+                            .write_all(clocks_table_path.display(), &commands, || {
+                                self.handle.set_clocks_table(&table)
+                            })
                             .context("Could not write clocks table")
-                            .with_context(|| {
-                                format!(
-                                    "Clocks table commands: {:?}",
-                                    table.get_commands(&original_table)
-                                )
-                            })?;
-                        commit_handles.push(handle);
+                            .with_context(|| format!("Clocks table commands: {commands:?}"))?;
+                        commit_handles.push(PendingCommit {
+                            path: clocks_table_path,
+                            handle,
+                        });
                     }
                     Err(err) => {
                         error!("custom clock settings are present but will be ignored, could not get clocks table: {err}");
@@ -881,28 +978,34 @@ impl GpuController for AmdGpuController {
             }
 
             if let Some(level) = config.performance_level {
-                self.handle
-                    .set_power_force_performance_level(level)
-                    .context("Failed to set power performance level")?;
+                set_performance_level(level).context("Failed to set power performance level")?;
             }
             // Else is not needed, it was previously reset to auto already
 
             if let Some(mode_index) = config.power_profile_mode_index {
                 if config.performance_level != Some(PerformanceLevel::Manual) {
-                    return Err(anyhow!(
-                        "Performance level has to be set to `manual` to use power profile modes"
+                    return Err(ErrorKind::InvalidArgument.error(
+                        "Performance level has to be set to `manual` to use power profile modes",
                     ));
                 }
 
+                let power_profile_mode_path = device_path.join("pp_power_profile_mode");
                 if config.custom_power_profile_mode_hueristics.is_empty() {
-                    self.handle
-                        .set_active_power_profile_mode(mode_index)
+                    writer
+                        .write(power_profile_mode_path.display(), mode_index, || {
+                            self.handle.set_active_power_profile_mode(mode_index)
+                        })
                         .context("Failed to set active power profile mode")?;
                 } else {
-                    self.handle
-                        .set_custom_power_profile_mode_heuristics(
-                            &config.custom_power_profile_mode_hueristics,
-                        )
+                    let heuristics = &config.custom_power_profile_mode_hueristics;
+                    let commands = self
+                        .custom_heuristics_commands(heuristics)
+                        .context("Failed to set custom power profile mode heuristics")?;
+                    writer
+                        .write_all(power_profile_mode_path.display(), commands, || {
+                            self.handle
+                                .set_custom_power_profile_mode_heuristics(heuristics)
+                        })
                         .context("Failed to set custom power profile mode heuristics")?;
                 }
             }
@@ -912,7 +1015,7 @@ impl GpuController for AmdGpuController {
                     match settings.mode {
                         lact_schema::FanControlMode::Static => {
                             if let Some(commit_handle) = self
-                                .set_static_fan_control(settings.static_speed)
+                                .set_static_fan_control(writer, settings.static_speed)
                                 .await
                                 .context("Failed to set static fan control")?
                             {
@@ -921,11 +1024,17 @@ impl GpuController for AmdGpuController {
                         }
                         lact_schema::FanControlMode::Curve => {
                             if settings.curve.0.is_empty() {
-                                return Err(anyhow!("Cannot use empty fan curve"));
+                                return Err(
+                                    ErrorKind::InvalidArgument.error("Cannot use empty fan curve")
+                                );
                             }
 
                             if let Some(commit_handle) = self
-                                .start_curve_fan_control(settings.curve.clone(), settings.clone())
+                                .start_curve_fan_control(
+                                    writer,
+                                    settings.curve.clone(),
+                                    settings.clone(),
+                                )
                                 .await
                                 .context("Failed to set curve fan control")?
                             {
@@ -934,9 +1043,8 @@ impl GpuController for AmdGpuController {
                         }
                     }
                 } else {
-                    return Err(anyhow!(
-                        "Trying to enable fan control with no settings provided"
-                    ));
+                    return Err(ErrorKind::InvalidArgument
+                        .error("Trying to enable fan control with no settings provided"));
                 }
             } else {
                 let pmfw = &config.pmfw_options;
@@ -949,8 +1057,12 @@ impl GpuController for AmdGpuController {
                         != acoustic_limit
                     {
                         let commit_handle = self
-                            .handle
-                            .set_fan_acoustic_limit(acoustic_limit)
+                            .write_fan_ctrl(
+                                writer,
+                                "acoustic_limit_rpm_threshold",
+                                [acoustic_limit],
+                                || self.handle.set_fan_acoustic_limit(acoustic_limit),
+                            )
                             .context("Could not set acoustic limit")?;
                         commit_handles.push(commit_handle);
                     }
@@ -964,8 +1076,12 @@ impl GpuController for AmdGpuController {
                         != acoustic_target
                     {
                         let commit_handle = self
-                            .handle
-                            .set_fan_acoustic_target(acoustic_target)
+                            .write_fan_ctrl(
+                                writer,
+                                "acoustic_target_rpm_threshold",
+                                [acoustic_target],
+                                || self.handle.set_fan_acoustic_target(acoustic_target),
+                            )
                             .context("Could not set acoustic target")?;
                         commit_handles.push(commit_handle);
                     }
@@ -979,8 +1095,12 @@ impl GpuController for AmdGpuController {
                         != target_temperature
                     {
                         let commit_handle = self
-                            .handle
-                            .set_fan_target_temperature(target_temperature)
+                            .write_fan_ctrl(
+                                writer,
+                                "fan_target_temperature",
+                                [target_temperature],
+                                || self.handle.set_fan_target_temperature(target_temperature),
+                            )
                             .context("Could not set target temperature")?;
                         commit_handles.push(commit_handle);
                     }
@@ -994,14 +1114,15 @@ impl GpuController for AmdGpuController {
                         != minimum_pwm
                     {
                         let commit_handle = self
-                            .handle
-                            .set_fan_minimum_pwm(minimum_pwm)
+                            .write_fan_ctrl(writer, "fan_minimum_pwm", [minimum_pwm], || {
+                                self.handle.set_fan_minimum_pwm(minimum_pwm)
+                            })
                             .context("Could not set minimum pwm")?;
                         commit_handles.push(commit_handle);
                     }
                 }
 
-                self.stop_fan_control(true)
+                self.stop_fan_control(writer, true)
                     .await
                     .context("Failed to stop fan control")?;
             }
@@ -1012,8 +1133,12 @@ impl GpuController for AmdGpuController {
                     Ok(current_zero_rpm) => {
                         if current_zero_rpm != zero_rpm {
                             let commit_handle = self
-                                .handle
-                                .set_fan_zero_rpm_enable(zero_rpm)
+                                .write_fan_ctrl(
+                                    writer,
+                                    "fan_zero_rpm_enable",
+                                    [u32::from(zero_rpm)],
+                                    || self.handle.set_fan_zero_rpm_enable(zero_rpm),
+                                )
                                 .context("Could not set zero RPM mode")?;
                             commit_handles.push(commit_handle);
                         }
@@ -1029,8 +1154,15 @@ impl GpuController for AmdGpuController {
                     Ok(current_threshold) => {
                         if current_threshold.current != zero_rpm_threshold {
                             let commit_handle = self
-                                .handle
-                                .set_fan_zero_rpm_stop_temperature(zero_rpm_threshold)
+                                .write_fan_ctrl(
+                                    writer,
+                                    "fan_zero_rpm_stop_temperature",
+                                    [zero_rpm_threshold],
+                                    || {
+                                        self.handle
+                                            .set_fan_zero_rpm_stop_temperature(zero_rpm_threshold)
+                                    },
+                                )
                                 .context("Could not set zero RPM temperature")?;
                             commit_handles.push(commit_handle);
                         }
@@ -1042,18 +1174,25 @@ impl GpuController for AmdGpuController {
             }
 
             for handle in commit_handles {
-                handle.commit()?;
+                handle.commit(writer)?;
             }
 
             for (kind, states) in &config.power_states {
                 if config.performance_level != Some(PerformanceLevel::Manual) {
-                    return Err(anyhow!(
-                        "Performance level has to be set to `manual` to configure power states"
+                    return Err(ErrorKind::InvalidArgument.error(
+                        "Performance level has to be set to `manual` to configure power states",
                     ));
                 }
 
-                self.handle
-                    .set_enabled_power_levels(*kind, states)
+                let levels = states
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writer
+                    .write(device_path.join(kind.filename()).display(), levels, || {
+                        self.handle.set_enabled_power_levels(*kind, states)
+                    })
                     .with_context(|| format!("Could not set {kind:?} power states"))?;
             }
 
@@ -1084,6 +1223,46 @@ impl GpuController for AmdGpuController {
     }
 }
 
+/// PMFW fan curve with the same speed at all temperatures
+fn static_pmfw_curve(current_curve: &PmfwCurve, static_speed: f64) -> anyhow::Result<PmfwCurve> {
+    let allowed_ranges = current_curve.allowed_ranges.clone().ok_or_else(|| {
+        ErrorKind::Unsupported
+            .error("The GPU does not allow setting custom fan values (is overdrive enabled?)")
+    })?;
+    let min_temperature = allowed_ranges.temperature_range.start();
+    let max_temperature = allowed_ranges.temperature_range.end();
+
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    let custom_pwm = (f64::from(*allowed_ranges.speed_range.end()) * static_speed) as u8;
+    let static_pwm = cmp::max(*allowed_ranges.speed_range.start(), custom_pwm);
+
+    let mut points = vec![(*min_temperature, static_pwm)];
+    for _ in 1..current_curve.points.len() {
+        points.push((*max_temperature, static_pwm));
+    }
+
+    Ok(PmfwCurve {
+        points: points.into_boxed_slice(),
+        allowed_ranges: Some(allowed_ranges),
+    })
+}
+
+/// A written value which takes effect once it's committed
+struct PendingCommit {
+    path: PathBuf,
+    /// `None` in a dry run
+    handle: Option<CommitHandle>,
+}
+
+impl PendingCommit {
+    fn commit(self, writer: &ConfigWriter) -> anyhow::Result<()> {
+        writer.write(self.path.display(), "c", || {
+            self.handle.map_or(Ok(()), CommitHandle::commit)
+        })?;
+        Ok(())
+    }
+}
+
 #[cfg(not(test))]
 fn get_drm_handle(handle: &GpuHandle, libdrm_amdgpu: &LibDrmAmdgpu) -> anyhow::Result<DrmHandle> {
     use std::os::unix::io::IntoRawFd;
@@ -1148,6 +1327,12 @@ impl ClocksConfiguration {
     }
 }
 
+/// Value written to `power1_cap`
+#[allow(clippy::cast_possible_truncation)]
+fn power_cap_microwatts(cap: f64) -> i64 {
+    (cap * 1_000_000.0).round() as i64
+}
+
 async fn wait_until_lowest_clock_level(handle: &GpuHandle) {
     loop {
         match handle.get_core_clock_levels() {
@@ -1,6 +1,5 @@
 use amdgpu_sysfs::{gpu_handle::fan_control::FanCurve as PmfwCurve, hw_mon::Temperature};
-use anyhow::{anyhow, bail, Context};
-use lact_schema::{default_fan_curve, FanCurveMap};
+use lact_schema::{default_fan_curve, ErrorKind, FanCurveMap};
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 
@@ -41,15 +40,15 @@ impl FanCurve {
 
     pub fn into_pmfw_curve(self, current_pmfw_curve: PmfwCurve) -> anyhow::Result<PmfwCurve> {
         if current_pmfw_curve.points.len() != self.0.len() {
-            return Err(anyhow!(
+            return Err(ErrorKind::InvalidArgument.error(format!(
                 "The GPU only supports {} curve points, given {}",
                 current_pmfw_curve.points.len(),
                 self.0.len()
-            ));
+            )));
         }
-        let allowed_ranges = current_pmfw_curve
-            .allowed_ranges
-            .context("The GPU does not allow fan curve modifications")?;
+        let allowed_ranges = current_pmfw_curve.allowed_ranges.ok_or_else(|| {
+            ErrorKind::Unsupported.error("The GPU does not allow fan curve modifications")
+        })?;
         let min_percent = *allowed_ranges.speed_range.start();
         let max_percent = *allowed_ranges.speed_range.end();
         let min_temp = *allowed_ranges.temperature_range.start();
@@ -62,11 +61,11 @@ impl FanCurve {
                 let custom_percent = (ratio * 100.0) as u8;
 
                 if !(min_temp..=max_temp).contains(&temp) {
-                    bail!("Temperature {temp}℃ is outside of the allowed range {min_temp}℃ to {max_temp}℃");
+                    return Err(ErrorKind::InvalidArgument.error(format!("Temperature {temp}℃ is outside of the allowed range {min_temp}℃ to {max_temp}℃")));
                 }
 
                 if !(min_percent..=max_percent).contains(&custom_percent) {
-                    bail!("Speed {custom_percent}% is outside of the allowed range {min_percent}% to {max_percent}%");
+                    return Err(ErrorKind::InvalidArgument.error(format!("Speed {custom_percent}% is outside of the allowed range {min_percent}% to {max_percent}%")));
                 }
 
                 Ok((temp, custom_percent))
@@ -84,7 +83,8 @@ impl FanCurve {
     pub fn validate(&self) -> anyhow::Result<()> {
         for percentage in self.0.values() {
             if !(0.0..=1.0).contains(percentage) {
-                return Err(anyhow!("Fan speed percentage must be between 0 and 1"));
+                return Err(ErrorKind::InvalidArgument
+                    .error("Fan speed percentage must be between 0 and 1"));
             }
         }
         Ok(())
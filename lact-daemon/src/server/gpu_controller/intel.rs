@@ -1,6 +1,6 @@
 mod drm;
 
-use super::{CommonControllerInfo, GpuController};
+use super::{CommonControllerInfo, ConfigWriter, GpuController};
 use crate::{
     bindings::intel::{
         drm_i915_gem_memory_class_I915_MEMORY_CLASS_DEVICE,
@@ -14,8 +14,8 @@ use anyhow::{anyhow, Context};
 use futures::future::LocalBoxFuture;
 use lact_schema::{
     ClocksInfo, ClocksTable, ClockspeedStats, DeviceInfo, DeviceStats, DrmInfo, DrmMemoryInfo,
-    FanStats, IntelClocksTable, IntelDrmInfo, LinkInfo, PowerState, PowerStates, PowerStats,
-    VoltageStats, VramStats,
+    ErrorKind, FanStats, IntelClocksTable, IntelDrmInfo, LinkInfo, PowerState, PowerStates,
+    PowerStats, VoltageStats, VramStats,
 };
 use std::{
     cell::Cell,
@@ -168,11 +168,19 @@ impl IntelGpuController {
         None
     }
 
-    fn write_file(&self, path: impl AsRef<Path>, contents: &str) -> anyhow::Result<()> {
+    fn write_file(
+        &self,
+        writer: &ConfigWriter,
+        path: impl AsRef<Path>,
+        contents: &str,
+    ) -> anyhow::Result<()> {
         let file_path = self.common.sysfs_path.join(path);
 
         if file_path.exists() {
-            fs::write(&file_path, contents)
+            writer
+                .write(file_path.display(), contents, || {
+                    fs::write(&file_path, contents)
+                })
                 .with_context(|| format!("Could not write to '{}'", file_path.display()))?;
             Ok(())
         } else {
@@ -211,12 +219,18 @@ impl IntelGpuController {
 
     fn write_hwmon_file(
         &self,
+        writer: &ConfigWriter,
         file_prefix: &str,
         file_suffix: &str,
         contents: &str,
     ) -> anyhow::Result<()> {
         debug!("writing value '{contents}' to '{file_prefix}*{file_suffix}'");
 
+        let path = self.hwmon_file_path(file_prefix, file_suffix)?;
+        self.write_file(writer, path, contents)
+    }
+
+    fn hwmon_file_path(&self, file_prefix: &str, file_suffix: &str) -> anyhow::Result<PathBuf> {
         if let Some(hwmon_path) = &self.hwmon_path {
             let mut files = Vec::with_capacity(1);
 
@@ -237,11 +251,10 @@ impl IntelGpuController {
             }
             files.sort_unstable();
 
-            if let Some(entry) = files.first() {
-                self.write_file(entry, contents)
-            } else {
-                Err(anyhow!("File not found"))
-            }
+            files
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("File not found"))
         } else {
             Err(anyhow!("No hwmon available"))
         }
@@ -359,9 +372,14 @@ impl IntelGpuController {
         self.freq_path(freq).and_then(|path| self.read_file(&path))
     }
 
-    fn write_freq(&self, freq: FrequencyType, value: i32) -> anyhow::Result<()> {
+    fn write_freq(
+        &self,
+        writer: &ConfigWriter,
+        freq: FrequencyType,
+        value: i32,
+    ) -> anyhow::Result<()> {
         let path = self.freq_path(freq).context("Frequency info not found")?;
-        self.write_file(path, &value.to_string())
+        self.write_file(writer, path, &value.to_string())
             .context("Could not write frequency")?;
         Ok(())
     }
@@ -565,24 +583,30 @@ impl GpuController for IntelGpuController {
     }
 
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-    fn apply_config<'a>(
+    fn apply_config_with<'a>(
         &'a self,
         config: &'a config::Gpu,
+        writer: &'a ConfigWriter,
     ) -> LocalBoxFuture<'a, anyhow::Result<()>> {
         Box::pin(async {
             if let Some(max_clock) = config.clocks_configuration.max_core_clock {
-                self.write_freq(FrequencyType::Max, max_clock)
+                self.write_freq(writer, FrequencyType::Max, max_clock)
                     .context("Could not set max clock")?;
             }
 
             if let Some(min_clock) = config.clocks_configuration.min_core_clock {
-                self.write_freq(FrequencyType::Min, min_clock)
+                self.write_freq(writer, FrequencyType::Min, min_clock)
                     .context("Could not set min clock")?;
             }
 
             if let Some(cap) = config.power_cap {
-                self.write_hwmon_file("power", "_max", &((cap * 1_000_000.0) as u64).to_string())
-                    .context("Could not set power cap")?;
+                self.write_hwmon_file(
+                    writer,
+                    "power",
+                    "_max",
+                    &((cap * 1_000_000.0) as u64).to_string(),
+                )
+                .context("Could not set power cap")?;
             }
 
             Ok(())
@@ -703,14 +727,16 @@ impl GpuController for IntelGpuController {
 
     #[allow(clippy::cast_possible_truncation)]
     fn reset_clocks(&self) -> anyhow::Result<()> {
+        let writer = ConfigWriter::hardware();
+
         if let Some(rp0) = self.read_freq(FrequencyType::Rp0) {
-            if let Err(err) = self.write_freq(FrequencyType::Max, rp0 as i32) {
+            if let Err(err) = self.write_freq(&writer, FrequencyType::Max, rp0 as i32) {
                 warn!("could not reset max clock: {err:#}");
             }
         }
 
         if let Some(rpn) = self.read_freq(FrequencyType::Rpn) {
-            if let Err(err) = self.write_freq(FrequencyType::Min, rpn as i32) {
+            if let Err(err) = self.write_freq(&writer, FrequencyType::Min, rpn as i32) {
                 warn!("could not reset min clock: {err:#}");
             }
         }
@@ -719,11 +745,11 @@ impl GpuController for IntelGpuController {
     }
 
     fn get_power_profile_modes(&self) -> anyhow::Result<PowerProfileModesTable> {
-        Err(anyhow!("Not supported"))
+        Err(ErrorKind::Unsupported.error("Not supported"))
     }
 
     fn vbios_dump(&self) -> anyhow::Result<Vec<u8>> {
-        Err(anyhow!("Not supported"))
+        Err(ErrorKind::Unsupported.error("Not supported"))
     }
 }
 
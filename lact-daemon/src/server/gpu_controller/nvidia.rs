@@ -5,16 +5,18 @@ use crate::{
     server::vulkan::get_vulkan_info,
 };
 
-use super::{fan_control::FanCurve, CommonControllerInfo, FanControlHandle, GpuController};
+use super::{
+    fan_control::FanCurve, CommonControllerInfo, ConfigWriter, FanControlHandle, GpuController,
+};
 use amdgpu_sysfs::{gpu_handle::power_profile_mode::PowerProfileModesTable, hw_mon::Temperature};
-use anyhow::{anyhow, bail, Context};
+use anyhow::{anyhow, Context};
 use driver::DriverHandle;
 use futures::{future::LocalBoxFuture, FutureExt};
 use indexmap::IndexMap;
 use lact_schema::{
     ClocksInfo, ClocksTable, ClockspeedStats, DeviceInfo, DeviceStats, DrmInfo, DrmMemoryInfo,
-    FanControlMode, FanStats, IntelDrmInfo, LinkInfo, NvidiaClockOffset, NvidiaClocksTable,
-    PmfwInfo, PowerState, PowerStates, PowerStats, VoltageStats, VramStats,
+    ErrorKind, FanControlMode, FanStats, IntelDrmInfo, LinkInfo, NvidiaClockOffset,
+    NvidiaClocksTable, PmfwInfo, PowerState, PowerStates, PowerStats, VoltageStats, VramStats,
 };
 use nvml_wrapper::{
     bitmasks::device::ThrottleReasons,
@@ -89,11 +91,12 @@ impl NvidiaGpuController {
 
     async fn start_curve_fan_control_task(
         &self,
+        writer: &ConfigWriter,
         curve: FanCurve,
         settings: FanControlSettings,
     ) -> anyhow::Result<()> {
         // Stop existing task to re-apply new curve
-        self.stop_fan_control().await?;
+        self.stop_fan_control(writer).await?;
 
         let device = self.device();
         device
@@ -102,7 +105,12 @@ impl NvidiaGpuController {
 
         let fan_count = device.num_fans().context("Could not read fan count")?;
         if fan_count == 0 {
-            return Err(anyhow!("Device has no fans"));
+            return Err(ErrorKind::Unsupported.error("Device has no fans"));
+        }
+
+        // The fan speed is only set by the task
+        if writer.is_dry_run() {
+            return Ok(());
         }
 
         let mut notify_guard = self
@@ -193,25 +201,31 @@ impl NvidiaGpuController {
         Ok(())
     }
 
-    async fn stop_fan_control(&self) -> anyhow::Result<()> {
+    async fn stop_fan_control(&self, writer: &ConfigWriter) -> anyhow::Result<()> {
         let mut fail_on_error = false;
 
-        let maybe_notify = self
-            .fan_control_handle
-            .try_borrow_mut()
-            .map_err(|err| anyhow!("Lock error: {err}"))?
-            .take();
-        if let Some((notify, handle)) = maybe_notify {
-            notify.notify_one();
-            handle.await?;
-            fail_on_error = true;
+        if !writer.is_dry_run() {
+            let maybe_notify = self
+                .fan_control_handle
+                .try_borrow_mut()
+                .map_err(|err| anyhow!("Lock error: {err}"))?
+                .take();
+            if let Some((notify, handle)) = maybe_notify {
+                notify.notify_one();
+                handle.await?;
+                fail_on_error = true;
+            }
         }
 
         let mut device = self.device();
         let fan_count = device.num_fans().context("Could not get fan count")?;
         for i in 0..fan_count {
-            if let Err(err) = device
-                .set_default_fan_speed(i)
+            if let Err(err) = writer
+                .write(
+                    "nvmlDeviceSetDefaultFanSpeed_v2",
+                    format!("fan {i}"),
+                    || device.set_default_fan_speed(i),
+                )
                 .context("Could not reset fan control to default")
             {
                 if fail_on_error {
@@ -224,6 +238,70 @@ impl NvidiaGpuController {
         Ok(())
     }
 
+    fn reset_clocks_with(&self, writer: &ConfigWriter) -> anyhow::Result<()> {
+        let mut device = self.device();
+
+        if let Ok(supported_pstates) = device.supported_performance_states() {
+            for pstate in supported_pstates {
+                for clock_type in [Clock::Graphics, Clock::Memory] {
+                    if let Ok(current_offset) = device.clock_offset(clock_type, pstate) {
+                        if current_offset.clock_offset_mhz != 0
+                            || self
+                                .last_applied_offsets
+                                .borrow()
+                                .get(&clock_type)
+                                .and_then(|applied_offsets| applied_offsets.get(&pstate))
+                                .is_some_and(|offset| *offset != 0)
+                        {
+                            debug!("resetting clock offset for {clock_type:?} pstate {pstate:?}");
+                            writer
+                                .write(
+                                    "nvmlDeviceSetClockOffsets",
+                                    format!("{clock_type:?} {pstate:?}: 0 MHz"),
+                                    || device.set_clock_offset(clock_type, pstate, 0),
+                                )
+                                .with_context(|| {
+                                    format!("Could not reset {clock_type:?} pstate {pstate:?}")
+                                })?;
+                        }
+                    }
+
+                    if !writer.is_dry_run() {
+                        if let Some(applied_offsets) =
+                            self.last_applied_offsets.borrow_mut().get_mut(&clock_type)
+                        {
+                            applied_offsets.remove(&pstate);
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.last_applied_gpu_locked_clocks.borrow().is_some() {
+            let reset = writer
+                .write("nvmlDeviceResetGpuLockedClocks", "", || {
+                    device.reset_gpu_locked_clocks()
+                })
+                .context("Could not reset locked GPU clocks")?;
+            if reset.is_some() {
+                self.last_applied_gpu_locked_clocks.take();
+            }
+        }
+
+        if self.last_applied_vram_locked_clocks.borrow().is_some() {
+            let reset = writer
+                .write("nvmlDeviceResetMemoryLockedClocks", "", || {
+                    device.reset_mem_locked_clocks()
+                })
+                .context("Could not reset locked GPU clocks")?;
+            if reset.is_some() {
+                self.last_applied_vram_locked_clocks.take();
+            }
+        }
+
+        Ok(())
+    }
+
     fn try_get_power_states(&self) -> anyhow::Result<PowerStates> {
         let device = self.device();
 
@@ -562,19 +640,24 @@ impl GpuController for NvidiaGpuController {
     }
 
     fn get_power_profile_modes(&self) -> anyhow::Result<PowerProfileModesTable> {
-        Err(anyhow!("Not supported on Nvidia"))
+        Err(ErrorKind::Unsupported.error("Not supported on Nvidia"))
     }
 
     fn reset_pmfw_settings(&self) {}
 
     fn vbios_dump(&self) -> anyhow::Result<Vec<u8>> {
-        Err(anyhow!("Not supported on Nvidia"))
+        Err(ErrorKind::Unsupported.error("Not supported on Nvidia"))
     }
 
-    #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
-    fn apply_config<'a>(
+    #[allow(
+        clippy::cast_possible_wrap,
+        clippy::cast_sign_loss,
+        clippy::too_many_lines
+    )]
+    fn apply_config_with<'a>(
         &'a self,
         config: &'a config::Gpu,
+        writer: &'a ConfigWriter,
     ) -> LocalBoxFuture<'a, anyhow::Result<()>> {
         Box::pin(async {
             let mut device = self.device();
@@ -589,8 +672,12 @@ impl GpuController for NvidiaGpuController {
 
                 if current_cap != cap {
                     debug!("setting power cap to {cap}");
-                    device
-                        .set_power_management_limit(cap)
+                    writer
+                        .write(
+                            "nvmlDeviceSetPowerManagementLimit",
+                            format!("{cap} mW"),
+                            || device.set_power_management_limit(cap),
+                        )
                         .context("Could not set power cap")?;
                 }
             } else {
@@ -600,78 +687,99 @@ impl GpuController for NvidiaGpuController {
                 if let (Ok(current_cap), Ok(default_cap)) = (current_cap, default_cap) {
                     if current_cap != default_cap {
                         debug!("resetting power cap to {default_cap}");
-                        device
-                            .set_power_management_limit(default_cap)
+                        writer
+                            .write(
+                                "nvmlDeviceSetPowerManagementLimit",
+                                format!("{default_cap} mW"),
+                                || device.set_power_management_limit(default_cap),
+                            )
                             .context("Could not reset power cap")?;
                     }
                 }
             }
 
-            self.reset_clocks()?;
+            self.reset_clocks_with(writer)?;
 
             let clocks = &config.clocks_configuration;
 
             match (clocks.min_core_clock, clocks.max_core_clock) {
                 (Some(min), Some(max)) => {
                     debug!("applying GPU locked clocks: {min}..{max}");
-                    device
-                        .set_gpu_locked_clocks(GpuLockedClocksSetting::Numeric {
-                            min_clock_mhz: min as u32,
-                            max_clock_mhz: max as u32,
-                        })
+                    let applied = writer
+                        .write(
+                            "nvmlDeviceSetGpuLockedClocks",
+                            format!("{min}-{max} MHz"),
+                            || {
+                                device.set_gpu_locked_clocks(GpuLockedClocksSetting::Numeric {
+                                    min_clock_mhz: min as u32,
+                                    max_clock_mhz: max as u32,
+                                })
+                            },
+                        )
                         .context("Could not apply GPU locked clocks")?;
-                    self.last_applied_gpu_locked_clocks
-                        .replace(Some((min as u32, max as u32)));
+                    if applied.is_some() {
+                        self.last_applied_gpu_locked_clocks
+                            .replace(Some((min as u32, max as u32)));
+                    }
                 }
                 (None, None) => (),
-                _ => bail!("Min and max GPU clock must be set together"),
+                _ => {
+                    return Err(ErrorKind::InvalidArgument
+                        .error("Min and max GPU clock must be set together"))
+                }
             }
 
             match (clocks.min_memory_clock, clocks.max_memory_clock) {
                 (Some(min), Some(max)) => {
                     debug!("applying VRAM locked clocks: {min}..{max}");
-                    device
-                        .set_mem_locked_clocks(min as u32, max as u32)
+                    let applied = writer
+                        .write(
+                            "nvmlDeviceSetMemoryLockedClocks",
+                            format!("{min}-{max} MHz"),
+                            || device.set_mem_locked_clocks(min as u32, max as u32),
+                        )
                         .context("Could not apply VRAM locked clocks")?;
-                    self.last_applied_vram_locked_clocks
-                        .replace(Some((min as u32, max as u32)));
+                    if applied.is_some() {
+                        self.last_applied_vram_locked_clocks
+                            .replace(Some((min as u32, max as u32)));
+                    }
                 }
                 (None, None) => (),
-                _ => bail!("Min and max VRAM clock must be set together"),
-            }
-
-            for (pstate, offset) in &clocks.gpu_clock_offsets {
-                let pstate = PerformanceState::try_from(*pstate)
-                    .map_err(|_| anyhow!("Invalid pstate '{pstate}'"))?;
-                debug!("applying offset {offset} for GPU pstate {pstate:?}");
-                device
-                    .set_clock_offset(Clock::Graphics, pstate, *offset)
-                    .with_context(|| {
-                        format!("Could not set clock offset {offset} for GPU pstate {pstate:?}")
-                    })?;
-
-                self.last_applied_offsets
-                    .borrow_mut()
-                    .entry(Clock::Graphics)
-                    .or_default()
-                    .insert(pstate, *offset);
+                _ => {
+                    return Err(ErrorKind::InvalidArgument
+                        .error("Min and max VRAM clock must be set together"))
+                }
             }
 
-            for (pstate, offset) in &clocks.mem_clock_offsets {
-                let pstate = PerformanceState::try_from(*pstate)
-                    .map_err(|_| anyhow!("Invalid pstate '{pstate}'"))?;
-                debug!("applying offset {offset} for VRAM pstate {pstate:?}");
-                device
-                    .set_clock_offset(Clock::Memory, pstate, *offset)
-                    .with_context(|| {
-                        format!("Could not set clock offset {offset} for VRAM pstate {pstate:?}")
+            for (clock_type, offsets, name) in [
+                (Clock::Graphics, &clocks.gpu_clock_offsets, "GPU"),
+                (Clock::Memory, &clocks.mem_clock_offsets, "VRAM"),
+            ] {
+                for (pstate, offset) in offsets {
+                    let pstate = PerformanceState::try_from(*pstate).map_err(|_| {
+                        ErrorKind::InvalidArgument.error(format!("Invalid pstate '{pstate}'"))
                     })?;
-
-                self.last_applied_offsets
-                    .borrow_mut()
-                    .entry(Clock::Memory)
-                    .or_default()
-                    .insert(pstate, *offset);
+                    debug!("applying offset {offset} for {name} pstate {pstate:?}");
+                    let applied = writer
+                        .write(
+                            "nvmlDeviceSetClockOffsets",
+                            format!("{clock_type:?} {pstate:?}: {offset} MHz"),
+                            || device.set_clock_offset(clock_type, pstate, *offset),
+                        )
+                        .with_context(|| {
+                            format!(
+                                "Could not set clock offset {offset} for {name} pstate {pstate:?}"
+                            )
+                        })?;
+
+                    if applied.is_some() {
+                        self.last_applied_offsets
+                            .borrow_mut()
+                            .entry(clock_type)
+                            .or_default()
+                            .insert(pstate, *offset);
+                    }
+                }
             }
 
             if config.fan_control_enabled {
@@ -681,7 +789,7 @@ impl GpuController for NvidiaGpuController {
                     .context("Fan control enabled with no settings")?;
                 match settings.mode {
                     FanControlMode::Static => {
-                        self.stop_fan_control()
+                        self.stop_fan_control(writer)
                             .await
                             .context("Could not reset fan control")?;
 
@@ -690,18 +798,26 @@ impl GpuController for NvidiaGpuController {
 
                         let fan_count = device.num_fans().context("Could not get fan count")?;
                         for fan in 0..fan_count {
-                            device
-                                .set_fan_speed(fan, speed)
+                            writer
+                                .write(
+                                    "nvmlDeviceSetFanSpeed_v2",
+                                    format!("fan {fan}: {speed}%"),
+                                    || device.set_fan_speed(fan, speed),
+                                )
                                 .context("Could not reset fan speed to default")?;
                         }
                     }
                     FanControlMode::Curve => {
-                        self.start_curve_fan_control_task(settings.curve.clone(), settings.clone())
-                            .await?;
+                        self.start_curve_fan_control_task(
+                            writer,
+                            settings.curve.clone(),
+                            settings.clone(),
+                        )
+                        .await?;
                     }
                 }
             } else {
-                self.stop_fan_control()
+                self.stop_fan_control(writer)
                     .await
                     .context("Could not reset fan control")?;
             }
@@ -711,53 +827,7 @@ impl GpuController for NvidiaGpuController {
     }
 
     fn reset_clocks(&self) -> anyhow::Result<()> {
-        let mut device = self.device();
-
-        if let Ok(supported_pstates) = device.supported_performance_states() {
-            for pstate in supported_pstates {
-                for clock_type in [Clock::Graphics, Clock::Memory] {
-                    if let Ok(current_offset) = device.clock_offset(clock_type, pstate) {
-                        if current_offset.clock_offset_mhz != 0
-                            || self
-                                .last_applied_offsets
-                                .borrow()
-                                .get(&clock_type)
-                                .and_then(|applied_offsets| applied_offsets.get(&pstate))
-                                .is_some_and(|offset| *offset != 0)
-                        {
-                            debug!("resetting clock offset for {clock_type:?} pstate {pstate:?}");
-                            device
-                                .set_clock_offset(clock_type, pstate, 0)
-                                .with_context(|| {
-                                    format!("Could not reset {clock_type:?} pstate {pstate:?}")
-                                })?;
-                        }
-                    }
-
-                    if let Some(applied_offsets) =
-                        self.last_applied_offsets.borrow_mut().get_mut(&clock_type)
-                    {
-                        applied_offsets.remove(&pstate);
-                    }
-                }
-            }
-        }
-
-        if self.last_applied_gpu_locked_clocks.borrow().is_some() {
-            device
-                .reset_gpu_locked_clocks()
-                .context("Could not reset locked GPU clocks")?;
-            self.last_applied_gpu_locked_clocks.take();
-        }
-
-        if self.last_applied_vram_locked_clocks.borrow().is_some() {
-            device
-                .reset_mem_locked_clocks()
-                .context("Could not reset locked GPU clocks")?;
-            self.last_applied_vram_locked_clocks.take();
-        }
-
-        Ok(())
+        self.reset_clocks_with(&ConfigWriter::hardware())
     }
 
     fn cleanup(&self) -> LocalBoxFuture<'_, ()> {
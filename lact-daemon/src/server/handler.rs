@@ -1,22 +1,28 @@
 use super::{
+    audit_log::{self, AuditLog},
     gpu_controller::{fan_control::FanCurve, DynGpuController, GpuController},
     profiles::ProfileWatcherCommand,
+    stats_history::{StatsHistory, STATS_HISTORY_INTERVAL},
+    stats_sampler::{StatsSampler, DEFAULT_STATS_SAMPLE_INTERVAL_MS},
     system::{self, detect_initramfs_type, PP_FEATURE_MASK_PATH},
+    validation,
 };
 use crate::{
     bindings::intel::IntelDrm,
-    config::{self, default_fan_static_speed, Config, FanControlSettings, Profile},
+    config::{self, default_fan_static_speed, Config, FanControlSettings, Profile, SaveOutcome},
     server::{gpu_controller::init_controller, profiles, system::DAEMON_VERSION},
 };
 use amdgpu_sysfs::gpu_handle::{
     power_profile_mode::PowerProfileModesTable, PerformanceLevel, PowerLevelKind,
 };
-use anyhow::{anyhow, bail, Context};
+use anyhow::{anyhow, Context};
 use lact_schema::{
     default_fan_curve,
     request::{ClockspeedType, ConfirmCommand, ProfileBase, SetClocksCommand},
-    ClocksInfo, DeviceInfo, DeviceListEntry, DeviceStats, FanControlMode, FanOptions, PmfwOptions,
-    PowerStates, ProfileRule, ProfileWatcherState, ProfilesInfo,
+    AuditEvent, AuditLogEntry, ClocksInfo, ConfigRevision, ConfigValidation, DeviceInfo,
+    DeviceListEntry, DeviceStats, DeviceStatsHistoryEntry, ErrorKind, FanControlMode, FanOptions,
+    GpuPciInfo, PmfwOptions, PowerStates, ProfileRule, ProfileWatcherState, ProfilesInfo,
+    SaveStatus, SettingsOutcome,
 };
 use libdrm_amdgpu_sys::LibDrmAmdgpu;
 use libflate::gzip;
@@ -38,8 +44,8 @@ use std::{
 };
 use tokio::{
     process::Command,
-    sync::{mpsc, oneshot, RwLock, RwLockReadGuard},
-    time::sleep,
+    sync::{mpsc, oneshot, watch, RwLock, RwLockReadGuard},
+    time::{interval_at, sleep, Instant as TokioInstant, MissedTickBehavior},
 };
 use tracing::{debug, error, info, trace, warn};
 
@@ -87,14 +93,25 @@ const SNAPSHOT_FAN_CTRL_FILES: &[&str] = &[
     "fan_zero_rpm_stop_temperature",
 ];
 
+/// Settings change that was applied but not yet confirmed
+struct PendingConfig {
+    gpu_id: String,
+    /// Confirming also passes a channel for the result of saving the settings
+    confirm_tx: oneshot::Sender<(ConfirmCommand, oneshot::Sender<anyhow::Result<SaveStatus>>)>,
+}
+
 #[derive(Clone)]
 pub struct Handler {
     pub config: Rc<RwLock<Config>>,
     gpu_controllers: Rc<RwLock<BTreeMap<String, DynGpuController>>>,
-    confirm_config_tx: Rc<RefCell<Option<oneshot::Sender<ConfirmCommand>>>>,
+    confirm_config_tx: Rc<RefCell<Option<PendingConfig>>>,
     pub config_last_saved: Rc<Cell<Instant>>,
     profile_watcher_tx: Rc<RefCell<Option<mpsc::Sender<ProfileWatcherCommand>>>>,
     pub profile_watcher_state: Rc<RefCell<Option<ProfileWatcherState>>>,
+    current_profile_tx: Rc<watch::Sender<Option<Rc<str>>>>,
+    stats_sampler: Rc<StatsSampler>,
+    stats_history: Rc<StatsHistory>,
+    audit_log: Rc<AuditLog>,
 }
 
 impl<'a> Handler {
@@ -157,6 +174,21 @@ impl<'a> Handler {
         }
         info!("initialized {} GPUs", controllers.len());
 
+        let stats_sample_interval_ms = config
+            .daemon
+            .stats_sample_interval_ms
+            .unwrap_or(DEFAULT_STATS_SAMPLE_INTERVAL_MS);
+        // Recording the history keeps reading the hardware, so it is only done when enabled explicitly
+        let stats_history_seconds = config.daemon.stats_history_seconds.unwrap_or(0);
+
+        let (current_profile_tx, _) = watch::channel(config.current_profile.clone());
+
+        let audit_log_path = if cfg!(test) {
+            None
+        } else {
+            Some(audit_log::default_path())
+        };
+
         let handler = Self {
             gpu_controllers: Rc::new(RwLock::new(controllers)),
             config: Rc::new(RwLock::new(config)),
@@ -164,7 +196,12 @@ impl<'a> Handler {
             config_last_saved: Rc::new(Cell::new(Instant::now())),
             profile_watcher_tx: Rc::new(RefCell::new(None)),
             profile_watcher_state: Rc::new(RefCell::new(None)),
+            current_profile_tx: Rc::new(current_profile_tx),
+            stats_sampler: Rc::new(StatsSampler::new(stats_sample_interval_ms)),
+            stats_history: Rc::new(StatsHistory::new(stats_history_seconds)),
+            audit_log: Rc::new(AuditLog::new(audit_log_path)),
         };
+
         if let Err(err) = handler.apply_current_config().await {
             error!("could not apply config: {err:#}");
         }
@@ -213,6 +250,7 @@ impl<'a> Handler {
                 }
 
                 *controllers_guard = new_controllers;
+                self.stats_sampler.clear();
 
                 match apply_config_to_controllers(&controllers_guard, &config).await {
                     Ok(()) => {
@@ -236,6 +274,14 @@ impl<'a> Handler {
         }
     }
 
+    /// Makes the profile watcher evaluate the rules again, such as when the rules were edited
+    async fn update_profile_watcher(&self) {
+        let tx = self.profile_watcher_tx.borrow().clone();
+        if let Some(tx) = tx {
+            let _ = tx.send(ProfileWatcherCommand::Update).await;
+        }
+    }
+
     pub async fn start_profile_watcher(&self) {
         self.stop_profile_watcher().await;
 
@@ -250,21 +296,13 @@ impl<'a> Handler {
         id: String,
         f: F,
     ) -> anyhow::Result<u64> {
-        if self
-            .confirm_config_tx
-            .try_borrow_mut()
-            .map_err(|err| anyhow!("{err}"))?
-            .is_some()
-        {
-            return Err(anyhow!(
-                "There is an unconfirmed configuration change pending"
-            ));
-        }
+        self.check_no_pending_config()?;
 
         let (gpu_config, apply_timer) = {
             let config = self.config.read().await;
+            config.check_gpu_editable(&id)?;
             let apply_timer = config.apply_settings_timer;
-            let gpu_config = config.gpus()?.get(&id).cloned().unwrap_or_default();
+            let gpu_config = config.gpu(&id)?.cloned().unwrap_or_default();
             (gpu_config, apply_timer)
         };
 
@@ -275,21 +313,40 @@ impl<'a> Handler {
 
         match controller.apply_config(&new_config).await {
             Ok(()) => {
+                self.stats_sampler.invalidate(&id);
                 self.wait_config_confirm(id, gpu_config, new_config, apply_timer)?;
                 Ok(apply_timer)
             }
             Err(apply_err) => {
                 error!("could not apply settings: {apply_err:?}");
+                // Keep the kind of validation errors, anything else failed when writing the settings
+                let kind = match ErrorKind::of(&apply_err) {
+                    ErrorKind::Other => ErrorKind::HardwareWriteFailed,
+                    kind => kind,
+                };
                 match controller.apply_config(&gpu_config).await {
-                    Ok(()) => Err(apply_err.context("Could not apply settings")),
-                    Err(err) => Err(apply_err.context(err.context(
+                    Ok(()) => Err(apply_err.context(kind.context("Could not apply settings"))),
+                    Err(err) => Err(apply_err.context(err.context(kind.context(
                         "Could not apply settings, and could not reset to default settings",
-                    ))),
+                    )))),
                 }
             }
         }
     }
 
+    fn check_no_pending_config(&self) -> anyhow::Result<()> {
+        if self
+            .confirm_config_tx
+            .try_borrow()
+            .map_err(|err| anyhow!("{err}"))?
+            .is_some()
+        {
+            return Err(ErrorKind::PendingConfirmation
+                .error("There is an unconfirmed configuration change pending"));
+        }
+        Ok(())
+    }
+
     /// Should be called after applying new config without writing it
     fn wait_config_confirm(
         &self,
@@ -302,52 +359,86 @@ impl<'a> Handler {
         *self
             .confirm_config_tx
             .try_borrow_mut()
-            .map_err(|err| anyhow!("{err}"))? = Some(tx);
+            .map_err(|err| anyhow!("{err}"))? = Some(PendingConfig {
+            gpu_id: id.clone(),
+            confirm_tx: tx,
+        });
 
         let handler = self.clone();
+        let caller = audit_log::current_caller();
 
         tokio::task::spawn_local(async move {
             let controller = handler
                 .controller_by_id(&id)
                 .await
                 .expect("GPU controller disappeared");
+            let changes = audit_log::diff(&previous_config, &new_config);
 
-            tokio::select! {
+            let outcome = tokio::select! {
                 () = tokio::time::sleep(Duration::from_secs(apply_timer)) => {
                     info!("no confirmation received, reverting settings");
 
                     if let Err(err) = controller.apply_config(&previous_config).await {
                         error!("could not revert settings: {err:#}");
                     }
+                    SettingsOutcome::TimedOut
                 }
                 result = rx => {
                     match result {
-                        Ok(ConfirmCommand::Confirm) => {
+                        Ok((ConfirmCommand::Confirm, save_tx)) => {
                             info!("saving updated config");
 
                             let mut config_guard = handler.config.write().await;
-                            match config_guard.gpus_mut() {
+                            let result = match config_guard.gpus_mut() {
                                 Ok(gpus) => {
-                                    gpus.insert(id, new_config);
+                                    gpus.insert(id.clone(), new_config);
+                                    config_guard.save(&handler.config_last_saved)
                                 }
-                                Err(err) => error!("{err:#}"),
-                            }
-
-                            if let Err(err) = config_guard.save(&handler.config_last_saved) {
-                                error!("{err:#}");
-                            }
+                                Err(err) => Err(err),
+                            };
+                            let status = match result {
+                                Ok(SaveOutcome::Written) => Ok(SaveStatus::Saved),
+                                Ok(SaveOutcome::NotPersisted) => {
+                                    warn!("settings were confirmed, but they are not persisted because the config is immutable");
+                                    Ok(SaveStatus::NotPersisted)
+                                }
+                                Err(err) => {
+                                    error!("{err:#}");
+                                    Err(err.context("Could not save the confirmed settings"))
+                                }
+                            };
+                            let _ = save_tx.send(status);
+                            SettingsOutcome::Confirmed
                         }
-                        Ok(ConfirmCommand::Revert) | Err(_) => {
+                        Ok((ConfirmCommand::Revert, _)) | Err(_) => {
                             if let Err(err) = controller.apply_config(&previous_config).await {
                                 error!("could not revert settings: {err:#}");
                             }
+                            SettingsOutcome::Reverted
                         }
                     }
                 }
-            }
+            };
+            handler.stats_sampler.invalidate(&id);
+            handler.audit_log.record(
+                caller,
+                AuditEvent::GpuSettings {
+                    gpu_id: id,
+                    outcome,
+                    changes,
+                },
+            );
 
+            // A newer change may have been made after this one was confirmed or reverted
             match handler.confirm_config_tx.try_borrow_mut() {
-                Ok(mut guard) => *guard = None,
+                Ok(mut guard) => {
+                    if guard
+                        .as_ref()
+                        .is_some_and(|pending| pending.confirm_tx.is_closed())
+                    {
+                        *guard = None;
+                    }
+                }
                 Err(err) => error!("{err}"),
             }
         });
@@ -361,7 +452,7 @@ impl<'a> Handler {
     ) -> anyhow::Result<RwLockReadGuard<'_, dyn GpuController>> {
         let guard = self.gpu_controllers.read().await;
         RwLockReadGuard::try_map(guard, |controllers| controllers.get(id).map(Box::as_ref))
-            .map_err(|_| anyhow!("Controller '{id}' not found"))
+            .map_err(|_| ErrorKind::NotFound.error(format!("Controller '{id}' not found")))
     }
 
     pub async fn list_devices(&'a self) -> Vec<DeviceListEntry> {
@@ -384,36 +475,129 @@ impl<'a> Handler {
             .collect()
     }
 
+    pub async fn list_gpu_pci_info(&self) -> Vec<(String, GpuPciInfo)> {
+        self.gpu_controllers
+            .read()
+            .await
+            .iter()
+            .map(|(id, controller)| (id.clone(), controller.controller_info().pci_info.clone()))
+            .collect()
+    }
+
     pub async fn get_device_info(&'a self, id: &str) -> anyhow::Result<DeviceInfo> {
         Ok(self.controller_by_id(id).await?.get_info().await)
     }
 
+    /// Returns the latest stats sampled for the GPU, reading them directly if there is no recent sample
     pub async fn get_gpu_stats(&'a self, id: &str) -> anyhow::Result<DeviceStats> {
+        if let Some(stats) = self.stats_sampler.get(id) {
+            return Ok(stats);
+        }
+
+        let stats = self.read_gpu_stats(id).await?;
+        self.stats_sampler.store(id, stats.clone());
+        if !self.stats_sampler.is_running(id) {
+            self.start_stats_sampler(id);
+        }
+        Ok(stats)
+    }
+
+    /// Spawns a task that keeps the cached stats of the GPU up to date until they stop being requested
+    fn start_stats_sampler(&self, id: &str) {
+        let handler = self.clone();
+        let gpu_id = id.to_owned();
+        let period = self.stats_sampler.interval();
+
+        let task = tokio::task::spawn_local(async move {
+            let mut interval = interval_at(TokioInstant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                if !handler.stats_sampler.is_active(&gpu_id) {
+                    debug!("stats of GPU {gpu_id} are no longer requested, stopping sampler");
+                    handler.stats_sampler.remove_task(&gpu_id);
+                    break;
+                }
+
+                // Errors are reported to clients when they read the stats directly
+                match handler.read_gpu_stats(&gpu_id).await {
+                    Ok(stats) => handler.stats_sampler.store(&gpu_id, stats),
+                    Err(err) => debug!("could not sample stats of GPU {gpu_id}: {err:#}"),
+                }
+            }
+        });
+        self.stats_sampler.set_task(id, task);
+    }
+
+    pub async fn get_gpu_stats_history(
+        &self,
+        id: &str,
+        since: Option<u64>,
+        resolution: Option<u64>,
+    ) -> anyhow::Result<Vec<DeviceStatsHistoryEntry>> {
+        // Make sure the GPU exists
+        let _ = self.controller_by_id(id).await?;
+        Ok(self.stats_history.query(id, since, resolution))
+    }
+
+    /// Records the stats of every GPU into the history until the daemon exits
+    pub async fn record_stats_history(self) {
+        if !self.stats_history.is_enabled() {
+            info!("stats history is disabled");
+            return;
+        }
+
+        let mut interval = interval_at(TokioInstant::now(), STATS_HISTORY_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let ids: Vec<String> = self.gpu_controllers.read().await.keys().cloned().collect();
+            for id in ids {
+                // Reuse the latest sample if a client is already polling the stats
+                let stats = match self.stats_sampler.peek(&id) {
+                    Some(stats) => stats,
+                    None => match self.read_gpu_stats(&id).await {
+                        Ok(stats) => stats,
+                        Err(err) => {
+                            debug!("could not record stats of GPU {id}: {err:#}");
+                            continue;
+                        }
+                    },
+                };
+                self.stats_history.push(&id, stats);
+            }
+        }
+    }
+
+    async fn read_gpu_stats(&self, id: &str) -> anyhow::Result<DeviceStats> {
         let config = self.config.read().await;
-        let gpu_config = config.gpus()?.get(id);
+        let gpu_config = config.gpu(id)?;
         Ok(self.controller_by_id(id).await?.get_stats(gpu_config))
     }
 
     pub async fn get_clocks_info(&'a self, id: &str) -> anyhow::Result<ClocksInfo> {
         let config = self.config.read().await;
-        let gpu_config = config.gpus()?.get(id);
+        let gpu_config = config.gpu(id)?;
         self.controller_by_id(id).await?.get_clocks_info(gpu_config)
     }
 
     pub async fn set_fan_control(&'a self, opts: FanOptions<'_>) -> anyhow::Result<u64> {
         let settings = {
             let mut config_guard = self.config.write().await;
-            let gpu_config = config_guard
-                .gpus_mut()?
-                .entry(opts.id.to_owned())
-                .or_default();
+            let gpu_config = config_guard.gpu_entry(opts.id)?;
 
             match opts.mode {
                 Some(mode) => match mode {
                     FanControlMode::Static => {
                         if matches!(opts.static_speed, Some(speed) if !(0.0..=1.0).contains(&speed))
                         {
-                            return Err(anyhow!("static speed value out of range"));
+                            return Err(
+                                ErrorKind::InvalidArgument.error("static speed value out of range")
+                            );
                         }
 
                         if let Some(mut existing_settings) = gpu_config.fan_control_settings.clone()
@@ -497,9 +681,22 @@ impl<'a> Handler {
         .context("Failed to edit GPU config and set power cap")
     }
 
+    /// Sets the power cap and saves it right away, for callers that have no way of confirming the change later
+    pub async fn set_power_cap_confirmed(
+        &self,
+        id: &str,
+        maybe_cap: Option<f64>,
+    ) -> anyhow::Result<()> {
+        self.set_power_cap(id, maybe_cap).await?;
+        // Nothing else can run between registering the pending change and confirming it,
+        // so the confirmation can't apply to a change made by another client
+        self.confirm_pending_config(ConfirmCommand::Confirm).await?;
+        Ok(())
+    }
+
     pub async fn get_power_states(&self, id: &str) -> anyhow::Result<PowerStates> {
         let config = self.config.read().await;
-        let gpu_config = config.gpus()?.get(id);
+        let gpu_config = config.gpu(id)?;
 
         let states = self
             .controller_by_id(id)
@@ -689,7 +886,7 @@ impl<'a> Handler {
             Err(err) => warn!("could not read service log: {err}"),
         }
 
-        let system_info = system::info()
+        let system_info = system::info(self.config.read().await.is_immutable())
             .await
             .ok()
             .map(|info| serde_json::to_value(info).unwrap());
@@ -737,7 +934,7 @@ impl<'a> Handler {
         let mut map = BTreeMap::new();
 
         for (id, controller) in controllers.iter() {
-            let gpu_config = config.gpus().ok().and_then(|gpus| gpus.get(id));
+            let gpu_config = config.gpu(id).ok().flatten();
 
             let data = json!({
                 "pci_info": controller.controller_info().pci_info.clone(),
@@ -799,38 +996,81 @@ impl<'a> Handler {
         }
 
         self.cleanup().await;
-        self.config.write().await.current_profile = name;
+        let previous_profile =
+            std::mem::replace(&mut self.config.write().await.current_profile, name.clone());
+        self.publish_current_profile(name.clone());
+        if previous_profile != name {
+            self.audit_log.record(
+                audit_log::current_caller(),
+                AuditEvent::ProfileSwitch {
+                    from: previous_profile.as_deref().map(str::to_owned),
+                    to: name.as_deref().map(str::to_owned),
+                },
+            );
+        }
 
         self.apply_current_config().await?;
 
         Ok(())
     }
 
+    /// Receives the name of the active profile whenever a different one is selected
+    pub fn subscribe_current_profile(&self) -> watch::Receiver<Option<Rc<str>>> {
+        self.current_profile_tx.subscribe()
+    }
+
+    fn publish_current_profile(&self, profile: Option<Rc<str>>) {
+        self.current_profile_tx.send_if_modified(|current| {
+            let modified = *current != profile;
+            *current = profile;
+            modified
+        });
+    }
+
     pub async fn create_profile(&self, name: String, base: ProfileBase) -> anyhow::Result<()> {
         {
             let mut config = self.config.write().await;
             if config.profiles.contains_key(name.as_str()) {
-                bail!("Profile {name} already exists");
+                return Err(
+                    ErrorKind::InvalidArgument.error(format!("Profile {name} already exists"))
+                );
             }
 
             let profile = match base {
                 ProfileBase::Empty => Profile::default(),
                 ProfileBase::Default => config.default_profile(),
                 ProfileBase::Profile(name) => config.profile(&name)?.clone(),
+                ProfileBase::Extends(base) => {
+                    config.profile(&base)?;
+                    Profile::extending(base.into())
+                }
             };
             config.profiles.insert(name.into(), profile);
             config.save(&self.config_last_saved)?;
         }
 
-        let tx = self.profile_watcher_tx.borrow().clone();
-        if let Some(tx) = tx {
-            let _ = tx.send(ProfileWatcherCommand::Update).await;
-        }
+        self.update_profile_watcher().await;
 
         Ok(())
     }
 
     pub async fn delete_profile(&self, name: String) -> anyhow::Result<()> {
+        Config::check_profile_editable(&name)?;
+
+        if let Some(child) = self
+            .config
+            .read()
+            .await
+            .profiles
+            .iter()
+            .find(|(_, profile)| profile.extends.as_deref() == Some(&name))
+            .map(|(child, _)| child.clone())
+        {
+            return Err(ErrorKind::InvalidArgument.error(format!(
+                "Profile {name} cannot be deleted, as profile {child} extends it"
+            )));
+        }
+
         if self.config.read().await.current_profile.as_deref() == Some(&name) {
             self.set_current_profile(None).await?;
         }
@@ -842,35 +1082,31 @@ impl<'a> Handler {
 
         self.config.write().await.save(&self.config_last_saved)?;
 
-        let tx = self.profile_watcher_tx.borrow().clone();
-        if let Some(tx) = tx {
-            let _ = tx.send(ProfileWatcherCommand::Update).await;
-        }
+        self.update_profile_watcher().await;
 
         Ok(())
     }
 
     pub async fn move_profile(&self, name: &str, new_position: usize) -> anyhow::Result<()> {
+        Config::check_profile_editable(name)?;
+
         {
             let mut config = self.config.write().await;
 
             let current_index = config
                 .profiles
                 .get_index_of(name)
-                .with_context(|| format!("Profile {name} not found"))?;
+                .ok_or_else(|| ErrorKind::NotFound.error(format!("Profile {name} not found")))?;
 
             if new_position >= config.profiles.len() {
-                bail!("Provided index is out of bounds");
+                return Err(ErrorKind::InvalidArgument.error("Provided index is out of bounds"));
             }
 
             config.profiles.swap_indices(current_index, new_position);
             config.save(&self.config_last_saved)?;
         }
 
-        let tx = self.profile_watcher_tx.borrow().clone();
-        if let Some(tx) = tx {
-            let _ = tx.send(ProfileWatcherCommand::Update).await;
-        }
+        self.update_profile_watcher().await;
 
         Ok(())
     }
@@ -880,20 +1116,23 @@ impl<'a> Handler {
         name: &str,
         rule: Option<ProfileRule>,
     ) -> anyhow::Result<()> {
+        if let Some(rule) = &rule {
+            profiles::CompiledRule::new(rule)
+                .context(ErrorKind::InvalidArgument.context("Invalid profile rule"))?;
+        }
+        Config::check_profile_editable(name)?;
+
         self.config
             .write()
             .await
             .profiles
             .get_mut(name)
-            .with_context(|| format!("Profile {name} not found"))?
+            .ok_or_else(|| ErrorKind::NotFound.error(format!("Profile {name} not found")))?
             .rule = rule;
 
-        self.config.read().await.save(&self.config_last_saved)?;
+        self.config.write().await.save(&self.config_last_saved)?;
 
-        let tx = self.profile_watcher_tx.borrow().clone();
-        if let Some(tx) = tx {
-            let _ = tx.send(ProfileWatcherCommand::Update).await;
-        }
+        self.update_profile_watcher().await;
 
         Ok(())
     }
@@ -901,36 +1140,152 @@ impl<'a> Handler {
     pub fn evaluate_profile_rule(&self, rule: &ProfileRule) -> anyhow::Result<bool> {
         let profile_watcher_state_guard = self.profile_watcher_state.borrow();
         match profile_watcher_state_guard.as_ref() {
-            Some(state) => Ok(profiles::profile_rule_matches(state, rule)),
+            Some(state) => profiles::profile_rule_matches(state, rule),
             None => Err(anyhow!(
                 "Automatic profile switching is not currently active"
             )),
         }
     }
 
-    pub fn confirm_pending_config(&self, command: ConfirmCommand) -> anyhow::Result<()> {
-        if let Some(tx) = self
+    /// Replaces the config with one that was edited externally and applies it
+    pub async fn reload_config(&self, new_config: Config) -> anyhow::Result<()> {
+        let changes = {
+            let mut config = self.config.write().await;
+            let changes = audit_log::diff(&*config, &new_config);
+            *config = new_config;
+            self.publish_current_profile(config.current_profile.clone());
+            changes
+        };
+        if !changes.is_empty() {
+            self.audit_log.record(
+                "config file".to_owned(),
+                AuditEvent::ConfigReload { changes },
+            );
+        }
+
+        // The rules have to be evaluated again even if the settings could not be applied
+        let result = self.apply_current_config().await;
+        self.update_profile_watcher().await;
+        result
+    }
+
+    pub fn get_audit_log(
+        &self,
+        gpu_id: Option<&str>,
+        since: Option<u64>,
+        limit: Option<usize>,
+    ) -> anyhow::Result<Vec<AuditLogEntry>> {
+        self.audit_log.query(gpu_id, since, limit)
+    }
+
+    #[allow(clippy::unused_self)]
+    pub fn list_config_revisions(&self) -> anyhow::Result<Vec<ConfigRevision>> {
+        config::history::list_revisions()
+    }
+
+    /// Replaces the config with a previously saved version and applies it
+    /// Only the GPU settings and profiles of the revision are restored, see [`Config::restore_settings`].
+    /// The config is saved once the revision has been applied.
+    pub async fn restore_config_revision(&self, id: u64) -> anyhow::Result<()> {
+        self.check_no_pending_config()?;
+
+        let mut revision = config::history::load_revision(id)?;
+        revision.migrate_versions();
+
+        let previous_config = self.config.read().await.clone();
+        let mut new_config = previous_config.clone();
+        new_config.restore_settings(revision);
+
+        {
+            let controllers = self.gpu_controllers.read().await;
+            if let Err(err) = apply_config_strict(&controllers, &new_config).await {
+                error!("could not apply config revision {id}: {err:#}");
+                if let Err(err) = apply_config_to_controllers(&controllers, &previous_config).await
+                {
+                    error!("could not go back to the previous config: {err:#}");
+                }
+                return Err(err.context(format!("Could not apply config revision {id}")));
+            }
+        }
+
+        let changes = {
+            let mut config = self.config.write().await;
+            let changes = audit_log::diff(&*config, &new_config);
+            *config = new_config;
+            config.save(&self.config_last_saved)?;
+            self.publish_current_profile(config.current_profile.clone());
+            changes
+        };
+        self.audit_log.record(
+            audit_log::current_caller(),
+            AuditEvent::ConfigRestore {
+                revision: id,
+                changes,
+            },
+        );
+
+        self.update_profile_watcher().await;
+        Ok(())
+    }
+
+    pub async fn validate_config(&self, yaml: &str) -> ConfigValidation {
+        let controllers = self.gpu_controllers.read().await;
+        validation::validate_config(yaml, &controllers).await
+    }
+
+    /// GPU that the pending settings change belongs to, if there is one
+    pub fn pending_config_gpu_id(&self) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .confirm_config_tx
+            .try_borrow()
+            .map_err(|err| anyhow!("{err}"))?
+            .as_ref()
+            .map(|pending| pending.gpu_id.clone()))
+    }
+
+    /// Returns whether the settings were saved when confirming, and `None` when reverting
+    pub async fn confirm_pending_config(
+        &self,
+        command: ConfirmCommand,
+    ) -> anyhow::Result<Option<SaveStatus>> {
+        let pending = self
             .confirm_config_tx
             .try_borrow_mut()
             .map_err(|err| anyhow!("{err}"))?
             .take()
-        {
-            tx.send(command)
-                .map_err(|_| anyhow!("Could not confirm config"))
+            .ok_or_else(|| ErrorKind::NotFound.error("No pending config changes"))?;
+
+        let confirm = command == ConfirmCommand::Confirm;
+        let (save_tx, save_rx) = oneshot::channel();
+        pending
+            .confirm_tx
+            .send((command, save_tx))
+            .map_err(|_| anyhow!("Could not confirm config"))?;
+
+        if confirm {
+            let status = save_rx
+                .await
+                .map_err(|_| anyhow!("Could not confirm config"))??;
+            Ok(Some(status))
         } else {
-            Err(anyhow!("No pending config changes"))
+            Ok(None)
         }
     }
 
     pub async fn reset_config(&self) {
         self.cleanup().await;
 
-        let mut config = self.config.write().await;
-        config.clear();
+        {
+            let mut config = self.config.write().await;
+            config.clear();
+            self.publish_current_profile(config.current_profile.clone());
 
-        if let Err(err) = config.save(&self.config_last_saved) {
-            error!("could not save config: {err:#}");
+            if let Err(err) = config.save(&self.config_last_saved) {
+                error!("could not save config: {err:#}");
+            }
         }
+
+        self.update_profile_watcher().await;
     }
 
     pub async fn cleanup(&self) {
@@ -961,13 +1316,24 @@ async fn apply_config_to_controllers(
     config: &Config,
 ) -> anyhow::Result<()> {
     let gpus = config.gpus()?;
-    for (id, gpu_config) in gpus {
-        if let Some(controller) = controllers.get(id) {
+    for (id, controller) in controllers {
+        if let Some(gpu_config) = config::find_gpu(gpus, id) {
             debug!("applying config {gpu_config:#?} to controller {id}");
             if let Err(err) = controller.apply_config(gpu_config).await {
                 error!("could not apply existing config for gpu {id}: {err:#}");
             }
-        } else {
+        }
+    }
+
+    for id in gpus.keys() {
+        if config::is_selector(id) {
+            if !controllers
+                .keys()
+                .any(|gpu_id| config::selector_matches(id, gpu_id))
+            {
+                warn!("no GPU matches selector {id} defined in configuration");
+            }
+        } else if !controllers.contains_key(id) {
             warn!("could not find GPU with id {id} defined in configuration");
         }
     }
@@ -975,6 +1341,28 @@ async fn apply_config_to_controllers(
     Ok(())
 }
 
+/// Unlike [`apply_config_to_controllers`], stops at the first GPU that the settings could not be applied to
+async fn apply_config_strict(
+    controllers: &BTreeMap<String, Box<dyn GpuController>>,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let gpus = config.gpus()?;
+    for (id, controller) in controllers {
+        if let Some(gpu_config) = config::find_gpu(gpus, id) {
+            if let Err(err) = controller.apply_config(gpu_config).await {
+                let kind = match ErrorKind::of(&err) {
+                    ErrorKind::Other => ErrorKind::HardwareWriteFailed,
+                    kind => kind,
+                };
+                return Err(
+                    err.context(kind.context(format!("Could not apply settings to GPU {id}")))
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
 fn read_pci_db() -> Database {
     Database::read().unwrap_or_else(|err| {
         warn!("could not read PCI ID database: {err}, device information will be limited");
@@ -1143,3 +1531,55 @@ fn drm_base_path() -> PathBuf {
         Err(_) => PathBuf::from("/sys/class/drm"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PendingConfig;
+    use crate::tests::test_handler;
+    use lact_schema::{request::ConfirmCommand, ErrorKind, SaveStatus};
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn restore_is_rejected_while_change_is_pending() {
+        let handler = test_handler().await;
+        let (confirm_tx, _confirm_rx) = oneshot::channel();
+        *handler.confirm_config_tx.borrow_mut() = Some(PendingConfig {
+            gpu_id: "gpu-1".to_owned(),
+            confirm_tx,
+        });
+
+        let err = handler.restore_config_revision(1).await.unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::PendingConfirmation);
+    }
+
+    #[tokio::test]
+    async fn confirm_reports_save_status() {
+        let handler = test_handler().await;
+
+        for (command, expected) in [
+            (ConfirmCommand::Confirm, Some(SaveStatus::NotPersisted)),
+            (ConfirmCommand::Revert, None),
+        ] {
+            let (confirm_tx, confirm_rx) = oneshot::channel();
+            *handler.confirm_config_tx.borrow_mut() = Some(PendingConfig {
+                gpu_id: "gpu-1".to_owned(),
+                confirm_tx,
+            });
+            tokio::spawn(async move {
+                let (command, save_tx) = confirm_rx.await.unwrap();
+                if command == ConfirmCommand::Confirm {
+                    save_tx.send(Ok(SaveStatus::NotPersisted)).unwrap();
+                }
+            });
+
+            let status = handler.confirm_pending_config(command).await.unwrap();
+            assert_eq!(status, expected);
+        }
+
+        let err = handler
+            .confirm_pending_config(ConfirmCommand::Confirm)
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,397 @@
+use super::handler::Handler;
+use lact_schema::{DeviceStats, GpuPciInfo};
+use std::{fmt::Write, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    time::timeout,
+};
+use tracing::{debug, error, info};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const MAX_REQUEST_HEAD_SIZE: usize = 8192;
+/// Clients that do not send a complete request in time are disconnected, so they cannot keep connections open
+const REQUEST_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+/// New connections are not accepted while this many are being served
+const MAX_CONNECTIONS: usize = 16;
+
+type MetricValueFn = fn(&DeviceStats) -> Option<f64>;
+
+/// Metrics which have a single value per GPU
+#[allow(clippy::cast_precision_loss)]
+const GPU_METRICS: &[(&str, &str, MetricValueFn)] = &[
+    (
+        "lact_gpu_fan_speed_rpm",
+        "Current fan speed in RPM",
+        |stats| stats.fan.speed_current.map(f64::from),
+    ),
+    (
+        "lact_gpu_fan_speed_min_rpm",
+        "Minimum fan speed in RPM",
+        |stats| stats.fan.speed_min.map(f64::from),
+    ),
+    (
+        "lact_gpu_fan_speed_max_rpm",
+        "Maximum fan speed in RPM",
+        |stats| stats.fan.speed_max.map(f64::from),
+    ),
+    (
+        "lact_gpu_fan_pwm",
+        "Current fan PWM value (0-255)",
+        |stats| stats.fan.pwm_current.map(f64::from),
+    ),
+    (
+        "lact_gpu_fan_control_enabled",
+        "Whether custom fan control is enabled",
+        |stats| Some(f64::from(u8::from(stats.fan.control_enabled))),
+    ),
+    (
+        "lact_gpu_clock_mhz",
+        "Current (average) GPU clockspeed in MHz",
+        |stats| stats.clockspeed.gpu_clockspeed.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_target_clock_mhz",
+        "Target GPU clockspeed in MHz",
+        |stats| stats.clockspeed.current_gfxclk.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_vram_clock_mhz",
+        "Current VRAM clockspeed in MHz",
+        |stats| stats.clockspeed.vram_clockspeed.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_voltage_millivolts",
+        "GPU voltage in mV",
+        |stats| stats.voltage.gpu.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_northbridge_voltage_millivolts",
+        "Northbridge voltage in mV",
+        |stats| stats.voltage.northbridge.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_vram_total_bytes",
+        "Total VRAM in bytes",
+        |stats| stats.vram.total.map(|value| value as f64),
+    ),
+    ("lact_gpu_vram_used_bytes", "Used VRAM in bytes", |stats| {
+        stats.vram.used.map(|value| value as f64)
+    }),
+    (
+        "lact_gpu_power_average_watts",
+        "Average power usage in watts",
+        |stats| stats.power.average,
+    ),
+    (
+        "lact_gpu_power_current_watts",
+        "Current power usage in watts",
+        |stats| stats.power.current,
+    ),
+    (
+        "lact_gpu_power_cap_watts",
+        "Current power limit in watts",
+        |stats| stats.power.cap_current,
+    ),
+    (
+        "lact_gpu_power_cap_max_watts",
+        "Maximum power limit in watts",
+        |stats| stats.power.cap_max,
+    ),
+    (
+        "lact_gpu_power_cap_min_watts",
+        "Minimum power limit in watts",
+        |stats| stats.power.cap_min,
+    ),
+    (
+        "lact_gpu_power_cap_default_watts",
+        "Default power limit in watts",
+        |stats| stats.power.cap_default,
+    ),
+    ("lact_gpu_busy_percent", "GPU usage in percent", |stats| {
+        stats.busy_percent.map(f64::from)
+    }),
+    (
+        "lact_gpu_core_power_state",
+        "Index of the active core power state",
+        |stats| stats.core_power_state.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_memory_power_state",
+        "Index of the active memory power state",
+        |stats| stats.memory_power_state.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_pcie_power_state",
+        "Index of the active PCIe power state",
+        |stats| stats.pcie_power_state.map(|value| value as f64),
+    ),
+    (
+        "lact_gpu_throttled",
+        "Whether the GPU is currently throttling",
+        |stats| {
+            stats
+                .throttle_info
+                .as_ref()
+                .map(|info| f64::from(u8::from(!info.is_empty())))
+        },
+    ),
+];
+
+pub async fn listen(listener: TcpListener, handler: Handler) {
+    info!("serving metrics on {:?}", listener.local_addr());
+    let connection_permits = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let permit = connection_permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Semaphore is never closed");
+
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let handler = handler.clone();
+                tokio::task::spawn_local(async move {
+                    if let Err(err) = handle_connection(stream, &handler).await {
+                        debug!("could not serve metrics request: {err:#}");
+                    }
+                    drop(permit);
+                });
+            }
+            Err(err) => error!("failed to handle metrics connection: {err}"),
+        }
+    }
+}
+
+/// A minimal HTTP/1.1 responder, which only serves `GET /metrics` and closes the connection afterwards
+async fn handle_connection(mut stream: TcpStream, handler: &Handler) -> anyhow::Result<()> {
+    let buf = match timeout(REQUEST_HEAD_TIMEOUT, read_request_head(&mut stream)).await {
+        Ok(result) => match result? {
+            RequestHead::Complete(buf) => buf,
+            RequestHead::TooLarge => {
+                return write_response(&mut stream, "431 Request Header Fields Too Large", "", "")
+                    .await;
+            }
+            RequestHead::Closed => return Ok(()),
+        },
+        Err(_) => return write_response(&mut stream, "408 Request Timeout", "", "").await,
+    };
+
+    let head = String::from_utf8_lossy(&buf);
+    match parse_request_line(&head) {
+        ("GET", "/metrics") => {
+            let body = render(handler).await;
+            write_response(&mut stream, "200 OK", CONTENT_TYPE, &body).await
+        }
+        ("GET", _) => write_response(&mut stream, "404 Not Found", "", "").await,
+        _ => write_response(&mut stream, "405 Method Not Allowed", "", "").await,
+    }
+}
+
+/// Method and path of the request, the query string is not used
+fn parse_request_line(head: &str) -> (&str, &str) {
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    (method, path)
+}
+
+enum RequestHead {
+    Complete(Vec<u8>),
+    TooLarge,
+    Closed,
+}
+
+async fn read_request_head(stream: &mut TcpStream) -> std::io::Result<RequestHead> {
+    let mut buf = Vec::with_capacity(1024);
+    while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST_HEAD_SIZE {
+            return Ok(RequestHead::TooLarge);
+        }
+
+        let mut chunk = [0; 1024];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(RequestHead::Closed);
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+    Ok(RequestHead::Complete(buf))
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> anyhow::Result<()> {
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    );
+    if !content_type.is_empty() {
+        write!(response, "Content-Type: {content_type}\r\n").unwrap();
+    }
+    response.push_str("\r\n");
+    response.push_str(body);
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Renders the stats of all GPUs in the `OpenMetrics` text format
+pub async fn render(handler: &Handler) -> String {
+    let mut gpus = Vec::new();
+    for (id, pci_info) in handler.list_gpu_pci_info().await {
+        match handler.get_gpu_stats(&id).await {
+            Ok(stats) => gpus.push((gpu_labels(&id, &pci_info), stats)),
+            Err(err) => error!("could not get stats of GPU {id} for metrics: {err:#}"),
+        }
+    }
+    render_gpus(&gpus)
+}
+
+fn render_gpus(gpus: &[(String, DeviceStats)]) -> String {
+    let mut out = String::new();
+
+    write_family(
+        &mut out,
+        "lact_gpu_temperature_celsius",
+        "Temperature sensor reading in degrees Celsius",
+    );
+    for (labels, stats) in gpus {
+        let mut temps: Vec<_> = stats.temps.iter().collect();
+        temps.sort_by_key(|(name, _)| *name);
+
+        for (sensor, temp) in temps {
+            if let Some(current) = temp.current {
+                let labels = format!("{labels},sensor=\"{}\"", escape_label(sensor));
+                write_sample(
+                    &mut out,
+                    "lact_gpu_temperature_celsius",
+                    &labels,
+                    current.into(),
+                );
+            }
+        }
+    }
+
+    for (name, help, value_fn) in GPU_METRICS {
+        write_family(&mut out, name, help);
+        for (labels, stats) in gpus {
+            if let Some(value) = value_fn(stats) {
+                write_sample(&mut out, name, labels, value);
+            }
+        }
+    }
+
+    write_family(
+        &mut out,
+        "lact_gpu_throttle_reason",
+        "Active throttling reasons, present only while the reason is active",
+    );
+    for (labels, stats) in gpus {
+        for reason in stats.throttle_info.iter().flat_map(|info| info.keys()) {
+            let labels = format!("{labels},reason=\"{}\"", escape_label(reason));
+            write_sample(&mut out, "lact_gpu_throttle_reason", &labels, 1.0);
+        }
+    }
+
+    out.push_str("# EOF\n");
+    out
+}
+
+fn gpu_labels(id: &str, pci_info: &GpuPciInfo) -> String {
+    let device = &pci_info.device_pci_info;
+    let subsystem = &pci_info.subsystem_pci_info;
+
+    format!(
+        "gpu_id=\"{}\",vendor=\"{}\",model=\"{}\",subsystem_vendor=\"{}\",subsystem_model=\"{}\"",
+        escape_label(id),
+        escape_label(device.vendor.as_deref().unwrap_or_default()),
+        escape_label(device.model.as_deref().unwrap_or_default()),
+        escape_label(subsystem.vendor.as_deref().unwrap_or_default()),
+        escape_label(subsystem.model.as_deref().unwrap_or_default()),
+    )
+}
+
+fn write_family(out: &mut String, name: &str, help: &str) {
+    writeln!(out, "# TYPE {name} gauge").unwrap();
+    writeln!(out, "# HELP {name} {help}").unwrap();
+}
+
+fn write_sample(out: &mut String, name: &str, labels: &str, value: f64) {
+    writeln!(out, "{name}{{{labels}}} {value}").unwrap();
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_request_line, render_gpus};
+    use amdgpu_sysfs::hw_mon::Temperature;
+    use insta::assert_snapshot;
+    use lact_schema::{ClockspeedStats, DeviceStats, FanStats, PowerStats};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn render_metrics() {
+        let stats = DeviceStats {
+            fan: FanStats {
+                control_enabled: true,
+                pwm_current: Some(120),
+                speed_current: Some(1500),
+                ..Default::default()
+            },
+            clockspeed: ClockspeedStats {
+                gpu_clockspeed: Some(2100),
+                current_gfxclk: None,
+                vram_clockspeed: Some(1000),
+            },
+            power: PowerStats {
+                average: Some(150.5),
+                cap_current: Some(200.0),
+                ..Default::default()
+            },
+            temps: HashMap::from([(
+                "edge".to_owned(),
+                Temperature {
+                    current: Some(55.0),
+                    crit: None,
+                    crit_hyst: None,
+                },
+            )]),
+            busy_percent: Some(99),
+            throttle_info: Some(BTreeMap::from([(
+                "Thermal".to_owned(),
+                vec!["TEMP_HOTSPOT".to_owned()],
+            )])),
+            ..Default::default()
+        };
+        let labels = "gpu_id=\"1002:687F-1043:0555-0000:0b:00.0\",vendor=\"AMD\",model=\"Vega \\\"10\\\"\",subsystem_vendor=\"\",subsystem_model=\"\"".to_owned();
+
+        assert_snapshot!(render_gpus(&[(labels, stats)]));
+    }
+
+    #[test]
+    fn request_line() {
+        assert_eq!(
+            parse_request_line("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            ("GET", "/metrics")
+        );
+        assert_eq!(
+            parse_request_line("GET /metrics?name[]=lact_gpu_clock_mhz HTTP/1.1\r\n\r\n"),
+            ("GET", "/metrics")
+        );
+        assert_eq!(parse_request_line("POST /?a=b HTTP/1.1\r\n"), ("POST", "/"));
+        assert_eq!(parse_request_line(""), ("", ""));
+    }
+}
@@ -1,7 +1,10 @@
 mod gamemode;
+mod matching;
 mod process;
 
-use crate::server::handler::Handler;
+pub(crate) use matching::CompiledRule;
+
+use crate::{config::Config, server::handler::Handler};
 use copes::solver::PEvent;
 use futures::StreamExt;
 use lact_schema::{ProfileRule, ProfileWatcherState};
@@ -120,7 +123,8 @@ pub async fn run_watcher(handler: Handler, mut command_rx: mpsc::Receiver<Profil
 
     *handler.profile_watcher_state.borrow_mut() = Some(state);
 
-    update_profile(&handler).await;
+    let mut profile_rules = compile_rules(&*handler.config.read().await);
+    update_profile(&handler, &profile_rules).await;
 
     let mut should_reload = false;
     let mut suspiciously_quiet = false;
@@ -134,7 +138,8 @@ pub async fn run_watcher(handler: Handler, mut command_rx: mpsc::Receiver<Profil
                 match cmd {
                     ProfileWatcherCommand::Stop => break,
                     ProfileWatcherCommand::Update => {
-                        update_profile(&handler).await;
+                        profile_rules = compile_rules(&*handler.config.read().await);
+                        update_profile(&handler, &profile_rules).await;
                     }
                 }
             }
@@ -176,7 +181,7 @@ pub async fn run_watcher(handler: Handler, mut command_rx: mpsc::Receiver<Profil
                     }
                 }
 
-                update_profile(&handler).await;
+                update_profile(&handler, &profile_rules).await;
             },
             () = &mut inactivity_timer => {
                 if suspiciously_quiet {
@@ -252,18 +257,33 @@ fn handle_profile_event(event: &ProfileWatcherEvent, handler: &Handler, should_r
     }
 }
 
-async fn update_profile(handler: &Handler) {
-    let new_profile = {
-        let config = handler.config.read().await;
-        let profile_rules = config
-            .profiles
-            .iter()
-            .filter_map(|(name, profile)| Some((name, profile.rule.as_ref()?)));
+/// Compiles the rules of all profiles in their evaluation order
+fn compile_rules(config: &Config) -> Vec<(Rc<str>, CompiledRule)> {
+    config
+        .profiles
+        .iter()
+        .filter_map(|(name, profile)| {
+            let rule = profile.rule.as_ref()?;
+            match CompiledRule::new(rule) {
+                Ok(rule) => Some((name.clone(), rule)),
+                Err(err) => {
+                    error!("could not load the rule of profile '{name}': {err:#}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
 
+async fn update_profile(handler: &Handler, profile_rules: &[(Rc<str>, CompiledRule)]) {
+    let new_profile = {
         let state_guard = handler.profile_watcher_state.borrow();
         if let Some(state) = state_guard.as_ref() {
             let started_at = Instant::now();
-            let new_profile = evaluate_current_profile(state, profile_rules);
+            let new_profile = evaluate_current_profile(
+                state,
+                profile_rules.iter().map(|(name, rule)| (name, rule)),
+            );
             trace!("evaluated profile rules in {:?}", started_at.elapsed());
             new_profile.cloned()
         } else {
@@ -287,10 +307,10 @@ async fn update_profile(handler: &Handler) {
 /// Returns the new active profile
 fn evaluate_current_profile<'a>(
     state: &ProfileWatcherState,
-    profile_rules: impl Iterator<Item = (&'a Rc<str>, &'a ProfileRule)>,
+    profile_rules: impl Iterator<Item = (&'a Rc<str>, &'a CompiledRule)>,
 ) -> Option<&'a Rc<str>> {
     for (profile_name, rule) in profile_rules {
-        if profile_rule_matches(state, rule) {
+        if rule.matches(state) {
             return Some(profile_name);
         }
     }
@@ -298,55 +318,16 @@ fn evaluate_current_profile<'a>(
     None
 }
 
-#[inline]
-pub(crate) fn profile_rule_matches(state: &ProfileWatcherState, rule: &ProfileRule) -> bool {
-    match rule {
-        ProfileRule::Process(process_rule) => {
-            if let Some(pids) = state.process_names_map.get(&process_rule.name) {
-                match &process_rule.args {
-                    Some(args_filter) => {
-                        for pid in pids {
-                            if let Some(process_info) = state.process_list.get(pid) {
-                                if process_info.cmdline.contains(args_filter) {
-                                    return true;
-                                }
-                            } else {
-                                error!("process {pid} not found in process map");
-                            }
-                        }
-                    }
-                    None => return true,
-                }
-            }
-        }
-        ProfileRule::Gamemode(None) => return !state.gamemode_games.is_empty(),
-        ProfileRule::Gamemode(Some(gamemode_rule)) => {
-            if let Some(pids) = state.process_names_map.get(&gamemode_rule.name) {
-                for pid in pids {
-                    if state.gamemode_games.contains(pid) {
-                        match &gamemode_rule.args {
-                            Some(args_filter) => {
-                                if let Some(process_info) = state.process_list.get(pid) {
-                                    if process_info.cmdline.contains(args_filter) {
-                                        return true;
-                                    }
-                                } else {
-                                    error!("process {pid} not found in process map");
-                                }
-                            }
-                            None => return true,
-                        }
-                    }
-                }
-            }
-        }
-    }
-    false
+pub(crate) fn profile_rule_matches(
+    state: &ProfileWatcherState,
+    rule: &ProfileRule,
+) -> anyhow::Result<bool> {
+    Ok(CompiledRule::new(rule)?.matches(state))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::evaluate_current_profile;
+    use super::{evaluate_current_profile, profile_rule_matches, CompiledRule};
     use lact_schema::{ProcessInfo, ProcessProfileRule, ProfileRule, ProfileWatcherState};
     use pretty_assertions::assert_eq;
     use std::rc::Rc;
@@ -368,6 +349,7 @@ mod tests {
                 ProfileRule::Process(ProcessProfileRule {
                     name: "game1".into(),
                     args: None,
+                    ..Default::default()
                 }),
             ),
             (
@@ -375,9 +357,11 @@ mod tests {
                 ProfileRule::Process(ProcessProfileRule {
                     name: "game2".into(),
                     args: None,
+                    ..Default::default()
                 }),
             ),
-        ];
+        ]
+        .map(|(name, rule): (Rc<str>, _)| (name, CompiledRule::new(&rule).unwrap()));
 
         assert_eq!(
             Some(&Rc::from("1")),
@@ -408,14 +392,58 @@ mod tests {
             evaluate_current_profile(&state, profile_rules.iter().map(|(key, rule)| (key, rule)))
         );
     }
+
+    #[test]
+    fn evaluate_combined_rules() {
+        let mut state = ProfileWatcherState::default();
+        state.push_process(
+            1,
+            ProcessInfo {
+                name: "game".into(),
+                cmdline: "game --fullscreen".into(),
+            },
+        );
+        state.gamemode_games.insert(1);
+
+        let process = |name: &str| {
+            ProfileRule::Process(ProcessProfileRule {
+                name: name.into(),
+                args: None,
+                ..Default::default()
+            })
+        };
+        // The game is running in gamemode and OBS is not running
+        let rule = ProfileRule::And(vec![
+            process("game"),
+            ProfileRule::Gamemode(None),
+            ProfileRule::Not(Box::new(process("obs"))),
+        ]);
+        assert!(profile_rule_matches(&state, &rule).unwrap());
+
+        state.push_process(
+            2,
+            ProcessInfo {
+                name: "obs".into(),
+                cmdline: "obs".into(),
+            },
+        );
+        assert!(!profile_rule_matches(&state, &rule).unwrap());
+
+        let rule = ProfileRule::Or(vec![process("other-game"), process("obs")]);
+        assert!(profile_rule_matches(&state, &rule).unwrap());
+        assert!(!profile_rule_matches(&state, &ProfileRule::Or(vec![])).unwrap());
+        assert!(profile_rule_matches(&state, &ProfileRule::And(vec![])).unwrap());
+    }
 }
 
 #[cfg(feature = "bench")]
 mod benches {
-    use super::evaluate_current_profile;
+    use super::{evaluate_current_profile, CompiledRule};
     use divan::Bencher;
-    use lact_schema::{ProcessInfo, ProcessProfileRule, ProfileRule, ProfileWatcherState};
-    use std::hint::black_box;
+    use lact_schema::{
+        ProcessInfo, ProcessMatchMode, ProcessProfileRule, ProfileRule, ProfileWatcherState,
+    };
+    use std::{hint::black_box, rc::Rc};
 
     #[divan::bench(sample_size = 1000, min_time = 2)]
     fn evaluate_profiles(bencher: Bencher) {
@@ -433,6 +461,7 @@ mod benches {
                 ProfileRule::Process(ProcessProfileRule {
                     name: "game-abc".into(),
                     args: None,
+                    ..Default::default()
                 }),
             ),
             (
@@ -440,9 +469,20 @@ mod benches {
                 ProfileRule::Process(ProcessProfileRule {
                     name: "game-1034".into(),
                     args: None,
+                    ..Default::default()
+                }),
+            ),
+            (
+                "3".into(),
+                ProfileRule::Process(ProcessProfileRule {
+                    name: "*.exe".into(),
+                    args: Some("*steamapps*common*".to_owned()),
+                    match_mode: ProcessMatchMode::Glob,
+                    ignore_case: true,
                 }),
             ),
-        ];
+        ]
+        .map(|(name, rule): (Rc<str>, _)| (name, CompiledRule::new(&rule).unwrap()));
 
         bencher.bench_local(move || {
             evaluate_current_profile(
@@ -0,0 +1,263 @@
+//! Profile rules prepared for evaluation. The rules are evaluated after every burst of process events, so the name and
+//! argument patterns are compiled once when the rules change instead of on every evaluation.
+use anyhow::Context;
+use lact_schema::{ProcessMatchMode, ProcessProfileRule, ProfileRule, ProfileWatcherState};
+use regex::{Regex, RegexBuilder};
+use std::sync::Arc;
+use tracing::error;
+
+pub enum CompiledRule {
+    Process(ProcessFilter),
+    Gamemode(Option<ProcessFilter>),
+    And(Vec<CompiledRule>),
+    Or(Vec<CompiledRule>),
+    Not(Box<CompiledRule>),
+}
+
+impl CompiledRule {
+    pub fn new(rule: &ProfileRule) -> anyhow::Result<Self> {
+        let compiled = match rule {
+            ProfileRule::Process(rule) => Self::Process(ProcessFilter::new(rule)?),
+            ProfileRule::Gamemode(rule) => {
+                Self::Gamemode(rule.as_ref().map(ProcessFilter::new).transpose()?)
+            }
+            ProfileRule::And(rules) => Self::And(Self::new_all(rules)?),
+            ProfileRule::Or(rules) => Self::Or(Self::new_all(rules)?),
+            ProfileRule::Not(rule) => Self::Not(Box::new(Self::new(rule)?)),
+        };
+        Ok(compiled)
+    }
+
+    fn new_all(rules: &[ProfileRule]) -> anyhow::Result<Vec<Self>> {
+        rules.iter().map(Self::new).collect()
+    }
+
+    pub fn matches(&self, state: &ProfileWatcherState) -> bool {
+        match self {
+            Self::Process(filter) => filter.any_process(state, |_| true),
+            Self::Gamemode(None) => !state.gamemode_games.is_empty(),
+            Self::Gamemode(Some(filter)) => {
+                filter.any_process(state, |pid| state.gamemode_games.contains(&pid))
+            }
+            Self::And(rules) => rules.iter().all(|rule| rule.matches(state)),
+            Self::Or(rules) => rules.iter().any(|rule| rule.matches(state)),
+            Self::Not(rule) => !rule.matches(state),
+        }
+    }
+}
+
+pub struct ProcessFilter {
+    name: NamePattern,
+    args: Option<ArgsPattern>,
+}
+
+enum NamePattern {
+    /// Looked up directly in the process names map
+    Exact(Arc<str>),
+    Regex(Regex),
+}
+
+enum ArgsPattern {
+    Contains(String),
+    Regex(Regex),
+}
+
+impl ProcessFilter {
+    fn new(rule: &ProcessProfileRule) -> anyhow::Result<Self> {
+        let literal = rule.match_mode == ProcessMatchMode::Exact && !rule.ignore_case;
+
+        let name = if literal {
+            NamePattern::Exact(rule.name.clone())
+        } else {
+            NamePattern::Regex(
+                compile(&rule.name, rule.match_mode, rule.ignore_case, true)
+                    .context("Invalid process name pattern")?,
+            )
+        };
+
+        let args = match &rule.args {
+            None => None,
+            Some(args) if literal => Some(ArgsPattern::Contains(args.clone())),
+            Some(args) => Some(ArgsPattern::Regex(
+                compile(args, rule.match_mode, rule.ignore_case, false)
+                    .context("Invalid process arguments pattern")?,
+            )),
+        };
+
+        Ok(Self { name, args })
+    }
+
+    /// If any running process that passes the given pid filter matches
+    fn any_process(&self, state: &ProfileWatcherState, pid_filter: impl Fn(i32) -> bool) -> bool {
+        let process_matches = |pid: &i32| pid_filter(*pid) && self.args_match(state, *pid);
+
+        match &self.name {
+            NamePattern::Exact(name) => state
+                .process_names_map
+                .get(name)
+                .is_some_and(|pids| pids.iter().any(process_matches)),
+            // Each distinct name only has to be checked once, no matter how many processes use it
+            NamePattern::Regex(regex) => state
+                .process_names_map
+                .iter()
+                .filter(|(name, _)| regex.is_match(name))
+                .any(|(_, pids)| pids.iter().any(process_matches)),
+        }
+    }
+
+    fn args_match(&self, state: &ProfileWatcherState, pid: i32) -> bool {
+        let Some(args) = &self.args else {
+            return true;
+        };
+
+        let Some(process_info) = state.process_list.get(&pid) else {
+            error!("process {pid} not found in process map");
+            return false;
+        };
+
+        match args {
+            ArgsPattern::Contains(text) => process_info.cmdline.contains(text.as_str()),
+            ArgsPattern::Regex(regex) => regex.is_match(&process_info.cmdline),
+        }
+    }
+}
+
+/// Exact names have to be equal, while exact arguments only have to be contained in the command line
+fn compile(
+    pattern: &str,
+    mode: ProcessMatchMode,
+    ignore_case: bool,
+    is_name: bool,
+) -> anyhow::Result<Regex> {
+    let source = match mode {
+        ProcessMatchMode::Exact if is_name => format!("^{}$", regex::escape(pattern)),
+        ProcessMatchMode::Exact => regex::escape(pattern),
+        ProcessMatchMode::Glob => glob_to_regex(pattern),
+        ProcessMatchMode::Regex => pattern.to_owned(),
+    };
+
+    RegexBuilder::new(&source)
+        .case_insensitive(ignore_case)
+        .build()
+        .with_context(|| format!("Could not compile pattern '{pattern}'"))
+}
+
+fn glob_to_regex(pattern: &str) -> String {
+    let mut source = String::from("^");
+    let mut literal = [0; 4];
+    for c in pattern.chars() {
+        match c {
+            '*' => source.push_str(".*"),
+            '?' => source.push('.'),
+            _ => source.push_str(&regex::escape(c.encode_utf8(&mut literal))),
+        }
+    }
+    source.push('$');
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompiledRule;
+    use lact_schema::{
+        ProcessInfo, ProcessMatchMode, ProcessProfileRule, ProfileRule, ProfileWatcherState,
+    };
+
+    fn state() -> ProfileWatcherState {
+        let mut state = ProfileWatcherState::default();
+        state.push_process(
+            1,
+            ProcessInfo {
+                name: "Cyberpunk2077.exe".into(),
+                cmdline: "Z:\\mnt\\games\\SteamLibrary\\steamapps\\common\\Cyberpunk 2077\\bin\\x64\\Cyberpunk2077.exe".into(),
+            },
+        );
+        state.push_process(
+            2,
+            ProcessInfo {
+                name: "steam".into(),
+                cmdline: "/home/user/.local/share/Steam/ubuntu12_32/steam -silent".into(),
+            },
+        );
+        state
+    }
+
+    fn matches(
+        name: &str,
+        args: Option<&str>,
+        match_mode: ProcessMatchMode,
+        ignore_case: bool,
+    ) -> bool {
+        let rule = ProfileRule::Process(ProcessProfileRule {
+            name: name.into(),
+            args: args.map(str::to_owned),
+            match_mode,
+            ignore_case,
+        });
+        CompiledRule::new(&rule).unwrap().matches(&state())
+    }
+
+    #[test]
+    fn exact() {
+        assert!(matches(
+            "steam",
+            Some("-silent"),
+            ProcessMatchMode::Exact,
+            false
+        ));
+        assert!(!matches("Steam", None, ProcessMatchMode::Exact, false));
+        assert!(matches(
+            "Steam",
+            Some("-SILENT"),
+            ProcessMatchMode::Exact,
+            true
+        ));
+        // Exact names are not matched partially, even when ignoring case
+        assert!(!matches("stea", None, ProcessMatchMode::Exact, true));
+    }
+
+    #[test]
+    fn glob() {
+        assert!(matches(
+            "*.exe",
+            Some("*steamapps*common*"),
+            ProcessMatchMode::Glob,
+            false
+        ));
+        assert!(matches(
+            "cyberpunk????.EXE",
+            None,
+            ProcessMatchMode::Glob,
+            true
+        ));
+        assert!(!matches(
+            "*.exe",
+            Some("steamapps"),
+            ProcessMatchMode::Glob,
+            false
+        ));
+        assert!(!matches("*.EXE", None, ProcessMatchMode::Glob, false));
+        // Other regex syntax is matched literally
+        assert!(!matches("steam.+", None, ProcessMatchMode::Glob, false));
+    }
+
+    #[test]
+    fn regex() {
+        assert!(matches(
+            r"\.exe$",
+            Some(r"steamapps\\common"),
+            ProcessMatchMode::Regex,
+            false
+        ));
+        assert!(matches("^STEAM$", None, ProcessMatchMode::Regex, true));
+        assert!(!matches("^team", None, ProcessMatchMode::Regex, false));
+
+        let rule = ProfileRule::Process(ProcessProfileRule {
+            name: "game(".into(),
+            args: None,
+            match_mode: ProcessMatchMode::Regex,
+            ignore_case: false,
+        });
+        assert!(CompiledRule::new(&rule).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use super::audit_log::unix_timestamp_ms;
+use lact_schema::{DeviceStats, DeviceStatsHistoryEntry};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+pub const STATS_HISTORY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bounded per-GPU history of stats, recorded once every `STATS_HISTORY_INTERVAL`
+pub struct StatsHistory {
+    capacity: usize,
+    entries: RefCell<HashMap<String, VecDeque<DeviceStatsHistoryEntry>>>,
+}
+
+impl StatsHistory {
+    pub fn new(history_seconds: u64) -> Self {
+        let capacity = history_seconds / STATS_HISTORY_INTERVAL.as_secs();
+        Self {
+            capacity: usize::try_from(capacity).unwrap_or(usize::MAX),
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn push(&self, id: &str, stats: DeviceStats) {
+        let timestamp = unix_timestamp_ms();
+        self.push_entry(id, DeviceStatsHistoryEntry { timestamp, stats });
+    }
+
+    fn push_entry(&self, id: &str, entry: DeviceStatsHistoryEntry) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.borrow_mut();
+        let history = entries.entry(id.to_owned()).or_default();
+        if history.len() >= self.capacity {
+            history.pop_front();
+        }
+        history.push_back(entry);
+    }
+
+    /// Returns the entries recorded at or after `since`,
+    /// skipping entries that are less than `resolution` milliseconds apart from the previous returned one.
+    pub fn query(
+        &self,
+        id: &str,
+        since: Option<u64>,
+        resolution: Option<u64>,
+    ) -> Vec<DeviceStatsHistoryEntry> {
+        let entries = self.entries.borrow();
+        let Some(history) = entries.get(id) else {
+            return vec![];
+        };
+
+        let since = since.unwrap_or(0);
+        let resolution = resolution.unwrap_or(0);
+        let start = history.partition_point(|entry| entry.timestamp < since);
+
+        let mut result = Vec::new();
+        let mut last_timestamp = None;
+        for entry in history.range(start..) {
+            if last_timestamp.is_some_and(|last| entry.timestamp.saturating_sub(last) < resolution)
+            {
+                continue;
+            }
+            last_timestamp = Some(entry.timestamp);
+            result.push(entry.clone());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatsHistory;
+    use lact_schema::{DeviceStats, DeviceStatsHistoryEntry};
+
+    fn push_at(history: &StatsHistory, timestamp: u64) {
+        history.push_entry(
+            "gpu",
+            DeviceStatsHistoryEntry {
+                timestamp,
+                stats: DeviceStats::default(),
+            },
+        );
+    }
+
+    fn timestamps(entries: &[DeviceStatsHistoryEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.timestamp).collect()
+    }
+
+    #[test]
+    fn bounded_history() {
+        let history = StatsHistory::new(3);
+        for timestamp in [1000, 2000, 3000, 4000] {
+            push_at(&history, timestamp);
+        }
+
+        let entries = history.query("gpu", None, None);
+        assert_eq!(vec![2000, 3000, 4000], timestamps(&entries));
+        assert!(history.query("other", None, None).is_empty());
+    }
+
+    #[test]
+    fn query_since_with_resolution() {
+        let history = StatsHistory::new(3600);
+        for timestamp in (1..=10).map(|i| i * 1000) {
+            push_at(&history, timestamp);
+        }
+
+        let entries = history.query("gpu", Some(3500), None);
+        assert_eq!(4000, entries[0].timestamp);
+        assert_eq!(7, entries.len());
+
+        let entries = history.query("gpu", Some(3000), Some(3000));
+        assert_eq!(vec![3000, 6000, 9000], timestamps(&entries));
+    }
+
+    #[test]
+    fn disabled_history() {
+        let history = StatsHistory::new(0);
+        push_at(&history, 1000);
+        assert!(history.query("gpu", None, None).is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use lact_schema::DeviceStats;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_STATS_SAMPLE_INTERVAL_MS: u64 = 500;
+const MIN_STATS_SAMPLE_INTERVAL_MS: u64 = 50;
+/// How many sample intervals a GPU can go without its stats being requested before its sampler stops
+const IDLE_INTERVALS: u32 = 10;
+
+/// Latest `DeviceStats` of each GPU, refreshed by one sampler task per GPU.
+///
+/// A sampler is only running while the stats of its GPU are being requested,
+/// so an idle daemon does not keep reading sysfs in the background.
+pub struct StatsSampler {
+    interval: Duration,
+    entries: RefCell<HashMap<String, Entry>>,
+}
+
+struct Entry {
+    last_requested: Instant,
+    sample: Option<(Instant, DeviceStats)>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl StatsSampler {
+    pub fn new(interval_ms: u64) -> Self {
+        Self {
+            interval: Duration::from_millis(interval_ms.max(MIN_STATS_SAMPLE_INTERVAL_MS)),
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the cached stats if they were sampled within the last interval,
+    /// and marks the GPU as being actively requested.
+    pub fn get(&self, id: &str) -> Option<DeviceStats> {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(id) {
+            entry.last_requested = Instant::now();
+        }
+        self.peek(id)
+    }
+
+    /// Returns the cached stats if they were sampled within the last interval,
+    /// without keeping the sampler of the GPU active.
+    pub fn peek(&self, id: &str) -> Option<DeviceStats> {
+        self.entries
+            .borrow()
+            .get(id)?
+            .sample
+            .as_ref()
+            .filter(|(sampled_at, _)| sampled_at.elapsed() <= self.interval)
+            .map(|(_, stats)| stats.clone())
+    }
+
+    pub fn store(&self, id: &str, stats: DeviceStats) {
+        let now = Instant::now();
+        self.entries
+            .borrow_mut()
+            .entry(id.to_owned())
+            .or_insert_with(|| Entry {
+                last_requested: now,
+                sample: None,
+                task: None,
+            })
+            .sample = Some((now, stats));
+    }
+
+    /// Whether the stats of the given GPU were requested recently enough to keep sampling them
+    pub fn is_active(&self, id: &str) -> bool {
+        self.entries
+            .borrow()
+            .get(id)
+            .is_some_and(|entry| entry.last_requested.elapsed() < self.interval * IDLE_INTERVALS)
+    }
+
+    pub fn is_running(&self, id: &str) -> bool {
+        self.entries
+            .borrow()
+            .get(id)
+            .is_some_and(|entry| entry.task.is_some())
+    }
+
+    /// Registers the sampler task of a GPU. Has no effect if there are no stats stored for it.
+    pub fn set_task(&self, id: &str, task: JoinHandle<()>) {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(id) {
+            if let Some(old_task) = entry.task.replace(task) {
+                old_task.abort();
+            }
+        }
+    }
+
+    /// Called by a sampler task when it exits on its own
+    pub fn remove_task(&self, id: &str) {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(id) {
+            entry.task = None;
+        }
+    }
+
+    /// Drops the cached stats of a GPU, so that the next request reads them again.
+    /// Used when settings that are reflected in the stats change.
+    pub fn invalidate(&self, id: &str) {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(id) {
+            entry.sample = None;
+        }
+    }
+
+    /// Stops all samplers and drops the cached stats
+    pub fn clear(&self) {
+        for (_, entry) in self.entries.borrow_mut().drain() {
+            if let Some(task) = entry.task {
+                task.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatsSampler;
+    use lact_schema::DeviceStats;
+
+    #[test]
+    fn cached_stats() {
+        let sampler = StatsSampler::new(1000);
+        assert!(sampler.get("gpu").is_none());
+        assert!(!sampler.is_active("gpu"));
+
+        let stats = DeviceStats {
+            busy_percent: Some(50),
+            ..Default::default()
+        };
+        sampler.store("gpu", stats);
+        assert!(sampler.is_active("gpu"));
+        assert_eq!(Some(50), sampler.get("gpu").unwrap().busy_percent);
+
+        sampler.invalidate("gpu");
+        assert!(sampler.get("gpu").is_none());
+        assert!(sampler.is_active("gpu"));
+
+        sampler.clear();
+        assert!(!sampler.is_active("gpu"));
+    }
+}
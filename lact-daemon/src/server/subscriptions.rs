@@ -0,0 +1,175 @@
+use super::handler::Handler;
+use anyhow::Context;
+use lact_schema::Event;
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+use tracing::{debug, error};
+
+const MIN_STATS_INTERVAL_MS: u64 = 50;
+
+/// Event subscriptions that belong to a single client connection.
+/// Events are serialized and sent to the connection through `event_tx`.
+#[derive(Clone)]
+pub struct Subscriptions {
+    event_tx: mpsc::Sender<Vec<u8>>,
+    stats_tasks: Rc<RefCell<HashMap<String, JoinHandle<()>>>>,
+    profile_task: Rc<RefCell<Option<JoinHandle<()>>>>,
+}
+
+impl Subscriptions {
+    pub fn new(event_tx: mpsc::Sender<Vec<u8>>) -> Self {
+        Self {
+            event_tx,
+            stats_tasks: Rc::default(),
+            profile_task: Rc::default(),
+        }
+    }
+
+    pub async fn subscribe_stats(
+        &self,
+        handler: &Handler,
+        id: &str,
+        interval_ms: u64,
+    ) -> anyhow::Result<()> {
+        // Make sure the GPU exists before starting the task
+        handler.get_gpu_stats(id).await?;
+
+        let handler = handler.clone();
+        let event_tx = self.event_tx.clone();
+        let gpu_id = id.to_owned();
+        let period = Duration::from_millis(interval_ms.max(MIN_STATS_INTERVAL_MS));
+
+        let task = tokio::task::spawn_local(async move {
+            let mut interval = interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let stats = match handler.get_gpu_stats(&gpu_id).await {
+                    Ok(stats) => stats,
+                    Err(err) => {
+                        error!("could not get stats for subscription: {err:#}");
+                        break;
+                    }
+                };
+
+                let event = Event::DeviceStats {
+                    id: gpu_id.clone(),
+                    stats,
+                };
+                let payload = serde_json::to_vec(&event).expect("Event is always serializable");
+
+                if event_tx.send(payload).await.is_err() {
+                    debug!("connection closed, stopping stats subscription");
+                    break;
+                }
+            }
+        });
+
+        if let Some(old_task) = self.stats_tasks.borrow_mut().insert(id.to_owned(), task) {
+            old_task.abort();
+        }
+
+        Ok(())
+    }
+
+    pub fn unsubscribe_stats(&self, id: &str) -> anyhow::Result<()> {
+        self.stats_tasks
+            .borrow_mut()
+            .remove(id)
+            .with_context(|| format!("Not subscribed to stats of '{id}'"))?
+            .abort();
+        Ok(())
+    }
+
+    /// Sends the current profile, and then the new one every time it is switched
+    pub fn subscribe_profile(&self, handler: &Handler) {
+        let mut profile_rx = handler.subscribe_current_profile();
+        let event_tx = self.event_tx.clone();
+
+        let task = tokio::task::spawn_local(async move {
+            loop {
+                let profile = profile_rx.borrow_and_update().clone();
+                let event = Event::ProfileChanged {
+                    profile: profile.as_deref().map(str::to_owned),
+                };
+                let payload = serde_json::to_vec(&event).expect("Event is always serializable");
+
+                if event_tx.send(payload).await.is_err() {
+                    debug!("connection closed, stopping profile subscription");
+                    break;
+                }
+                if profile_rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        if let Some(old_task) = self.profile_task.borrow_mut().replace(task) {
+            old_task.abort();
+        }
+    }
+
+    pub fn clear(&self) {
+        for (_, task) in self.stats_tasks.borrow_mut().drain() {
+            task.abort();
+        }
+        if let Some(task) = self.profile_task.borrow_mut().take() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Subscriptions;
+    use crate::tests::test_handler;
+    use lact_schema::Event;
+    use std::time::{Duration, Instant};
+    use tokio::{sync::mpsc, task::LocalSet, time::timeout};
+
+    #[tokio::test]
+    async fn stats_subscription() {
+        LocalSet::new()
+            .run_until(async {
+                let handler = test_handler().await;
+                let gpu_id = handler.list_devices().await[0].id.clone();
+
+                let (event_tx, mut event_rx) = mpsc::channel(16);
+                let subscriptions = Subscriptions::new(event_tx);
+                subscriptions
+                    .subscribe_stats(&handler, &gpu_id, 100)
+                    .await
+                    .unwrap();
+
+                // The first frame is sent right away, the rest once per interval
+                let start = Instant::now();
+                for _ in 0..3 {
+                    let payload = event_rx.recv().await.unwrap();
+                    match serde_json::from_slice(&payload).unwrap() {
+                        Event::DeviceStats { id, .. } => assert_eq!(id, gpu_id),
+                        event @ Event::ProfileChanged { .. } => {
+                            panic!("unexpected event {event:?}")
+                        }
+                    }
+                }
+                let elapsed = start.elapsed();
+                assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+                assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+
+                subscriptions.unsubscribe_stats(&gpu_id).unwrap();
+                assert!(subscriptions.unsubscribe_stats(&gpu_id).is_err());
+
+                while event_rx.try_recv().is_ok() {}
+                assert!(timeout(Duration::from_millis(300), event_rx.recv())
+                    .await
+                    .is_err());
+            })
+            .await;
+    }
+}
@@ -22,7 +22,7 @@ pub const PP_FEATURE_MASK_PATH: &str = "/sys/module/amdgpu/parameters/ppfeaturem
 pub const MODULE_CONF_PATH: &str = "/etc/modprobe.d/99-amdgpu-overdrive.conf";
 pub const DAEMON_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub async fn info() -> anyhow::Result<SystemInfo> {
+pub async fn info(config_immutable: bool) -> anyhow::Result<SystemInfo> {
     let version = DAEMON_VERSION.to_owned();
     let profile = if cfg!(debug_assertions) {
         "debug"
@@ -53,6 +53,7 @@ pub async fn info() -> anyhow::Result<SystemInfo> {
         kernel_version,
         amdgpu_overdrive_enabled,
         commit: Some(GIT_COMMIT.to_owned()),
+        config_immutable,
     })
 }
 
@@ -0,0 +1,158 @@
+use crate::config::TlsConfig;
+use anyhow::{bail, Context};
+use std::{path::Path, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+pub fn acceptor(config: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let certs = load_certs(&config.cert)?;
+    let key = PrivateKeyDer::from_pem_file(&config.key).with_context(|| {
+        format!(
+            "Could not read TLS private key from {}",
+            config.key.display()
+        )
+    })?;
+
+    let builder = ServerConfig::builder();
+    let builder = match &config.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca)? {
+                roots
+                    .add(cert)
+                    .context("Could not add client CA certificate")?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .context("Could not create client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let server_config = builder
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate or key")?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("Could not read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::acceptor;
+    use crate::config::TlsConfig;
+    use std::{path::PathBuf, sync::Arc};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{
+            pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+            ClientConfig, RootCertStore,
+        },
+        TlsConnector,
+    };
+
+    /// The server and client certificates are signed by `ca.pem` and valid for `localhost`,
+    /// `other-ca.pem` is an unrelated CA
+    fn cert_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/tests/tls")
+            .join(name)
+    }
+
+    fn tls_config(require_client_cert: bool) -> TlsConfig {
+        TlsConfig {
+            cert: cert_path("server.pem"),
+            key: cert_path("server.key"),
+            client_ca: require_client_cert.then(|| cert_path("ca.pem")),
+        }
+    }
+
+    fn connector(ca: &str, client_cert: bool) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(cert_path(ca)).unwrap())
+            .unwrap();
+
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = if client_cert {
+            builder
+                .with_client_auth_cert(
+                    vec![CertificateDer::from_pem_file(cert_path("client.pem")).unwrap()],
+                    PrivateKeyDer::from_pem_file(cert_path("client.key")).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    /// Connects over an in-memory stream and echoes a message, returning the results of the server and the client
+    async fn exchange(
+        config: &TlsConfig,
+        connector: TlsConnector,
+    ) -> (anyhow::Result<()>, anyhow::Result<()>) {
+        let acceptor = acceptor(config).unwrap();
+        let (client_stream, server_stream) = duplex(64 * 1024);
+
+        let server = async {
+            let mut stream = acceptor.accept(server_stream).await?;
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await?;
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+            anyhow::Ok(())
+        };
+        let client = async {
+            let server_name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(server_name, client_stream).await?;
+            stream.write_all(b"ping").await?;
+            stream.flush().await?;
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"ping");
+            anyhow::Ok(())
+        };
+        tokio::join!(server, client)
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let (server, client) = exchange(&tls_config(false), connector("ca.pem", false)).await;
+        server.unwrap();
+        client.unwrap();
+    }
+
+    #[tokio::test]
+    async fn untrusted_cert_is_rejected() {
+        let (_, client) = exchange(&tls_config(false), connector("other-ca.pem", false)).await;
+        let err = format!("{:#}", client.unwrap_err());
+        assert!(err.contains("UnknownIssuer"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn client_cert() {
+        let (server, client) = exchange(&tls_config(true), connector("ca.pem", true)).await;
+        server.unwrap();
+        client.unwrap();
+
+        let (server, client) = exchange(&tls_config(true), connector("ca.pem", false)).await;
+        assert!(server.is_err());
+        assert!(client.is_err());
+    }
+}
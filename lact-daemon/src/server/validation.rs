@@ -0,0 +1,397 @@
+use super::{gpu_controller::DynGpuController, profiles::CompiledRule};
+use crate::config::{self, Config};
+use amdgpu_sysfs::gpu_handle::{
+    fan_control::FanInfo, overdrive::ClocksTable as _, PerformanceLevel,
+};
+use indexmap::IndexMap;
+use lact_schema::{ClocksTable, ConfigIssue, ConfigValidation, FanControlMode, PmfwInfo};
+use std::{collections::BTreeMap, fmt::Display};
+
+/// Checks a config file against the GPUs of the system without applying it
+pub async fn validate_config(
+    yaml: &str,
+    controllers: &BTreeMap<String, DynGpuController>,
+) -> ConfigValidation {
+    let mut validation = ConfigValidation::default();
+
+    let parsed = serde_yaml::from_str(yaml)
+        .map_err(anyhow::Error::from)
+        .and_then(Config::from_value);
+    let config = match parsed {
+        Ok(config) => config,
+        Err(err) => {
+            push_issue(
+                &mut validation.errors,
+                String::new(),
+                format!("Could not parse config: {err:#}"),
+            );
+            return validation;
+        }
+    };
+
+    if let Some(profile) = &config.current_profile {
+        if !config.profiles.contains_key(profile) {
+            push_issue(
+                &mut validation.errors,
+                "current_profile".to_owned(),
+                format!("Profile '{profile}' does not exist"),
+            );
+        }
+    }
+
+    let mut checker = Checker {
+        controllers,
+        validation: &mut validation,
+    };
+    checker.check_gpus("gpus", &config.default_profile().gpus);
+    for (name, profile) in &config.profiles {
+        checker.check_gpus(&format!("profiles.{name}.gpus"), &profile.gpus);
+        if let Some(Err(err)) = profile.rule.as_ref().map(CompiledRule::new) {
+            checker.error(format!("profiles.{name}.rule"), format!("{err:#}"));
+        }
+    }
+
+    // Only the settings of the active profile would be written to the hardware
+    if let Ok(active_gpus) = config.gpus() {
+        let prefix = match &config.current_profile {
+            Some(name) => format!("profiles.{name}.gpus"),
+            None => "gpus".to_owned(),
+        };
+
+        for (id, controller) in controllers {
+            let Some(gpu_config) = config::find_gpu(active_gpus, id) else {
+                continue;
+            };
+            let path = format!("{prefix}.{id}");
+            match controller.plan_config(gpu_config).await {
+                Ok(writes) => {
+                    validation.writes.insert(id.clone(), writes);
+                }
+                Err(err) => {
+                    let message = format!("{err:#}");
+                    // Most failures are already reported by the checks above
+                    if !validation
+                        .errors
+                        .iter()
+                        .any(|issue| issue.message == message)
+                    {
+                        push_issue(&mut validation.errors, path, message);
+                    }
+                }
+            }
+        }
+    }
+
+    validation
+}
+
+fn push_issue(issues: &mut Vec<ConfigIssue>, path: String, message: impl Display) {
+    issues.push(ConfigIssue {
+        path,
+        message: message.to_string(),
+    });
+}
+
+struct Checker<'a> {
+    controllers: &'a BTreeMap<String, DynGpuController>,
+    validation: &'a mut ConfigValidation,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, path: String, message: impl Display) {
+        push_issue(&mut self.validation.errors, path, message);
+    }
+
+    fn warning(&mut self, path: String, message: impl Display) {
+        push_issue(&mut self.validation.warnings, path, message);
+    }
+
+    fn check_gpus(&mut self, prefix: &str, gpus: &IndexMap<String, config::Gpu>) {
+        for (key, gpu_config) in gpus {
+            let path = format!("{prefix}.{key}");
+            if config::is_selector(key) {
+                let controllers = self.controllers;
+                let mut matched = false;
+                for id in controllers.keys() {
+                    if config::selector_matches(key, id) {
+                        matched = true;
+                        self.check_gpu(&path, id, gpu_config);
+                    }
+                }
+                if !matched {
+                    self.warning(path, "No GPU matching this selector was found");
+                }
+            } else if self.controllers.contains_key(key) {
+                self.check_gpu(&path, key, gpu_config);
+            } else {
+                self.error(path, "No GPU with this id was found");
+            }
+        }
+    }
+
+    fn check_gpu(&mut self, path: &str, id: &str, gpu_config: &config::Gpu) {
+        let controllers = self.controllers;
+        let controller = &controllers[id];
+
+        let stats = controller.get_stats(None);
+
+        if let Some(cap) = gpu_config.power_cap {
+            let path = format!("{path}.power_cap");
+            match (stats.power.cap_min, stats.power.cap_max) {
+                (None, None) => self.warning(path, "The GPU does not report a power cap range"),
+                (min, max) => {
+                    if min.is_some_and(|min| cap < min) || max.is_some_and(|max| cap > max) {
+                        self.error(
+                            path,
+                            format!(
+                                "Power cap {cap}W is outside of the supported range {}",
+                                format_range(min, max, "W")
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        self.check_clocks(path, controller, &gpu_config.clocks_configuration);
+        self.check_pmfw(path, &stats.fan.pmfw_info, gpu_config);
+
+        if gpu_config.fan_control_enabled {
+            match &gpu_config.fan_control_settings {
+                Some(settings) => {
+                    if settings.mode == FanControlMode::Curve && settings.curve.0.is_empty() {
+                        self.error(
+                            format!("{path}.fan_control_settings.curve"),
+                            "Cannot use empty fan curve",
+                        );
+                    }
+                    if !(0.0..=1.0).contains(&settings.static_speed) {
+                        self.error(
+                            format!("{path}.fan_control_settings.static_speed"),
+                            "Static fan speed has to be between 0 and 1",
+                        );
+                    }
+                }
+                None => self.error(
+                    format!("{path}.fan_control_enabled"),
+                    "Trying to enable fan control with no settings provided",
+                ),
+            }
+        }
+
+        if gpu_config.performance_level != Some(PerformanceLevel::Manual) {
+            if gpu_config.power_profile_mode_index.is_some() {
+                self.error(
+                    format!("{path}.power_profile_mode_index"),
+                    "Performance level has to be set to `manual` to use power profile modes",
+                );
+            }
+            if !gpu_config.power_states.is_empty() {
+                self.error(
+                    format!("{path}.power_states"),
+                    "Performance level has to be set to `manual` to configure power states",
+                );
+            }
+        }
+    }
+
+    fn check_clocks(
+        &mut self,
+        path: &str,
+        controller: &DynGpuController,
+        clocks: &config::ClocksConfiguration,
+    ) {
+        if *clocks == config::ClocksConfiguration::default() {
+            return;
+        }
+
+        let table = match controller.get_clocks_info(None) {
+            Ok(info) => info.table,
+            Err(err) => {
+                self.error(
+                    path.to_owned(),
+                    format!("Could not read the clocks of the GPU: {err:#}"),
+                );
+                return;
+            }
+        };
+
+        let values = [
+            ("min_core_clock", clocks.min_core_clock),
+            ("max_core_clock", clocks.max_core_clock),
+            ("min_memory_clock", clocks.min_memory_clock),
+            ("max_memory_clock", clocks.max_memory_clock),
+            ("min_voltage", clocks.min_voltage),
+            ("max_voltage", clocks.max_voltage),
+        ];
+
+        match table {
+            Some(ClocksTable::Amd(table)) => {
+                let ranges = [
+                    table.get_min_sclk_range(),
+                    table.get_max_sclk_range(),
+                    table.get_min_mclk_range(),
+                    table.get_max_mclk_range(),
+                    table.get_min_voltage_range(),
+                    table.get_max_voltage_range(),
+                ];
+                for ((name, value), range) in values.into_iter().zip(ranges) {
+                    if let Some(value) = value {
+                        let range = range.unwrap_or_default();
+                        self.check_range(format!("{path}.{name}"), value, range.min, range.max);
+                    }
+                }
+            }
+            Some(ClocksTable::Nvidia(table)) => {
+                let ranges = [
+                    table.gpu_clock_range,
+                    table.gpu_clock_range,
+                    table.vram_clock_range,
+                    table.vram_clock_range,
+                ];
+                for ((name, value), range) in values.into_iter().zip(ranges) {
+                    if let Some(value) = value {
+                        let (min, max) = range.unzip();
+                        self.check_range(
+                            format!("{path}.{name}"),
+                            i64::from(value),
+                            min.map(i64::from),
+                            max.map(i64::from),
+                        );
+                    }
+                }
+
+                for (name, offsets, supported) in [
+                    (
+                        "gpu_clock_offsets",
+                        &clocks.gpu_clock_offsets,
+                        &table.gpu_offsets,
+                    ),
+                    (
+                        "mem_clock_offsets",
+                        &clocks.mem_clock_offsets,
+                        &table.mem_offsets,
+                    ),
+                ] {
+                    for (pstate, offset) in offsets {
+                        let path = format!("{path}.{name}.{pstate}");
+                        match supported.get(pstate) {
+                            Some(range) => {
+                                self.check_range(path, *offset, Some(range.min), Some(range.max));
+                            }
+                            None => self.error(
+                                path,
+                                format!("The GPU does not support offsets for pstate {pstate}"),
+                            ),
+                        }
+                    }
+                }
+            }
+            Some(ClocksTable::Intel(table)) => {
+                let to_i32 = |freq: Option<u64>| freq.and_then(|freq| i32::try_from(freq).ok());
+                let (min, max) = (to_i32(table.rpn_freq), to_i32(table.rp0_freq));
+                for (name, value) in &values[..2] {
+                    if let Some(value) = value {
+                        self.check_range(format!("{path}.{name}"), *value, min, max);
+                    }
+                }
+            }
+            None => self.warning(
+                path.to_owned(),
+                "The GPU does not support clock configuration, clock settings will be ignored",
+            ),
+        }
+    }
+
+    fn check_range<T: PartialOrd + Display + Copy>(
+        &mut self,
+        path: String,
+        value: T,
+        min: Option<T>,
+        max: Option<T>,
+    ) {
+        if min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max) {
+            self.error(
+                path,
+                format!(
+                    "Value {value} is outside of the supported range {}",
+                    format_range(min, max, "")
+                ),
+            );
+        }
+    }
+
+    fn check_pmfw(&mut self, path: &str, info: &PmfwInfo, gpu_config: &config::Gpu) {
+        let options = &gpu_config.pmfw_options;
+        // These are only applied when the fan is not controlled by LACT
+        let fan_options = [
+            (
+                "acoustic_limit",
+                options.acoustic_limit,
+                info.acoustic_limit,
+            ),
+            (
+                "acoustic_target",
+                options.acoustic_target,
+                info.acoustic_target,
+            ),
+            (
+                "target_temperature",
+                options.target_temperature,
+                info.target_temp,
+            ),
+            ("minimum_pwm", options.minimum_pwm, info.minimum_pwm),
+        ];
+        for (name, value, info) in fan_options {
+            let Some(value) = value else {
+                continue;
+            };
+            let path = format!("{path}.pmfw_options.{name}");
+            if gpu_config.fan_control_enabled {
+                self.warning(path, "Ignored while custom fan control is enabled");
+            } else {
+                match info {
+                    Some(info) => self.check_fan_info(path, value, info),
+                    None => self.error(path, "Not supported by the GPU"),
+                }
+            }
+        }
+
+        if options.zero_rpm.is_some() && info.zero_rpm_enable.is_none() {
+            self.warning(
+                format!("{path}.pmfw_options.zero_rpm"),
+                "Not supported by the GPU, will be ignored",
+            );
+        }
+        if let Some(value) = options.zero_rpm_threshold {
+            let path = format!("{path}.pmfw_options.zero_rpm_threshold");
+            match info.zero_rpm_temperature {
+                Some(info) => self.check_fan_info(path, value, info),
+                None => self.warning(path, "Not supported by the GPU, will be ignored"),
+            }
+        }
+    }
+
+    fn check_fan_info(&mut self, path: String, value: u32, info: FanInfo) {
+        match info.allowed_range {
+            Some((min, max)) => {
+                if !(min..=max).contains(&value) {
+                    self.error(
+                        path,
+                        format!("Value {value} is outside of the supported range {min}-{max}"),
+                    );
+                }
+            }
+            None => self.error(path, "The GPU does not allow changing this value"),
+        }
+    }
+}
+
+fn format_range<T: Display>(min: Option<T>, max: Option<T>, unit: &str) -> String {
+    match (min, max) {
+        (Some(min), Some(max)) => format!("{min}-{max}{unit}"),
+        (Some(min), None) => format!("of at least {min}{unit}"),
+        (None, Some(max)) => format!("of at most {max}{unit}"),
+        (None, None) => "(unknown)".to_owned(),
+    }
+}
@@ -2,23 +2,40 @@ use anyhow::{anyhow, bail, Context};
 use indexmap::{map::Entry, IndexMap};
 use lact_schema::{GpuPciInfo, VulkanDriverInfo, VulkanInfo};
 use serde::Deserialize;
-use std::fs;
+use std::{collections::BTreeMap, fs};
 use tempfile::tempdir;
-use tokio::process::Command;
+use tokio::{process::Command, sync::Mutex};
 use tracing::trace;
 
 include!(concat!(env!("OUT_DIR"), "/vulkan_constants.rs"));
 
-#[cfg_attr(test, allow(unreachable_code, unused_variables))]
+/// Vulkan info by the vendor and device id. Device info can be requested by any user on the monitor socket,
+/// so `vulkaninfo` is only run once per device instead of on every request.
+static VULKAN_INFO_CACHE: Mutex<BTreeMap<(u32, u32), VulkanInfo>> =
+    Mutex::const_new(BTreeMap::new());
+
 pub async fn get_vulkan_info(pci_info: &GpuPciInfo) -> anyhow::Result<VulkanInfo> {
+    let vendor_id = u32::from_str_radix(&pci_info.device_pci_info.vendor_id, 16)?;
+    let device_id = u32::from_str_radix(&pci_info.device_pci_info.model_id, 16)?;
+
+    // Held while reading, so that concurrent requests wait for the first one instead of running `vulkaninfo` again
+    let mut cache = VULKAN_INFO_CACHE.lock().await;
+    if let Some(info) = cache.get(&(vendor_id, device_id)) {
+        return Ok(info.clone());
+    }
+
+    let info = read_vulkan_info(vendor_id, device_id).await?;
+    cache.insert((vendor_id, device_id), info.clone());
+    Ok(info)
+}
+
+#[cfg_attr(test, allow(unreachable_code, unused_variables))]
+async fn read_vulkan_info(vendor_id: u32, device_id: u32) -> anyhow::Result<VulkanInfo> {
     #[cfg(test)]
     return Ok(VulkanInfo::default());
 
     let workdir = tempdir().context("Could not create temp folder")?;
 
-    trace!("Reading vulkan info");
-    let vendor_id = u32::from_str_radix(&pci_info.device_pci_info.vendor_id, 16)?;
-    let device_id = u32::from_str_radix(&pci_info.device_pci_info.model_id, 16)?;
     trace!("Reading vulkan info");
 
     let summary_output = Command::new("vulkaninfo")
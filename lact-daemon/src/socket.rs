@@ -3,38 +3,47 @@ use nix::{
     sys::stat::{umask, Mode},
     unistd::{chown, getuid, Gid, Group},
 };
-use std::{fs, path::PathBuf, str::FromStr};
+use std::{
+    fs::{self, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::net::UnixListener;
 use tracing::{debug, info};
 
 pub fn get_socket_path() -> PathBuf {
+    socket_dir().join("lactd.sock")
+}
+
+/// Path of the socket which only allows read-only requests, and is accessible by all users
+pub fn get_monitor_socket_path() -> PathBuf {
+    socket_dir().join("lactd-monitor.sock")
+}
+
+fn socket_dir() -> PathBuf {
     let uid = getuid();
     if uid.is_root() {
-        PathBuf::from_str("/var/run/lactd.sock").unwrap()
+        PathBuf::from_str("/var/run").unwrap()
     } else {
-        PathBuf::from_str(&format!("/var/run/user/{uid}/lactd.sock")).unwrap()
+        PathBuf::from_str(&format!("/var/run/user/{uid}")).unwrap()
     }
 }
 
 pub fn cleanup() {
-    let socket_path = get_socket_path();
-
-    if socket_path.exists() {
-        fs::remove_file(socket_path).expect("failed to remove socket");
+    for socket_path in [get_socket_path(), get_monitor_socket_path()] {
+        if socket_path.exists() {
+            fs::remove_file(socket_path).expect("failed to remove socket");
+        }
     }
     debug!("removed socket");
 }
 
-pub fn listen(admin_groups: &[String]) -> anyhow::Result<UnixListener> {
+/// When `world_accessible` is set, the socket can be opened by all users,
+/// and access has to be checked per connection instead.
+pub fn listen(admin_groups: &[String], world_accessible: bool) -> anyhow::Result<UnixListener> {
     let socket_path = get_socket_path();
-
-    if socket_path.exists() {
-        return Err(anyhow!(
-            "Socket {socket_path:?} already exists. \
-            This probably means that another instance of lact-daemon is currently running. \
-            If you are sure that this is not the case, please remove the file"
-        ));
-    }
+    ensure_not_in_use(&socket_path)?;
 
     let socket_mask = Mode::S_IXUSR | Mode::S_IXGRP | Mode::S_IRWXO;
     umask(socket_mask);
@@ -42,11 +51,36 @@ pub fn listen(admin_groups: &[String]) -> anyhow::Result<UnixListener> {
     let listener = UnixListener::bind(&socket_path)?;
 
     chown(&socket_path, None, Some(socket_gid(admin_groups)))?;
+    if world_accessible {
+        fs::set_permissions(&socket_path, Permissions::from_mode(0o666))?;
+    }
 
     info!("listening on {socket_path:?}");
     Ok(listener)
 }
 
+pub fn listen_monitor() -> anyhow::Result<UnixListener> {
+    let socket_path = get_monitor_socket_path();
+    ensure_not_in_use(&socket_path)?;
+
+    let listener = UnixListener::bind(&socket_path)?;
+    fs::set_permissions(&socket_path, Permissions::from_mode(0o666))?;
+
+    info!("listening for read-only connections on {socket_path:?}");
+    Ok(listener)
+}
+
+fn ensure_not_in_use(socket_path: &Path) -> anyhow::Result<()> {
+    if socket_path.exists() {
+        return Err(anyhow!(
+            "Socket {socket_path:?} already exists. \
+            This probably means that another instance of lact-daemon is currently running. \
+            If you are sure that this is not the case, please remove the file"
+        ));
+    }
+    Ok(())
+}
+
 fn socket_gid(admin_groups: &[String]) -> Gid {
     if getuid().is_root() {
         // Check if the group exists
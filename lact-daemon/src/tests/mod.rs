@@ -6,7 +6,8 @@ use crate::{
 };
 use insta::{assert_debug_snapshot, assert_json_snapshot};
 use mock_fs::MockSysfs;
-use std::{fs, path::PathBuf, sync::OnceLock};
+use pciid_parser::Database;
+use std::{collections::HashMap, fs, path::PathBuf, sync::OnceLock};
 use tempfile::tempdir;
 use tokio::task::LocalSet;
 
@@ -17,6 +18,18 @@ fn init_tracing() {
     });
 }
 
+/// Handler with a single GPU from the test data, for tests that only read from it
+pub(crate) async fn test_handler() -> Handler {
+    let device_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/tests/data/amd/rx6600");
+    let pci_db = Database {
+        vendors: HashMap::new(),
+        classes: HashMap::new(),
+    };
+    Handler::with_base_path(&device_dir, Config::default(), &pci_db)
+        .await
+        .unwrap()
+}
+
 #[tokio::test]
 async fn snapshot_everything() {
     init_tracing();
@@ -109,3 +122,52 @@ async fn apply_settings() {
 
     local_set.await;
 }
+
+#[tokio::test]
+async fn validate_config() {
+    init_tracing();
+
+    let test_data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/tests/data");
+    let pci_db = pciid_parser::Database::read().unwrap();
+
+    for vendor_dir in fs::read_dir(test_data_dir).unwrap().flatten() {
+        for device_dir in fs::read_dir(vendor_dir.path()).unwrap().flatten() {
+            if let Ok(raw_gpu_config) = fs::read_to_string(device_dir.path().join("config.yaml")) {
+                let test_key = format!(
+                    "validate_config/{}/{}",
+                    vendor_dir.file_name().to_string_lossy(),
+                    device_dir.file_name().to_string_lossy()
+                );
+                let gpu_config: config::Gpu = serde_yaml::from_str(&raw_gpu_config).unwrap();
+
+                let handler =
+                    Handler::with_base_path(&device_dir.path(), Config::default(), &pci_db)
+                        .await
+                        .unwrap();
+                let gpu_id = handler.list_devices().await[0].id.clone();
+
+                let mut config = Config::default();
+                config.gpus_mut().unwrap().insert(gpu_id, gpu_config);
+                let mut profile = config::Profile::default();
+                profile.gpus.insert(
+                    "1002:0000-0000:0000-0000:00:00.0".to_owned(),
+                    config::Gpu::default(),
+                );
+                config.profiles.insert("Missing GPU".into(), profile);
+
+                let mut validation = handler
+                    .validate_config(&serde_yaml::to_string(&config).unwrap())
+                    .await;
+                for write in validation.writes.values_mut().flatten() {
+                    if let Some(path) = write
+                        .target
+                        .strip_prefix(device_dir.path().to_str().unwrap())
+                    {
+                        write.target = path.trim_start_matches('/').to_owned();
+                    }
+                }
+                assert_json_snapshot!(test_key, validation);
+            }
+        }
+    }
+}
@@ -11,6 +11,7 @@ use crate::{APP_ID, GUI_VERSION};
 use anyhow::{anyhow, Context};
 use apply_revealer::{ApplyRevealer, ApplyRevealerMsg};
 use confirmation_dialog::ConfirmationDialog;
+use futures::StreamExt;
 use graphs_window::{GraphsWindow, GraphsWindowMsg};
 use gtk::{
     glib::{self, clone, ControlFlow},
@@ -24,11 +25,11 @@ use gtk::{
 use header::{
     profile_rule_window::ProfileRuleWindowMsg, Header, HeaderMsg, PROFILE_RULE_WINDOW_BROKER,
 };
-use lact_client::{ConnectionStatusMsg, DaemonClient};
+use lact_client::{ConnectionStatusMsg, DaemonClient, TlsOptions};
 use lact_schema::{
     args::GuiArgs,
     request::{ConfirmCommand, SetClocksCommand},
-    FanOptions, GIT_COMMIT,
+    ErrorKind, FanOptions, SaveStatus, GIT_COMMIT,
 };
 use msg::AppMsg;
 use pages::{
@@ -45,12 +46,13 @@ use relm4::{
 };
 use std::{
     os::unix::net::UnixStream,
+    pin::pin,
     rc::Rc,
     sync::{
         atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tracing::{debug, error, info, trace, warn};
 
@@ -58,6 +60,7 @@ pub(crate) static APP_BROKER: MessageBroker<AppMsg> = MessageBroker::new();
 static ERROR_WINDOW_COUNT: AtomicU32 = AtomicU32::new(0);
 
 const STATS_POLL_INTERVAL_MS: u64 = 250;
+const PROFILE_RESUBSCRIBE_INTERVAL_MS: u64 = 1000;
 
 pub struct AppModel {
     daemon_client: DaemonClient,
@@ -129,10 +132,23 @@ impl AsyncComponent for AppModel {
         root: Self::Root,
         sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
+        let use_tls = args.tls || args.tls_ca_cert.is_some() || args.tls_client_cert.is_some();
+        let tls_options = TlsOptions {
+            ca_cert_path: args.tls_ca_cert,
+            client_cert_path: args.tls_client_cert,
+            client_key_path: args.tls_client_key,
+        };
+
         let (daemon_client, conn_err) = match args.tcp_address {
             Some(remote_addr) => {
                 info!("establishing connection to {remote_addr}");
-                match DaemonClient::connect_tcp(&remote_addr).await {
+                let auth_token = args.tcp_auth_token.as_deref();
+                let conn_result = if use_tls {
+                    DaemonClient::connect_tls(&remote_addr, &tls_options, auth_token).await
+                } else {
+                    DaemonClient::connect_tcp(&remote_addr, auth_token).await
+                };
+                match conn_result {
                     Ok(conn) => (conn, None),
                     Err(err) => {
                         error!("TCP connection error: {err:#}");
@@ -213,7 +229,9 @@ impl AsyncComponent for AppModel {
             }
         ));
 
-        let graphs_window = GraphsWindow::builder().launch(()).detach();
+        let graphs_window = GraphsWindow::builder()
+            .launch(())
+            .forward(sender.input_sender(), |msg| msg);
 
         let model = AppModel {
             daemon_client,
@@ -242,6 +260,7 @@ impl AsyncComponent for AppModel {
         sender.input(AppMsg::ReloadProfiles {
             include_state: false,
         });
+        start_profile_update_loop(model.daemon_client.clone(), model.header.sender().clone());
 
         AsyncComponentParts { model, widgets }
     }
@@ -360,6 +379,22 @@ impl AppModel {
                     .await?;
                 sender.input(AppMsg::ReloadData { full: false });
             }
+            AppMsg::FetchStatsHistory { seconds } => {
+                let gpu_id = self.current_gpu_id()?;
+                let since = SystemTime::now()
+                    .checked_sub(Duration::from_secs(seconds))
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|since| since.as_millis() as u64);
+
+                match self
+                    .daemon_client
+                    .get_device_stats_history(&gpu_id, since, None)
+                    .await
+                {
+                    Ok(history) => self.graphs_window.emit(GraphsWindowMsg::History(history)),
+                    Err(err) => debug!("could not fetch stats history: {err:#}"),
+                }
+            }
             AppMsg::ShowGraphsWindow => {
                 self.graphs_window.emit(GraphsWindowMsg::Show);
             }
@@ -554,7 +589,6 @@ impl AppModel {
             gpu_id.to_owned(),
             self.daemon_client.clone(),
             sender,
-            self.header.sender().clone(),
         ));
 
         Ok(())
@@ -588,7 +622,9 @@ impl AppModel {
             .set_power_profile_mode(&gpu_id, None, vec![])
             .await
             .context("Could not set default power profile mode")?;
-        self.daemon_client
+        // Whether the settings are saved is the same for all of the changes
+        let mut save_status = self
+            .daemon_client
             .confirm_pending_config(ConfirmCommand::Confirm)
             .await
             .context("Could not commit config")?;
@@ -599,7 +635,8 @@ impl AppModel {
                 .set_performance_level(&gpu_id, level)
                 .await
                 .context("Failed to set power profile")?;
-            self.daemon_client
+            save_status = self
+                .daemon_client
                 .confirm_pending_config(ConfirmCommand::Confirm)
                 .await
                 .context("Could not commit config")?;
@@ -619,7 +656,8 @@ impl AppModel {
                 .set_power_profile_mode(&gpu_id, mode_index, custom_heuristics)
                 .await
                 .context("Could not set active power profile mode")?;
-            self.daemon_client
+            save_status = self
+                .daemon_client
                 .confirm_pending_config(ConfirmCommand::Confirm)
                 .await
                 .context("Could not commit config")?;
@@ -642,7 +680,8 @@ impl AppModel {
                 .set_fan_control(opts)
                 .await
                 .context("Could not set fan control")?;
-            self.daemon_client
+            save_status = self
+                .daemon_client
                 .confirm_pending_config(ConfirmCommand::Confirm)
                 .await
                 .context("Could not commit config")?;
@@ -660,7 +699,8 @@ impl AppModel {
                 .await
                 .context("Could not set power states")?;
 
-            self.daemon_client
+            save_status = self
+                .daemon_client
                 .confirm_pending_config(ConfirmCommand::Confirm)
                 .await
                 .context("Could not commit config")?;
@@ -673,6 +713,8 @@ impl AppModel {
                 .await
                 .context("Could not commit clocks settings")?;
             self.ask_settings_confirmation(delay, root, sender).await;
+        } else if save_status == Some(SaveStatus::NotPersisted) {
+            show_not_persisted(root);
         }
 
         sender.input(AppMsg::ReloadData { full: false });
@@ -744,8 +786,10 @@ impl AppModel {
                 diag.close();
 
                 relm4::spawn_local(async move {
-                    if let Err(err) = daemon_client.confirm_pending_config(command).await {
-                        show_error(&window, &err);
+                    match daemon_client.confirm_pending_config(command).await {
+                        Ok(Some(SaveStatus::NotPersisted)) => show_not_persisted(&window),
+                        Ok(_) => (),
+                        Err(err) => show_error(&window, &err),
                     }
                     sender.input(AppMsg::ReloadData { full: false });
                 });
@@ -847,13 +891,17 @@ impl AppModel {
 }
 
 fn show_error(parent: &ApplicationWindow, err: &anyhow::Error) {
-    let text = format!("{err:?}")
+    let mut text = format!("{err:?}")
         .lines()
         .map(str::trim)
         .collect::<Vec<&str>>()
         .join("\n");
     warn!("{text}");
 
+    if let Some(hint) = error_hint(ErrorKind::of(err)) {
+        text = format!("{hint}\n\n{text}");
+    }
+
     let errors_count = ERROR_WINDOW_COUNT.load(Ordering::SeqCst);
     if errors_count > 2 {
         warn!("Not showing error window, too many already open");
@@ -875,6 +923,35 @@ fn show_error(parent: &ApplicationWindow, err: &anyhow::Error) {
     })
 }
 
+/// Shown after confirming settings when the daemon config is immutable
+fn show_not_persisted(parent: &ApplicationWindow) {
+    let diag = MessageDialog::builder()
+        .title("Settings not saved")
+        .message_type(MessageType::Warning)
+        .text("The settings were applied, but the daemon config is immutable, so they will be lost when the daemon restarts.")
+        .buttons(ButtonsType::Close)
+        .transient_for(parent)
+        .build();
+    diag.run_async(|diag, _| diag.close());
+}
+
+/// Explanation shown above the error details for errors that the user can act on
+fn error_hint(kind: ErrorKind) -> Option<&'static str> {
+    match kind {
+        ErrorKind::PendingConfirmation => Some(
+            "Another settings change is waiting to be confirmed. Confirm or revert it before applying new settings.",
+        ),
+        ErrorKind::PermissionDenied => Some(
+            "The daemon does not allow this connection to change settings. Check the `admin_groups` and `access_control` options in the daemon config.",
+        ),
+        ErrorKind::AuthenticationRequired => {
+            Some("The daemon requires an authentication token, set it with `--tcp-auth-token`.")
+        }
+        ErrorKind::Unsupported => Some("This is not supported by the GPU or the running daemon version."),
+        _ => None,
+    }
+}
+
 fn show_embedded_info(parent: &ApplicationWindow, err: anyhow::Error) {
     let error_text = format!("Error info: {err:#}\n\n");
 
@@ -931,31 +1008,79 @@ fn start_stats_update_loop(
     gpu_id: String,
     daemon_client: DaemonClient,
     sender: AsyncComponentSender<AppModel>,
-    header_sender: relm4::Sender<HeaderMsg>,
 ) -> glib::JoinHandle<()> {
     debug!("spawning new stats update task with {STATS_POLL_INTERVAL_MS}ms interval");
     let duration = Duration::from_millis(STATS_POLL_INTERVAL_MS);
     relm4::spawn_local(async move {
-        loop {
-            tokio::time::sleep(duration).await;
+        let mut stats_stream = match daemon_client.subscribe_stats(&gpu_id, duration).await {
+            Ok(stream) => Some(Box::pin(stream)),
+            Err(err) => {
+                info!("could not subscribe to stats updates, falling back to polling: {err:#}");
+                None
+            }
+        };
 
-            match daemon_client.get_device_stats(&gpu_id).await {
-                Ok(stats) => {
-                    sender.input(AppMsg::Stats(Arc::new(stats)));
+        loop {
+            if let Some(stream) = &mut stats_stream {
+                match stream.next().await {
+                    Some(stats) => {
+                        sender.input(AppMsg::Stats(Arc::new(stats)));
+                    }
+                    None => {
+                        warn!("stats subscription ended, falling back to polling");
+                        stats_stream = None;
+                    }
                 }
-                Err(err) => {
-                    error!("could not fetch stats: {err:#}");
+            } else {
+                tokio::time::sleep(duration).await;
+
+                match daemon_client.get_device_stats(&gpu_id).await {
+                    Ok(stats) => {
+                        sender.input(AppMsg::Stats(Arc::new(stats)));
+                    }
+                    Err(err) => {
+                        error!("could not fetch stats: {err:#}");
+                    }
                 }
             }
+        }
+    })
+}
 
-            match daemon_client.list_profiles(false).await {
-                Ok(profiles) => {
-                    let _ = header_sender.send(HeaderMsg::Profiles(Box::new(profiles)));
+/// Updates the profile list in the header whenever the daemon switches to a different profile
+fn start_profile_update_loop(
+    daemon_client: DaemonClient,
+    header_sender: relm4::Sender<HeaderMsg>,
+) -> glib::JoinHandle<()> {
+    relm4::spawn_local(async move {
+        let mut subscribed = false;
+        loop {
+            match daemon_client.subscribe_profile().await {
+                Ok(changes) => {
+                    subscribed = true;
+                    let mut changes = pin!(changes);
+                    while changes.next().await.is_some() {
+                        match daemon_client.list_profiles(false).await {
+                            Ok(profiles) => {
+                                let _ = header_sender.send(HeaderMsg::Profiles(Box::new(profiles)));
+                            }
+                            Err(err) => {
+                                error!("could not fetch profile info: {err:#}");
+                            }
+                        }
+                    }
+                    warn!("profile subscription ended, resubscribing");
+                }
+                // The daemon may be restarting
+                Err(err) if subscribed => {
+                    debug!("could not resubscribe to profile changes: {err:#}");
                 }
                 Err(err) => {
-                    error!("could not fetch profile info: {err:#}");
+                    info!("could not subscribe to profile changes, profiles switched by the daemon will not be shown: {err:#}");
+                    break;
                 }
             }
+            tokio::time::sleep(Duration::from_millis(PROFILE_RESUBSCRIBE_INTERVAL_MS)).await;
         }
     })
 }
@@ -1,7 +1,9 @@
 pub(crate) mod plot;
 
+use super::AppMsg;
+use chrono::{DateTime, Local, NaiveDateTime};
 use gtk::prelude::*;
-use lact_schema::DeviceStats;
+use lact_schema::{DeviceStats, DeviceStatsHistoryEntry};
 use plot::{Plot, PlotData};
 use relm4::{ComponentParts, ComponentSender, RelmWidgetExt};
 use std::sync::Arc;
@@ -14,6 +16,8 @@ pub struct GraphsWindow {
 #[derive(Debug)]
 pub enum GraphsWindowMsg {
     Stats(Arc<DeviceStats>),
+    /// Replaces the plotted data with stats recorded by the daemon
+    History(Vec<DeviceStatsHistoryEntry>),
     VramClockRatio(f64),
     Refresh,
     Show,
@@ -24,7 +28,7 @@ pub enum GraphsWindowMsg {
 impl relm4::Component for GraphsWindow {
     type Init = ();
     type Input = GraphsWindowMsg;
-    type Output = ();
+    type Output = AppMsg;
     type CommandOutput = ();
 
     view! {
@@ -124,7 +128,9 @@ impl relm4::Component for GraphsWindow {
         root: &Self::Root,
     ) {
         match msg {
-            GraphsWindowMsg::Refresh => {}
+            GraphsWindowMsg::Refresh => {
+                self.request_history(&sender);
+            }
             GraphsWindowMsg::Show => {
                 root.show();
             }
@@ -132,106 +138,160 @@ impl relm4::Component for GraphsWindow {
                 self.vram_clock_ratio = ratio;
             }
             GraphsWindowMsg::Stats(stats) => {
-                let mut temperature_plot = widgets.temperature_plot.data_mut();
-                let mut clockspeed_plot = widgets.clockspeed_plot.data_mut();
-                let mut power_plot = widgets.power_plot.data_mut();
-                let mut fan_plot = widgets.fan_plot.data_mut();
-
-                let throttling_plots =
-                    [&mut temperature_plot, &mut clockspeed_plot, &mut power_plot];
-                match &stats.throttle_info {
-                    Some(throttle_info) => {
-                        if throttle_info.is_empty() {
-                            for plot in throttling_plots {
-                                plot.push_throttling("No", false);
-                            }
-                        } else {
-                            let type_text: Vec<String> = throttle_info
-                                .iter()
-                                .map(|(throttle_type, details)| {
-                                    format!("{throttle_type} ({})", details.join(", "))
-                                })
-                                .collect();
-
-                            let text = type_text.join(", ");
-
-                            for plot in throttling_plots {
-                                plot.push_throttling(&text, true);
-                            }
-                        }
-                    }
-                    None => {
-                        for plot in throttling_plots {
-                            plot.push_throttling("Unknown", false);
-                        }
+                self.push_stats(widgets, &stats, Local::now().naive_local());
+                self.trim_plots(widgets);
+                Self::queue_plots_draw(widgets);
+            }
+            // An empty history means it's disabled in the daemon, keep the locally collected stats
+            GraphsWindowMsg::History(history) if !history.is_empty() => {
+                Self::clear_plots(widgets);
+
+                for entry in history {
+                    let time = i64::try_from(entry.timestamp)
+                        .ok()
+                        .and_then(DateTime::from_timestamp_millis);
+                    if let Some(time) = time {
+                        let time = time.with_timezone(&Local).naive_local();
+                        self.push_stats(widgets, &entry.stats, time);
                     }
                 }
 
-                for (name, value) in &stats.temps {
-                    temperature_plot.push_line_series(name, value.current.unwrap_or(0.0) as f64);
-                }
+                self.trim_plots(widgets);
+                Self::queue_plots_draw(widgets);
+            }
+            GraphsWindowMsg::History(_) => {}
+            GraphsWindowMsg::Clear => {
+                Self::clear_plots(widgets);
+                Self::queue_plots_draw(widgets);
+                self.request_history(&sender);
+            }
+        }
 
-                if let Some(average) = stats.power.average {
-                    power_plot.push_line_series("Average", average);
-                }
-                if let Some(current) = stats.power.current {
-                    power_plot.push_line_series("Current", current);
-                }
-                if let Some(limit) = stats.power.cap_current {
-                    power_plot.push_line_series("Limit", limit);
-                }
+        self.update_view(widgets, sender);
+    }
+}
 
-                if let Some(point) = stats.clockspeed.gpu_clockspeed {
-                    clockspeed_plot.push_line_series("GPU (Avg)", point as f64);
-                }
-                if let Some(point) = stats.clockspeed.current_gfxclk {
-                    clockspeed_plot.push_line_series("GPU (Trgt)", point as f64);
+impl GraphsWindow {
+    fn push_stats(
+        &self,
+        widgets: &<Self as relm4::Component>::Widgets,
+        stats: &DeviceStats,
+        time: NaiveDateTime,
+    ) {
+        let mut temperature_plot = widgets.temperature_plot.data_mut();
+        let mut clockspeed_plot = widgets.clockspeed_plot.data_mut();
+        let mut power_plot = widgets.power_plot.data_mut();
+        let mut fan_plot = widgets.fan_plot.data_mut();
+
+        let throttling_plots = [&mut temperature_plot, &mut clockspeed_plot, &mut power_plot];
+        match &stats.throttle_info {
+            Some(throttle_info) => {
+                if throttle_info.is_empty() {
+                    for plot in throttling_plots {
+                        plot.push_throttling_with_time("No", false, time);
+                    }
+                } else {
+                    let type_text: Vec<String> = throttle_info
+                        .iter()
+                        .map(|(throttle_type, details)| {
+                            format!("{throttle_type} ({})", details.join(", "))
+                        })
+                        .collect();
+
+                    let text = type_text.join(", ");
+
+                    for plot in throttling_plots {
+                        plot.push_throttling_with_time(&text, true, time);
+                    }
                 }
-                if let Some(point) = stats.clockspeed.vram_clockspeed {
-                    clockspeed_plot.push_line_series("VRAM", point as f64 * self.vram_clock_ratio);
+            }
+            None => {
+                for plot in throttling_plots {
+                    plot.push_throttling_with_time("Unknown", false, time);
                 }
+            }
+        }
 
-                if let Some(max_speed) = stats.fan.speed_max {
-                    fan_plot.push_line_series("Maximum", max_speed as f64);
-                }
-                if let Some(min_speed) = stats.fan.speed_min {
-                    fan_plot.push_line_series("Minimum", min_speed as f64);
-                }
+        for (name, value) in &stats.temps {
+            temperature_plot.push_line_series_with_time(
+                name,
+                value.current.unwrap_or(0.0) as f64,
+                time,
+            );
+        }
 
-                if let Some(current_speed) = stats.fan.speed_current {
-                    fan_plot.push_line_series("Current", current_speed as f64);
-                }
+        if let Some(average) = stats.power.average {
+            power_plot.push_line_series_with_time("Average", average, time);
+        }
+        if let Some(current) = stats.power.current {
+            power_plot.push_line_series_with_time("Current", current, time);
+        }
+        if let Some(limit) = stats.power.cap_current {
+            power_plot.push_line_series_with_time("Limit", limit, time);
+        }
 
-                if let Some(pwm) = stats.fan.pwm_current {
-                    fan_plot.push_secondary_line_series(
-                        "Percentage",
-                        (pwm as f64 / u8::MAX as f64) * 100.0,
-                    );
-                }
+        if let Some(point) = stats.clockspeed.gpu_clockspeed {
+            clockspeed_plot.push_line_series_with_time("GPU (Avg)", point as f64, time);
+        }
+        if let Some(point) = stats.clockspeed.current_gfxclk {
+            clockspeed_plot.push_line_series_with_time("GPU (Trgt)", point as f64, time);
+        }
+        if let Some(point) = stats.clockspeed.vram_clockspeed {
+            clockspeed_plot.push_line_series_with_time(
+                "VRAM",
+                point as f64 * self.vram_clock_ratio,
+                time,
+            );
+        }
 
-                let time_period_seconds = self.time_period_seconds_adj.value() as i64;
-                temperature_plot.trim_data(time_period_seconds);
-                clockspeed_plot.trim_data(time_period_seconds);
-                power_plot.trim_data(time_period_seconds);
-                fan_plot.trim_data(time_period_seconds);
+        if let Some(max_speed) = stats.fan.speed_max {
+            fan_plot.push_line_series_with_time("Maximum", max_speed as f64, time);
+        }
+        if let Some(min_speed) = stats.fan.speed_min {
+            fan_plot.push_line_series_with_time("Minimum", min_speed as f64, time);
+        }
 
-                Self::queue_plots_draw(widgets);
-            }
-            GraphsWindowMsg::Clear => {
-                *widgets.temperature_plot.data_mut() = PlotData::default();
-                *widgets.clockspeed_plot.data_mut() = PlotData::default();
-                *widgets.power_plot.data_mut() = PlotData::default();
-                *widgets.fan_plot.data_mut() = PlotData::default();
+        if let Some(current_speed) = stats.fan.speed_current {
+            fan_plot.push_line_series_with_time("Current", current_speed as f64, time);
+        }
 
-                Self::queue_plots_draw(widgets);
-            }
+        if let Some(pwm) = stats.fan.pwm_current {
+            fan_plot.push_secondary_line_series_with_time(
+                "Percentage",
+                (pwm as f64 / u8::MAX as f64) * 100.0,
+                time,
+            );
         }
+    }
 
-        self.update_view(widgets, sender);
+    fn request_history(&self, sender: &ComponentSender<Self>) {
+        let seconds = self.time_period_seconds_adj.value() as u64;
+        sender
+            .output(AppMsg::FetchStatsHistory { seconds })
+            .unwrap();
+    }
+
+    fn trim_plots(&self, widgets: &<Self as relm4::Component>::Widgets) {
+        let time_period_seconds = self.time_period_seconds_adj.value() as i64;
+        widgets
+            .temperature_plot
+            .data_mut()
+            .trim_data(time_period_seconds);
+        widgets
+            .clockspeed_plot
+            .data_mut()
+            .trim_data(time_period_seconds);
+        widgets.power_plot.data_mut().trim_data(time_period_seconds);
+        widgets.fan_plot.data_mut().trim_data(time_period_seconds);
+    }
+
+    fn clear_plots(widgets: &<Self as relm4::Component>::Widgets) {
+        *widgets.temperature_plot.data_mut() = PlotData::default();
+        *widgets.clockspeed_plot.data_mut() = PlotData::default();
+        *widgets.power_plot.data_mut() = PlotData::default();
+        *widgets.fan_plot.data_mut() = PlotData::default();
     }
-}
 
-impl GraphsWindow {
     fn queue_plots_draw(widgets: &<Self as relm4::Component>::Widgets) {
         widgets.temperature_plot.queue_draw();
         widgets.clockspeed_plot.queue_draw();
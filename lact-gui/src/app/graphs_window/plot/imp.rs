@@ -99,27 +99,14 @@ pub struct PlotData {
 }
 
 impl PlotData {
-    pub fn push_line_series(&mut self, name: &str, point: f64) {
-        self.push_line_series_with_time(name, point, chrono::Local::now().naive_local());
-    }
-
-    pub fn push_secondary_line_series(&mut self, name: &str, point: f64) {
-        self.push_secondary_line_series_with_time(name, point, chrono::Local::now().naive_local());
-    }
-
-    pub(super) fn push_line_series_with_time(
-        &mut self,
-        name: &str,
-        point: f64,
-        time: NaiveDateTime,
-    ) {
+    pub fn push_line_series_with_time(&mut self, name: &str, point: f64, time: NaiveDateTime) {
         self.line_series
             .entry(name.to_owned())
             .or_default()
             .push((time.and_utc().timestamp_millis(), point));
     }
 
-    pub(super) fn push_secondary_line_series_with_time(
+    pub fn push_secondary_line_series_with_time(
         &mut self,
         name: &str,
         point: f64,
@@ -131,14 +118,9 @@ impl PlotData {
             .push((time.and_utc().timestamp_millis(), point));
     }
 
-    pub fn push_throttling(&mut self, name: &str, point: bool) {
-        self.throttling.push((
-            chrono::Local::now()
-                .naive_local()
-                .and_utc()
-                .timestamp_millis(),
-            (name.to_owned(), point),
-        ));
+    pub fn push_throttling_with_time(&mut self, name: &str, point: bool, time: NaiveDateTime) {
+        self.throttling
+            .push((time.and_utc().timestamp_millis(), (name.to_owned(), point)));
     }
 
     pub fn line_series_iter(&self) -> impl Iterator<Item = (&String, &Vec<(i64, f64)>)> {
@@ -43,7 +43,7 @@ impl Component for NewProfileDialog {
                     set_spacing: 5,
 
                     gtk::Label {
-                        set_label: "Copy settings from:",
+                        set_label: "Base settings on:",
                     },
 
                     #[local_ref]
@@ -87,7 +87,8 @@ impl Component for NewProfileDialog {
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
         let mut variants = vec![ProfileBase::Empty, ProfileBase::Default];
-        variants.extend(current_profiles.into_iter().map(ProfileBase::Profile));
+        variants.extend(current_profiles.iter().cloned().map(ProfileBase::Profile));
+        variants.extend(current_profiles.into_iter().map(ProfileBase::Extends));
 
         let base_selector = SimpleComboBox::<ProfileBase>::builder()
             .launch(SimpleComboBox {
@@ -1,3 +1,5 @@
+mod rule_tree;
+
 use std::time::Duration;
 
 use crate::app::{msg::AppMsg, APP_BROKER};
@@ -5,17 +7,20 @@ use gtk::{
     glib::{GStr, GString},
     prelude::{
         BoxExt, CheckButtonExt, DialogExt, DialogExtManual, EditableExt, EntryBufferExtManual,
-        EntryExt, GridExt, GtkWindowExt, ObjectExt, OrientableExt, PopoverExt, SelectionModelExt,
-        WidgetExt,
+        EntryExt, GridExt, GtkWindowExt, ListBoxRowExt, ObjectExt, OrientableExt, PopoverExt,
+        SelectionModelExt, WidgetExt,
     },
     SingleSelection,
 };
-use lact_schema::{ProcessInfo, ProcessProfileRule, ProfileRule, ProfileWatcherState};
+use lact_schema::{
+    ProcessInfo, ProcessMatchMode, ProcessProfileRule, ProfileRule, ProfileWatcherState,
+};
 use relm4::{
     tokio::time::sleep,
     typed_view::list::{RelmListItem, TypedListView},
     view, ComponentParts, ComponentSender, RelmWidgetExt,
 };
+use rule_tree::NodePath;
 use tracing::debug;
 
 const EVALUATE_INTERVAL_MS: u64 = 250;
@@ -23,11 +28,24 @@ const EVALUATE_INTERVAL_MS: u64 = 250;
 const PROCESS_PAGE: &str = "process";
 const GAMEMODE_PAGE: &str = "gamemode";
 
+/// Same order as `MATCH_MODE_VALUES`
+const MATCH_MODES: &[&str] = &["Exact text", "Glob pattern", "Regular expression"];
+const MATCH_MODE_VALUES: [ProcessMatchMode; 3] = [
+    ProcessMatchMode::Exact,
+    ProcessMatchMode::Glob,
+    ProcessMatchMode::Regex,
+];
+
 pub struct ProfileRuleWindow {
     profile_name: String,
     process_name_buffer: gtk::EntryBuffer,
     args_buffer: gtk::EntryBuffer,
     rule: ProfileRule,
+    /// Path of the rule selected in the tree
+    selected: NodePath,
+    /// Paths of the rows in the tree
+    tree_rows: Vec<NodePath>,
+    tree_labels: Vec<gtk::Label>,
     process_list_view: TypedListView<ProcessListItem, SingleSelection>,
     currently_matches: bool,
 }
@@ -43,6 +61,12 @@ pub enum ProfileRuleWindowMsg {
     SetFromSelectedProcess,
     Evaluate,
     EvaluationResult(bool),
+    SelectNode(usize),
+    AddCondition,
+    Group,
+    ToggleGroupKind,
+    ToggleNegation,
+    Remove,
     Save,
 }
 
@@ -55,7 +79,7 @@ impl relm4::Component for ProfileRuleWindow {
 
     view! {
         gtk::Dialog {
-            set_default_size: (600, 300),
+            set_default_size: (900, 350),
             set_title: Some("Profile activation rules"),
             set_hide_on_close: true,
             connect_response[root, sender] => move |_, response| {
@@ -86,8 +110,72 @@ impl relm4::Component for ProfileRuleWindow {
                     set_orientation: gtk::Orientation::Horizontal,
                     set_expand: true,
 
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+                        set_margin_all: 10,
+                        set_spacing: 5,
+
+                        gtk::ScrolledWindow {
+                            set_size_request: (250, -1),
+                            set_vexpand: true,
+
+                            #[name = "rule_tree"]
+                            gtk::ListBox {
+                                set_selection_mode: gtk::SelectionMode::Single,
+                                connect_row_selected[sender] => move |_, row| {
+                                    if let Some(row) = row {
+                                        sender.input(ProfileRuleWindowMsg::SelectNode(row.index() as usize));
+                                    }
+                                },
+                            },
+                        },
+
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Horizontal,
+                            set_spacing: 5,
+
+                            gtk::Button {
+                                set_icon_name: "list-add-symbolic",
+                                set_tooltip_text: Some("Add condition"),
+                                connect_clicked => ProfileRuleWindowMsg::AddCondition,
+                            },
+
+                            gtk::Button {
+                                set_label: "Group",
+                                set_tooltip_text: Some("Put the selected rule into a group"),
+                                connect_clicked => ProfileRuleWindowMsg::Group,
+                            },
+
+                            gtk::Button {
+                                set_label: "All/Any",
+                                set_tooltip_text: Some("Switch between matching all or any of the rules in the selected group"),
+                                #[watch]
+                                set_sensitive: model.selected_rule().is_some_and(rule_tree::is_group),
+                                connect_clicked => ProfileRuleWindowMsg::ToggleGroupKind,
+                            },
+
+                            gtk::Button {
+                                set_label: "Not",
+                                set_tooltip_text: Some("Negate the selected rule"),
+                                connect_clicked => ProfileRuleWindowMsg::ToggleNegation,
+                            },
+
+                            gtk::Button {
+                                set_icon_name: "list-remove-symbolic",
+                                set_tooltip_text: Some("Remove the selected rule"),
+                                #[watch]
+                                set_sensitive: !model.selected.is_empty(),
+                                connect_clicked => ProfileRuleWindowMsg::Remove,
+                            },
+                        },
+                    },
+
+                    gtk::Separator {},
+
                     gtk::StackSidebar {
                         set_stack: &stack,
+                        #[watch]
+                        set_sensitive: model.selected_rule().is_some_and(rule_tree::is_condition),
                     },
 
                     gtk::Box {
@@ -97,6 +185,8 @@ impl relm4::Component for ProfileRuleWindow {
 
                         #[name = "stack"]
                         gtk::Stack {
+                            #[watch]
+                            set_sensitive: model.selected_rule().is_some_and(rule_tree::is_condition),
                             connect_visible_child_name_notify => ProfileRuleWindowMsg::Evaluate,
 
                             add_titled[Some(PROCESS_PAGE), "A process is running"] = &gtk::Grid {
@@ -204,10 +294,30 @@ impl relm4::Component for ProfileRuleWindow {
                                 },
                             },
 
-                            set_visible_child_name: match &model.rule {
-                                ProfileRule::Process(_) => PROCESS_PAGE,
-                                ProfileRule::Gamemode(_) => GAMEMODE_PAGE,
-                            }
+                            set_visible_child_name: PROCESS_PAGE,
+                        },
+
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Horizontal,
+                            set_spacing: 10,
+                            #[watch]
+                            set_sensitive: model.selected_rule().is_some_and(rule_tree::is_condition),
+
+                            gtk::Label {
+                                set_label: "Match as:",
+                            },
+
+                            #[name = "match_mode_dropdown"]
+                            gtk::DropDown::from_strings(MATCH_MODES) {
+                                set_tooltip_text: Some("Glob patterns support * and ? wildcards and have to match the whole name or arguments"),
+                                connect_selected_notify => ProfileRuleWindowMsg::Evaluate,
+                            },
+
+                            #[name = "ignore_case_checkbutton"]
+                            gtk::CheckButton {
+                                set_label: Some("Ignore case"),
+                                connect_toggled => ProfileRuleWindowMsg::Evaluate,
+                            },
                         },
 
                         gtk::Separator {},
@@ -257,6 +367,9 @@ impl relm4::Component for ProfileRuleWindow {
 
         let mut model = Self {
             rule: ProfileRule::default(),
+            selected: NodePath::new(),
+            tree_rows: Vec::new(),
+            tree_labels: Vec::new(),
             profile_name: String::new(),
             process_name_buffer: gtk::EntryBuffer::new(GStr::NONE),
             args_buffer: gtk::EntryBuffer::new(GStr::NONE),
@@ -315,36 +428,15 @@ impl relm4::Component for ProfileRuleWindow {
             ProfileRuleWindowMsg::Show { profile_name, rule } => {
                 self.profile_name = profile_name;
 
-                let page = match rule {
-                    ProfileRule::Process(rule) => {
-                        self.process_name_buffer.set_text(rule.name.as_ref());
-                        self.args_buffer
-                            .set_text(rule.args.as_deref().unwrap_or_default());
-                        PROCESS_PAGE
-                    }
-                    ProfileRule::Gamemode(Some(rule)) => {
-                        self.process_name_buffer.set_text(rule.name.as_ref());
-                        self.args_buffer
-                            .set_text(rule.args.as_deref().unwrap_or_default());
-                        GAMEMODE_PAGE
-                    }
-                    ProfileRule::Gamemode(None) => {
-                        self.process_name_buffer.set_text("");
-                        self.args_buffer.set_text("");
-                        GAMEMODE_PAGE
-                    }
-                };
-                widgets.stack.set_visible_child_name(page);
-
-                widgets
-                    .filter_by_args_checkbutton
-                    .set_active(self.args_buffer.length() > 0);
-                widgets
-                    .gamemode_filter_by_process_checkbutton
-                    .set_active(self.process_name_buffer.length() > 0);
-                widgets
-                    .gamemode_filter_by_args_checkbutton
-                    .set_active(self.args_buffer.length() > 0);
+                // Start with the first condition selected, so that simple rules open like before
+                self.selected = rule_tree::flatten(&rule)
+                    .into_iter()
+                    .find(|path| rule_tree::node(&rule, path).is_some_and(rule_tree::is_condition))
+                    .unwrap_or_default();
+                self.rule = rule;
+
+                self.load_selected(widgets);
+                self.rebuild_tree(widgets);
 
                 root.present();
             }
@@ -383,17 +475,51 @@ impl relm4::Component for ProfileRuleWindow {
             }
             ProfileRuleWindowMsg::Evaluate => {
                 if root.is_visible() {
-                    let rule = self.get_rule(widgets);
-                    APP_BROKER.send(AppMsg::EvaluateProfile(rule));
+                    self.commit_condition(widgets);
+                    APP_BROKER.send(AppMsg::EvaluateProfile(self.rule.clone()));
                 }
             }
             ProfileRuleWindowMsg::EvaluationResult(matches) => {
                 self.currently_matches = matches;
             }
+            ProfileRuleWindowMsg::SelectNode(index) => {
+                if let Some(path) = self.tree_rows.get(index).cloned() {
+                    if path != self.selected {
+                        self.commit_condition(widgets);
+                        self.selected = path;
+                        self.load_selected(widgets);
+                    }
+                }
+            }
+            ProfileRuleWindowMsg::AddCondition => {
+                self.edit_tree(widgets, rule_tree::add_condition);
+            }
+            ProfileRuleWindowMsg::Group => {
+                self.edit_tree(widgets, |rule, path| {
+                    rule_tree::group(rule, path);
+                    path.to_vec()
+                });
+            }
+            ProfileRuleWindowMsg::ToggleGroupKind => {
+                self.edit_tree(widgets, |rule, path| {
+                    rule_tree::toggle_group_kind(rule, path);
+                    path.to_vec()
+                });
+            }
+            ProfileRuleWindowMsg::ToggleNegation => {
+                self.edit_tree(widgets, |rule, path| {
+                    rule_tree::toggle_negation(rule, path);
+                    path.to_vec()
+                });
+            }
+            ProfileRuleWindowMsg::Remove => {
+                self.edit_tree(widgets, rule_tree::remove);
+            }
             ProfileRuleWindowMsg::Save => {
+                self.commit_condition(widgets);
                 APP_BROKER.send(AppMsg::SetProfileRule {
                     name: self.profile_name.clone(),
-                    rule: Some(self.get_rule(widgets)),
+                    rule: Some(self.rule.clone()),
                 });
             }
         }
@@ -403,9 +529,119 @@ impl relm4::Component for ProfileRuleWindow {
 }
 
 impl ProfileRuleWindow {
-    fn get_rule(&self, widgets: &ProfileRuleWindowWidgets) -> ProfileRule {
+    fn selected_rule(&self) -> Option<&ProfileRule> {
+        rule_tree::node(&self.rule, &self.selected)
+    }
+
+    /// Applies a change to the rule tree. The change returns the path of the rule to select afterwards.
+    fn edit_tree(
+        &mut self,
+        widgets: &ProfileRuleWindowWidgets,
+        edit: impl FnOnce(&mut ProfileRule, &[usize]) -> NodePath,
+    ) {
+        self.commit_condition(widgets);
+        self.selected = edit(&mut self.rule, &self.selected);
+        self.load_selected(widgets);
+        self.rebuild_tree(widgets);
+    }
+
+    fn rebuild_tree(&mut self, widgets: &ProfileRuleWindowWidgets) {
+        while let Some(row) = widgets.rule_tree.row_at_index(0) {
+            widgets.rule_tree.remove(&row);
+        }
+
+        self.tree_rows = rule_tree::flatten(&self.rule);
+        self.tree_labels = self
+            .tree_rows
+            .iter()
+            .map(|path| {
+                let rule = rule_tree::node(&self.rule, path).expect("Flattened path must exist");
+                let label = gtk::Label::new(Some(&rule_tree::describe(rule)));
+                label.set_halign(gtk::Align::Start);
+                label.set_margin_all(5);
+                label.set_margin_start(5 + 20 * path.len() as i32);
+                widgets.rule_tree.append(&label);
+                label
+            })
+            .collect();
+
+        if let Some(index) = self
+            .tree_rows
+            .iter()
+            .position(|path| *path == self.selected)
+        {
+            let row = widgets.rule_tree.row_at_index(index as i32);
+            widgets.rule_tree.select_row(row.as_ref());
+        }
+    }
+
+    /// Stores the settings from the editor into the selected condition
+    fn commit_condition(&mut self, widgets: &ProfileRuleWindowWidgets) {
+        let condition = self.get_condition(widgets);
+        let Some(rule) = rule_tree::node_mut(&mut self.rule, &self.selected) else {
+            return;
+        };
+        if !rule_tree::is_condition(rule) || *rule == condition {
+            return;
+        }
+
+        if let Some(index) = self
+            .tree_rows
+            .iter()
+            .position(|path| *path == self.selected)
+        {
+            if let Some(label) = self.tree_labels.get(index) {
+                label.set_label(&rule_tree::describe(&condition));
+            }
+        }
+        *rule = condition;
+    }
+
+    /// Shows the selected condition in the editor
+    fn load_selected(&self, widgets: &ProfileRuleWindowWidgets) {
+        let (page, process_rule) = match self.selected_rule() {
+            Some(ProfileRule::Process(rule)) => (PROCESS_PAGE, Some(rule)),
+            Some(ProfileRule::Gamemode(rule)) => (GAMEMODE_PAGE, rule.as_ref()),
+            // Groups and negations are edited in the tree
+            _ => return,
+        };
+        widgets.stack.set_visible_child_name(page);
+
+        let process_rule = process_rule.cloned().unwrap_or_default();
+        self.process_name_buffer
+            .set_text(process_rule.name.as_ref());
+        self.args_buffer
+            .set_text(process_rule.args.as_deref().unwrap_or_default());
+        let match_mode_index = MATCH_MODE_VALUES
+            .iter()
+            .position(|mode| *mode == process_rule.match_mode)
+            .unwrap_or_default();
+        widgets
+            .match_mode_dropdown
+            .set_selected(match_mode_index as u32);
+        widgets
+            .ignore_case_checkbutton
+            .set_active(process_rule.ignore_case);
+
+        widgets
+            .filter_by_args_checkbutton
+            .set_active(self.args_buffer.length() > 0);
+        widgets
+            .gamemode_filter_by_process_checkbutton
+            .set_active(self.process_name_buffer.length() > 0);
+        widgets
+            .gamemode_filter_by_args_checkbutton
+            .set_active(self.args_buffer.length() > 0);
+    }
+
+    fn get_condition(&self, widgets: &ProfileRuleWindowWidgets) -> ProfileRule {
         let process_name = self.process_name_buffer.text();
         let process_args = self.args_buffer.text();
+        let match_mode = MATCH_MODE_VALUES
+            .get(widgets.match_mode_dropdown.selected() as usize)
+            .copied()
+            .unwrap_or_default();
+        let ignore_case = widgets.ignore_case_checkbutton.is_active();
 
         match widgets.stack.visible_child_name().as_deref() {
             Some(PROCESS_PAGE) => {
@@ -417,6 +653,8 @@ impl ProfileRuleWindow {
                 ProfileRule::Process(ProcessProfileRule {
                     name: process_name.as_str().into(),
                     args,
+                    match_mode,
+                    ignore_case,
                 })
             }
             Some(GAMEMODE_PAGE) => {
@@ -433,6 +671,8 @@ impl ProfileRuleWindow {
                     Some(ProcessProfileRule {
                         name: process_name.as_str().into(),
                         args,
+                        match_mode,
+                        ignore_case,
                     })
                 };
                 ProfileRule::Gamemode(rule)
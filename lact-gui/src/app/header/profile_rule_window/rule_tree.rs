@@ -0,0 +1,146 @@
+//! Editing of combined profile rules. Nodes in the rule tree are addressed by the indices of the children leading to
+//! them from the root rule, so an empty path is the root itself.
+use lact_schema::ProfileRule;
+use std::mem;
+
+pub type NodePath = Vec<usize>;
+
+pub fn is_condition(rule: &ProfileRule) -> bool {
+    matches!(rule, ProfileRule::Process(_) | ProfileRule::Gamemode(_))
+}
+
+pub fn is_group(rule: &ProfileRule) -> bool {
+    matches!(rule, ProfileRule::And(_) | ProfileRule::Or(_))
+}
+
+fn children(rule: &ProfileRule) -> &[ProfileRule] {
+    match rule {
+        ProfileRule::And(rules) | ProfileRule::Or(rules) => rules,
+        ProfileRule::Not(rule) => std::slice::from_ref(rule.as_ref()),
+        ProfileRule::Process(_) | ProfileRule::Gamemode(_) => &[],
+    }
+}
+
+pub fn node<'a>(rule: &'a ProfileRule, path: &[usize]) -> Option<&'a ProfileRule> {
+    match path.split_first() {
+        None => Some(rule),
+        Some((index, rest)) => node(children(rule).get(*index)?, rest),
+    }
+}
+
+pub fn node_mut<'a>(rule: &'a mut ProfileRule, path: &[usize]) -> Option<&'a mut ProfileRule> {
+    let Some((index, rest)) = path.split_first() else {
+        return Some(rule);
+    };
+    let child = match rule {
+        ProfileRule::And(rules) | ProfileRule::Or(rules) => rules.get_mut(*index)?,
+        ProfileRule::Not(rule) if *index == 0 => rule.as_mut(),
+        _ => return None,
+    };
+    node_mut(child, rest)
+}
+
+/// Paths of all nodes in the order they are displayed in
+pub fn flatten(rule: &ProfileRule) -> Vec<NodePath> {
+    fn walk(rule: &ProfileRule, path: &mut NodePath, paths: &mut Vec<NodePath>) {
+        paths.push(path.clone());
+        for (i, child) in children(rule).iter().enumerate() {
+            path.push(i);
+            walk(child, path, paths);
+            path.pop();
+        }
+    }
+
+    let mut paths = Vec::new();
+    walk(rule, &mut Vec::new(), &mut paths);
+    paths
+}
+
+pub fn describe(rule: &ProfileRule) -> String {
+    match rule {
+        ProfileRule::Process(rule) => match &rule.args {
+            Some(args) => format!("Process {} with arguments {args}", rule.name),
+            None => format!("Process {}", rule.name),
+        },
+        ProfileRule::Gamemode(Some(rule)) => format!("Gamemode is active with {}", rule.name),
+        ProfileRule::Gamemode(None) => "Gamemode is active".to_owned(),
+        ProfileRule::And(_) => "All of the following".to_owned(),
+        ProfileRule::Or(_) => "Any of the following".to_owned(),
+        ProfileRule::Not(_) => "Not".to_owned(),
+    }
+}
+
+/// Adds a new condition into the selected group, or next to the selected rule.
+/// Returns the path of the new condition.
+pub fn add_condition(root: &mut ProfileRule, path: &[usize]) -> NodePath {
+    let condition = ProfileRule::default();
+    if let Some(ProfileRule::And(rules) | ProfileRule::Or(rules)) = node_mut(root, path) {
+        rules.push(condition);
+        return [path, &[rules.len() - 1]].concat();
+    }
+    add_sibling(root, path, condition)
+}
+
+fn add_sibling(root: &mut ProfileRule, path: &[usize], rule: ProfileRule) -> NodePath {
+    let Some((index, parent_path)) = path.split_last() else {
+        let previous = mem::take(root);
+        *root = ProfileRule::And(vec![previous, rule]);
+        return vec![1];
+    };
+
+    match node_mut(root, parent_path) {
+        Some(ProfileRule::And(rules) | ProfileRule::Or(rules)) => {
+            rules.insert(index + 1, rule);
+            [parent_path, &[index + 1]].concat()
+        }
+        // A negation only has a single rule, so the new one is added next to the negation itself
+        _ => add_sibling(root, parent_path, rule),
+    }
+}
+
+/// Puts the rule into a new group
+pub fn group(root: &mut ProfileRule, path: &[usize]) {
+    if let Some(rule) = node_mut(root, path) {
+        *rule = ProfileRule::And(vec![mem::take(rule)]);
+    }
+}
+
+/// Switches a group between matching all or any of its rules
+pub fn toggle_group_kind(root: &mut ProfileRule, path: &[usize]) {
+    if let Some(rule) = node_mut(root, path) {
+        *rule = match mem::take(rule) {
+            ProfileRule::And(rules) => ProfileRule::Or(rules),
+            ProfileRule::Or(rules) => ProfileRule::And(rules),
+            other => other,
+        };
+    }
+}
+
+/// Negates the rule, or removes the negation if it is already negated
+pub fn toggle_negation(root: &mut ProfileRule, path: &[usize]) {
+    if let Some(rule) = node_mut(root, path) {
+        *rule = match mem::take(rule) {
+            ProfileRule::Not(inner) => *inner,
+            other => ProfileRule::Not(Box::new(other)),
+        };
+    }
+}
+
+/// Removes the rule from its group. Returns the path of the rule that should be selected afterwards.
+pub fn remove(root: &mut ProfileRule, path: &[usize]) -> NodePath {
+    // The root rule can only be replaced, not removed
+    let Some((index, parent_path)) = path.split_last() else {
+        return vec![];
+    };
+
+    match node_mut(root, parent_path) {
+        Some(ProfileRule::And(rules) | ProfileRule::Or(rules)) => {
+            if *index < rules.len() {
+                rules.remove(*index);
+            }
+            parent_path.to_vec()
+        }
+        // A negation is removed together with its rule
+        _ => remove(root, parent_path),
+    }
+}
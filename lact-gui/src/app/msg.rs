@@ -10,6 +10,9 @@ pub enum AppMsg {
         full: bool,
     },
     Stats(Arc<DeviceStats>),
+    FetchStatsHistory {
+        seconds: u64,
+    },
     ApplyChanges,
     RevertChanges,
     SettingsChanged,
@@ -24,6 +24,7 @@ impl SimpleComponent for SoftwarePage {
             append = &InfoRow::new_selectable("LACT Daemon:", &daemon_version),
             append = &InfoRow::new_selectable("LACT GUI:", &gui_version),
             append = &InfoRow::new_selectable("Kernel Version:", &system_info.kernel_version),
+            append = &InfoRow::new("Config:", config_status),
         }
     }
 
@@ -49,6 +50,12 @@ impl SimpleComponent for SoftwarePage {
         };
         let gui_version = format!("{GUI_VERSION}-{gui_profile} (commit {GIT_COMMIT})");
 
+        let config_status = if system_info.config_immutable {
+            "Read-only, changes are not saved across restarts"
+        } else {
+            "Writable"
+        };
+
         let widgets = view_output!();
 
         ComponentParts { model, widgets }
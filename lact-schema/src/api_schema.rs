@@ -0,0 +1,232 @@
+use crate::{
+    request::{COMMANDS, PROTOCOL_REVISION},
+    ApiError, AuditLogEntry, Capabilities, ClocksInfo, ConfigRevision, ConfigValidation,
+    DeviceInfo, DeviceListEntry, DeviceStats, DeviceStatsHistoryEntry, Event, Pong, PowerStates,
+    ProfilesInfo, Request, SaveStatus, SystemInfo,
+};
+use schemars::{generate::SchemaSettings, JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// JSON Schema (draft 7) description of the API, returned by `ApiSchema`.
+/// All of the `$ref`s point to `#/definitions/<name>`, so the document can be used as the root for resolving them.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ApiSchema {
+    pub protocol_revision: u32,
+    /// A request, without the optional `id` field
+    pub request: Schema,
+    /// The `data` field of a successful response, by command name
+    pub responses: BTreeMap<String, Schema>,
+    /// The `data` field of an error response
+    pub error: Schema,
+    /// A message sent without a matching request, such as after subscribing to stats
+    pub event: Schema,
+    pub definitions: Map<String, Value>,
+}
+
+impl ApiSchema {
+    pub fn generate() -> Self {
+        let mut generator = SchemaGenerator::new(SchemaSettings::draft07());
+
+        let request = generator.subschema_for::<Request>();
+        let responses = COMMANDS
+            .iter()
+            .map(|command| {
+                let schema = response_schema(command, &mut generator)
+                    .unwrap_or_else(|| panic!("No response type for command {command}"));
+                ((*command).to_owned(), schema)
+            })
+            .collect();
+        let error = generator.subschema_for::<ApiError>();
+        let event = generator.subschema_for::<Event>();
+
+        Self {
+            protocol_revision: PROTOCOL_REVISION,
+            request,
+            responses,
+            error,
+            event,
+            definitions: generator.take_definitions(true),
+        }
+    }
+}
+
+/// Type of the data returned on success by each command
+fn response_schema(command: &str, generator: &mut SchemaGenerator) -> Option<Schema> {
+    let schema = match command {
+        "ping" => generator.subschema_for::<Pong>(),
+        "hello" => generator.subschema_for::<Capabilities>(),
+        "api_schema" => generator.subschema_for::<ApiSchema>(),
+        "list_devices" => generator.subschema_for::<Vec<DeviceListEntry>>(),
+        "system_info" => generator.subschema_for::<SystemInfo>(),
+        "device_info" => generator.subschema_for::<DeviceInfo>(),
+        "device_stats" => generator.subschema_for::<DeviceStats>(),
+        "device_stats_history" => generator.subschema_for::<Vec<DeviceStatsHistoryEntry>>(),
+        "device_clocks_info" => generator.subschema_for::<ClocksInfo>(),
+        "device_power_profile_modes" => generator.subschema_for::<remote::PowerProfileModesTable>(),
+        "get_power_states" => generator.subschema_for::<PowerStates>(),
+        "vbios_dump" => generator.subschema_for::<Vec<u8>>(),
+        "list_profiles" => generator.subschema_for::<ProfilesInfo>(),
+        "evaluate_profile_rule" => generator.subschema_for::<bool>(),
+        "audit_log" => generator.subschema_for::<Vec<AuditLogEntry>>(),
+        "validate_config" => generator.subschema_for::<ConfigValidation>(),
+        "list_config_revisions" => generator.subschema_for::<Vec<ConfigRevision>>(),
+        // Only set when confirming with the `save_status` feature
+        "confirm_pending_config" => generator.subschema_for::<Option<SaveStatus>>(),
+        // Path of the generated file or a message about what was changed
+        "enable_overdrive" | "disable_overdrive" | "generate_snapshot" => {
+            generator.subschema_for::<String>()
+        }
+        // Seconds until the change is reverted if it is not confirmed
+        "set_fan_control"
+        | "reset_pmfw"
+        | "set_power_cap"
+        | "set_performance_level"
+        | "set_clocks_value"
+        | "batch_set_clocks_value"
+        | "set_power_profile_mode"
+        | "set_enabled_power_states" => generator.subschema_for::<u64>(),
+        "authenticate"
+        | "subscribe_stats"
+        | "unsubscribe_stats"
+        | "subscribe_profile"
+        | "set_profile"
+        | "create_profile"
+        | "delete_profile"
+        | "move_profile"
+        | "set_profile_rule"
+        | "rest_config"
+        | "restore_config_revision" => generator.subschema_for::<()>(),
+        _ => return None,
+    };
+    Some(schema)
+}
+
+/// Schemas for types from other crates that are part of the API.
+/// These have to be kept in sync with the serialized format of the original types.
+/// They are never constructed, only used through `#[schemars(with = "...")]`.
+#[allow(dead_code)]
+pub(crate) mod remote {
+    use schemars::JsonSchema;
+    use std::collections::BTreeMap;
+
+    #[derive(JsonSchema)]
+    #[serde(rename_all = "lowercase")]
+    pub enum PerformanceLevel {
+        Auto,
+        Low,
+        High,
+        Manual,
+    }
+
+    #[derive(JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    pub enum PowerLevelKind {
+        CoreClock,
+        MemoryClock,
+        SOCClock,
+        FabricClock,
+        DCEFClock,
+        PcieSpeed,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct FanInfo {
+        pub current: u32,
+        /// Minimum and maximum allowed values, empty if the value cannot be changed
+        pub allowed_range: Option<(u32, u32)>,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Temperature {
+        pub current: Option<f32>,
+        pub crit: Option<f32>,
+        pub crit_hyst: Option<f32>,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct PowerProfileModesTable {
+        pub modes: BTreeMap<u16, PowerProfile>,
+        /// Names for the values in the profile components
+        pub value_names: Vec<String>,
+        pub active: u16,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct PowerProfile {
+        pub name: String,
+        pub components: Vec<PowerProfileComponent>,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct PowerProfileComponent {
+        pub clock_type: Option<String>,
+        pub values: Vec<Option<i32>>,
+    }
+
+    /// Clocks and voltage table (`pp_od_clk_voltage`)
+    #[derive(JsonSchema)]
+    #[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+    pub enum ClocksTableGen {
+        /// Vega10 and older
+        Vega10(Vega10ClocksTable),
+        /// Vega20 and newer
+        Vega20(Vega20ClocksTable),
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Vega10ClocksTable {
+        pub sclk_levels: Vec<ClocksLevel>,
+        pub mclk_levels: Vec<ClocksLevel>,
+        pub od_range: Vega10OdRange,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Vega10OdRange {
+        pub sclk: Range,
+        pub mclk: Option<Range>,
+        pub vddc: Option<Range>,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Vega20ClocksTable {
+        pub current_sclk_range: Range,
+        pub sclk_offset: Option<i32>,
+        pub rdna4_sclk_offset_workaround: bool,
+        pub current_mclk_range: Range,
+        pub vddc_curve: Vec<ClocksLevel>,
+        pub voltage_offset: Option<i32>,
+        pub od_range: Vega20OdRange,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Vega20OdRange {
+        pub sclk: Option<Range>,
+        pub sclk_offset: Option<Range>,
+        pub mclk: Option<Range>,
+        pub curve_sclk_points: Vec<Range>,
+        pub curve_voltage_points: Vec<Range>,
+        pub voltage_offset: Option<Range>,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Range {
+        pub min: Option<i32>,
+        pub max: Option<i32>,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct ClocksLevel {
+        /// MHz
+        pub clockspeed: i32,
+        /// mV
+        pub voltage: i32,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct SerdeError {
+        pub description: String,
+        pub source: Option<Box<SerdeError>>,
+    }
+}
@@ -1,6 +1,7 @@
 pub use clap;
 
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 pub struct Args {
@@ -25,6 +26,21 @@ pub struct GuiArgs {
     /// Remote TCP address to connect to
     #[arg(long)]
     pub tcp_address: Option<String>,
+    /// Token to authenticate with on the remote TCP connection
+    #[arg(long, env = "LACT_TCP_AUTH_TOKEN")]
+    pub tcp_auth_token: Option<String>,
+    /// Use TLS for the remote TCP connection
+    #[arg(long)]
+    pub tls: bool,
+    /// CA certificate to verify the daemon with, instead of the system certificate store. Implies `--tls`
+    #[arg(long)]
+    pub tls_ca_cert: Option<PathBuf>,
+    /// Client certificate to present to the daemon. Implies `--tls`
+    #[arg(long, requires = "tls_client_key")]
+    pub tls_client_cert: Option<PathBuf>,
+    /// Private key of the client certificate
+    #[arg(long, requires = "tls_client_cert")]
+    pub tls_client_key: Option<PathBuf>,
 }
 
 #[derive(Parser)]
@@ -44,4 +60,27 @@ pub enum CliCommand {
     Info,
     /// Generate debug snapshot
     Snapshot,
+    /// Print the JSON Schema description of the daemon API
+    ApiSchema,
+    /// Manage the daemon config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Check a config file against the GPUs of the system and show the changes that applying it would make, without applying it
+    Check {
+        /// Path to the config file
+        file: PathBuf,
+    },
+    /// List the previous versions of the config that can be restored
+    Revisions,
+    /// Replace the config with a previous version and apply it
+    Restore {
+        /// Id of the version, as shown by `revisions`
+        id: u64,
+    },
 }
@@ -0,0 +1,106 @@
+use crate::api_schema::remote;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Machine-readable category of an error returned by the daemon
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The GPU, profile or other item referenced by the request does not exist
+    NotFound,
+    /// The request or one of its values is not valid
+    InvalidArgument,
+    /// The operation is not supported on this GPU or system
+    Unsupported,
+    /// A previous settings change is still waiting to be confirmed or reverted
+    PendingConfirmation,
+    /// The connection is not allowed to make this request
+    PermissionDenied,
+    /// The connection has to authenticate before making requests
+    AuthenticationRequired,
+    /// Settings could not be written to the GPU
+    HardwareWriteFailed,
+    /// Any other error, including kinds that are unknown to this version of the schema
+    #[default]
+    #[serde(other)]
+    Other,
+}
+
+impl ErrorKind {
+    /// Creates a new error of this kind
+    pub fn error(self, message: impl Into<String>) -> anyhow::Error {
+        anyhow::Error::new(self.context(message))
+    }
+
+    /// Creates a context for an existing error, which sets the kind of the whole error
+    pub fn context(self, message: impl Into<String>) -> KindError {
+        KindError {
+            kind: self,
+            message: message.into(),
+        }
+    }
+
+    /// The kind of the outermost error in the chain that has one.
+    /// Both errors created on the daemon side and errors received from the daemon are recognized.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<KindError>() {
+            error.kind
+        } else if let Some(error) = error.downcast_ref::<ApiError>() {
+            error.kind
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// Error with a kind, created through [`ErrorKind::error`] or [`ErrorKind::context`]
+#[derive(Debug)]
+pub struct KindError {
+    pub kind: ErrorKind,
+    message: String,
+}
+
+impl fmt::Display for KindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+impl std::error::Error for KindError {}
+
+/// Error returned by the daemon
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ApiError {
+    #[serde(default)]
+    pub kind: ErrorKind,
+    /// Messages of the error and everything that caused it, outermost first
+    #[serde(default)]
+    pub chain: Vec<String>,
+    /// The same chain in a nested form, as `description` and `source` fields
+    #[serde(flatten)]
+    #[schemars(with = "remote::SerdeError")]
+    pub error: serde_error::Error,
+}
+
+impl From<&anyhow::Error> for ApiError {
+    fn from(error: &anyhow::Error) -> Self {
+        Self {
+            kind: ErrorKind::of(error),
+            chain: error.chain().map(ToString::to_string).collect(),
+            error: serde_error::Error::new(&**error),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
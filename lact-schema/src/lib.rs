@@ -1,5 +1,7 @@
+pub mod api_schema;
 #[cfg(feature = "args")]
 pub mod args;
+mod error;
 mod profiles;
 pub mod request;
 mod response;
@@ -7,8 +9,9 @@ mod response;
 #[cfg(test)]
 mod tests;
 
+pub use error::{ApiError, ErrorKind, KindError};
 pub use request::Request;
-pub use response::Response;
+pub use response::{Event, Response, ResponseEnvelope};
 
 use amdgpu_sysfs::{
     gpu_handle::{
@@ -18,7 +21,9 @@ use amdgpu_sysfs::{
     },
     hw_mon::Temperature,
 };
+use api_schema::remote;
 use indexmap::{IndexMap, IndexSet};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::{
@@ -30,7 +35,7 @@ use std::{
 
 pub const GIT_COMMIT: &str = env!("VERGEN_GIT_SHA");
 
-#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum FanControlMode {
     Static,
@@ -56,21 +61,36 @@ pub fn default_fan_curve() -> FanCurveMap {
     [(40, 0.3), (50, 0.35), (60, 0.5), (70, 0.75), (80, 1.0)].into()
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Pong;
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct SystemInfo {
     pub version: String,
     pub commit: Option<String>,
     pub profile: String,
     pub kernel_version: String,
     pub amdgpu_overdrive_enabled: Option<bool>,
+    /// Settings changes are only applied in memory, as the daemon never writes its config
+    #[serde(default)]
+    pub config_immutable: bool,
+}
+
+/// Response to `Hello`
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct Capabilities {
+    pub daemon_version: String,
+    pub daemon_commit: Option<String>,
+    pub protocol_revision: u32,
+    /// Names of the supported commands
+    pub commands: Vec<String>,
+    /// Supported optional features, see [`request::features`]
+    pub features: Vec<String>,
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DeviceListEntry {
     pub id: String,
     pub name: Option<String>,
@@ -85,14 +105,14 @@ impl fmt::Display for DeviceListEntry {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct GpuPciInfo {
     pub device_pci_info: PciInfo,
     pub subsystem_pci_info: PciInfo,
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DeviceInfo {
     pub pci_info: Option<GpuPciInfo>,
     pub vulkan_info: Option<VulkanInfo>,
@@ -265,7 +285,7 @@ impl DeviceInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct DrmInfo {
     pub device_name: Option<String>,
     pub pci_revision_id: Option<u32>,
@@ -290,7 +310,7 @@ pub struct DrmInfo {
     pub intel: IntelDrmInfo,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct NvidiaRopInfo {
     pub unit_count: u32,
     pub operations_factor: u32,
@@ -298,14 +318,14 @@ pub struct NvidiaRopInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct IntelDrmInfo {
     pub execution_units: Option<u32>,
     pub subslices: Option<u32>,
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DrmMemoryInfo {
     pub cpu_accessible_used: u64,
     pub cpu_accessible_total: u64,
@@ -313,7 +333,7 @@ pub struct DrmMemoryInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, JsonSchema)]
 pub struct ClocksInfo {
     pub max_sclk: Option<i32>,
     pub max_mclk: Option<i32>,
@@ -321,16 +341,16 @@ pub struct ClocksInfo {
     pub table: Option<ClocksTable>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(tag = "type", content = "value", rename_all = "snake_case")]
 pub enum ClocksTable {
-    Amd(AmdClocksTableGen),
+    Amd(#[schemars(with = "remote::ClocksTableGen")] AmdClocksTableGen),
     Nvidia(NvidiaClocksTable),
     Intel(IntelClocksTable),
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, JsonSchema)]
 pub struct NvidiaClocksTable {
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
     pub gpu_offsets: IndexMap<u32, NvidiaClockOffset>,
@@ -348,7 +368,7 @@ pub struct NvidiaClocksTable {
 
 /// Doc from `xe_gt_freq.c`
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq, JsonSchema)]
 pub struct IntelClocksTable {
     pub gt_freq: Option<(u64, u64)>,
     /// - rpn_freq: The Render Performance (RP) N level, which is the minimal one.
@@ -359,7 +379,7 @@ pub struct IntelClocksTable {
     pub rp0_freq: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, JsonSchema)]
 pub struct NvidiaClockOffset {
     pub current: i32,
     pub min: i32,
@@ -381,7 +401,7 @@ impl From<AmdClocksTableGen> for ClocksInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct LinkInfo {
     pub current_width: Option<String>,
     pub current_speed: Option<String>,
@@ -389,7 +409,7 @@ pub struct LinkInfo {
     pub max_speed: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct VulkanInfo {
     pub device_name: String,
     pub api_version: String,
@@ -400,7 +420,7 @@ pub struct VulkanInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct VulkanDriverInfo {
     pub version: u32,
     pub name: Option<String>,
@@ -409,7 +429,7 @@ pub struct VulkanDriverInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct PciInfo {
     pub vendor_id: String,
     pub vendor: Option<String>,
@@ -418,15 +438,17 @@ pub struct PciInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct DeviceStats {
     pub fan: FanStats,
     pub clockspeed: ClockspeedStats,
     pub voltage: VoltageStats,
     pub vram: VramStats,
     pub power: PowerStats,
+    #[schemars(with = "HashMap<String, remote::Temperature>")]
     pub temps: HashMap<String, Temperature>,
     pub busy_percent: Option<u8>,
+    #[schemars(with = "Option<remote::PerformanceLevel>")]
     pub performance_level: Option<PerformanceLevel>,
     pub core_power_state: Option<usize>,
     pub memory_power_state: Option<usize>,
@@ -434,8 +456,127 @@ pub struct DeviceStats {
     pub throttle_info: Option<BTreeMap<String, Vec<String>>>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DeviceStatsHistoryEntry {
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+    pub stats: DeviceStats,
+}
+
+/// Result of checking a config with `ValidateConfig`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct ConfigValidation {
+    /// Problems that would make applying the config fail, and values outside of the ranges supported by the hardware
+    pub errors: Vec<ConfigIssue>,
+    /// Settings that would be ignored, such as options that the GPU does not support
+    pub warnings: Vec<ConfigIssue>,
+    /// Writes that applying the config would make, by GPU id.
+    /// Only the GPU settings of the profile that is active in the config are included.
+    pub writes: IndexMap<String, Vec<ConfigWrite>>,
+}
+
+impl ConfigValidation {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct ConfigIssue {
+    /// Location of the value in the config, such as `profiles.Gaming.gpus.<id>.power_cap`.
+    /// Empty when the issue is about the whole file.
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct ConfigWrite {
+    /// The sysfs file or driver function that would be written to
+    pub target: String,
+    pub value: String,
+}
+
+/// A previous version of the config file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct ConfigRevision {
+    pub id: u64,
+    /// Unix timestamp in milliseconds of when this version was saved
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct AuditLogEntry {
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+    /// Who made the change, such as a unix user or a TCP peer
+    pub caller: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// Settings of a GPU were applied, and then either confirmed or reverted
+    GpuSettings {
+        gpu_id: String,
+        outcome: SettingsOutcome,
+        changes: Vec<ConfigChange>,
+    },
+    ProfileSwitch {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    /// The config file was edited externally and reloaded
+    ConfigReload { changes: Vec<ConfigChange> },
+    /// A previous version of the config was restored
+    ConfigRestore {
+        revision: u64,
+        changes: Vec<ConfigChange>,
+    },
+}
+
+impl AuditEvent {
+    pub fn gpu_id(&self) -> Option<&str> {
+        match self {
+            AuditEvent::GpuSettings { gpu_id, .. } => Some(gpu_id),
+            AuditEvent::ProfileSwitch { .. }
+            | AuditEvent::ConfigReload { .. }
+            | AuditEvent::ConfigRestore { .. } => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsOutcome {
+    Confirmed,
+    Reverted,
+    TimedOut,
+}
+
+/// Whether a confirmed settings change was written to the config file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SaveStatus {
+    Saved,
+    /// The config is immutable, so the settings are only applied until the daemon is restarted
+    NotPersisted,
+}
+
+/// A single changed value, identified by its path in the config
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct ConfigChange {
+    pub path: String,
+    /// JSON representation of the previous value, if it was set
+    pub before: Option<String>,
+    /// JSON representation of the new value, if it is set
+    pub after: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct FanStats {
     pub control_enabled: bool,
     pub control_mode: Option<FanControlMode>,
@@ -453,18 +594,23 @@ pub struct FanStats {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
 pub struct PmfwInfo {
+    #[schemars(with = "Option<remote::FanInfo>")]
     pub acoustic_limit: Option<FanInfo>,
+    #[schemars(with = "Option<remote::FanInfo>")]
     pub acoustic_target: Option<FanInfo>,
+    #[schemars(with = "Option<remote::FanInfo>")]
     pub target_temp: Option<FanInfo>,
+    #[schemars(with = "Option<remote::FanInfo>")]
     pub minimum_pwm: Option<FanInfo>,
     pub zero_rpm_enable: Option<bool>,
+    #[schemars(with = "Option<remote::FanInfo>")]
     pub zero_rpm_temperature: Option<FanInfo>,
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, JsonSchema)]
 pub struct ClockspeedStats {
     pub gpu_clockspeed: Option<u64>,
     /// Target clock
@@ -473,21 +619,21 @@ pub struct ClockspeedStats {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, JsonSchema)]
 pub struct VoltageStats {
     pub gpu: Option<u64>,
     pub northbridge: Option<u64>,
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, JsonSchema)]
 pub struct VramStats {
     pub total: Option<u64>,
     pub used: Option<u64>,
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, JsonSchema)]
 pub struct PowerStats {
     pub average: Option<f64>,
     pub current: Option<f64>,
@@ -497,7 +643,7 @@ pub struct PowerStats {
     pub cap_default: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct PowerStates {
     pub core: Vec<PowerState>,
     pub vram: Vec<PowerState>,
@@ -510,7 +656,7 @@ impl PowerStates {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, JsonSchema)]
 pub struct PowerState {
     pub enabled: bool,
     pub min_value: Option<u64>,
@@ -526,7 +672,7 @@ pub enum InitramfsType {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
 pub struct PmfwOptions {
     pub acoustic_limit: Option<u32>,
     pub acoustic_target: Option<u32>,
@@ -543,7 +689,7 @@ impl PmfwOptions {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default, JsonSchema)]
 pub struct FanOptions<'a> {
     pub id: &'a str,
     pub enabled: bool,
@@ -556,7 +702,7 @@ pub struct FanOptions<'a> {
     pub change_threshold: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
 pub struct ProfilesInfo {
     pub profiles: IndexMap<String, Option<ProfileRule>>,
     pub current_profile: Option<String>,
@@ -573,11 +719,17 @@ impl PartialEq for ProfilesInfo {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
 #[serde(tag = "type", content = "filter", rename_all = "lowercase")]
 pub enum ProfileRule {
     Process(ProcessProfileRule),
     Gamemode(Option<ProcessProfileRule>),
+    /// Matches when all of the rules match
+    And(Vec<ProfileRule>),
+    /// Matches when any of the rules matches
+    Or(Vec<ProfileRule>),
+    /// Matches when the rule does not match
+    Not(Box<ProfileRule>),
 }
 
 impl Default for ProfileRule {
@@ -587,10 +739,15 @@ impl Default for ProfileRule {
 }
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
 pub struct ProcessProfileRule {
     pub name: Arc<str>,
     pub args: Option<String>,
+    /// How `name` and `args` are matched against the running processes
+    #[serde(default, skip_serializing_if = "ProcessMatchMode::is_exact")]
+    pub match_mode: ProcessMatchMode,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_case: bool,
 }
 
 impl Default for ProcessProfileRule {
@@ -598,13 +755,34 @@ impl Default for ProcessProfileRule {
         Self {
             name: String::new().into(),
             args: None,
+            match_mode: ProcessMatchMode::default(),
+            ignore_case: false,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessMatchMode {
+    /// The name has to be equal, and the arguments have to contain the given text
+    #[default]
+    Exact,
+    /// Wildcard patterns where `*` matches any text and `?` a single character.
+    /// The pattern has to match the whole name or arguments.
+    Glob,
+    /// Regular expressions, which can match any part of the name or arguments unless anchored with `^` and `$`
+    Regex,
+}
+
+impl ProcessMatchMode {
+    pub fn is_exact(&self) -> bool {
+        *self == Self::Exact
+    }
+}
+
 pub type ProcessMap = IndexMap<i32, ProcessInfo>;
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 pub struct ProfileWatcherState {
     pub process_list: ProcessMap,
     pub gamemode_games: IndexSet<i32>,
@@ -612,7 +790,7 @@ pub struct ProfileWatcherState {
 }
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct ProcessInfo {
     pub name: Arc<str>,
     pub cmdline: Box<str>,
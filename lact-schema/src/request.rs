@@ -1,13 +1,89 @@
-use std::fmt;
+use std::{borrow::Cow, fmt};
 
-use crate::{FanOptions, ProfileRule};
+use crate::{api_schema::remote, FanOptions, ProfileRule};
 use amdgpu_sysfs::gpu_handle::{PerformanceLevel, PowerLevelKind};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Revision of the protocol, increased whenever an existing request or response changes in an incompatible way.
+/// New requests and features do not change the revision, they are detected through `Hello` instead.
+pub const PROTOCOL_REVISION: u32 = 1;
+
+/// Optional protocol features, which are reported in the response to `Hello`
+pub mod features {
+    /// Requests can carry an `id`, which is echoed in the response, and are answered as soon as they complete
+    pub const REQUEST_IDS: &str = "request_ids";
+    /// Confirming a settings change responds with a [`crate::SaveStatus`] instead of `null`
+    pub const SAVE_STATUS: &str = "save_status";
+
+    /// All of the features supported by this version of the schema
+    pub const ALL: &[&str] = &[REQUEST_IDS, SAVE_STATUS];
+}
+
+/// Names of all commands, in the same format as the `command` field of a request
+pub const COMMANDS: &[&str] = &[
+    "ping",
+    "authenticate",
+    "hello",
+    "api_schema",
+    "list_devices",
+    "system_info",
+    "device_info",
+    "device_stats",
+    "subscribe_stats",
+    "unsubscribe_stats",
+    "subscribe_profile",
+    "device_stats_history",
+    "device_clocks_info",
+    "device_power_profile_modes",
+    "set_fan_control",
+    "reset_pmfw",
+    "set_power_cap",
+    "set_performance_level",
+    "set_clocks_value",
+    "batch_set_clocks_value",
+    "set_power_profile_mode",
+    "get_power_states",
+    "set_enabled_power_states",
+    "vbios_dump",
+    "list_profiles",
+    "set_profile",
+    "create_profile",
+    "delete_profile",
+    "move_profile",
+    "evaluate_profile_rule",
+    "set_profile_rule",
+    "enable_overdrive",
+    "disable_overdrive",
+    "generate_snapshot",
+    "confirm_pending_config",
+    "rest_config",
+    "audit_log",
+    "validate_config",
+    "list_config_revisions",
+    "restore_config_revision",
+];
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
 #[serde(tag = "command", content = "args", rename_all = "snake_case")]
 pub enum Request<'a> {
     Ping,
+    /// Authenticate the current connection. Required before any other request on TCP connections when the daemon has an auth token configured
+    Authenticate {
+        #[serde(borrow)]
+        token: AuthToken<'a>,
+    },
+    /// Negotiate capabilities with the daemon. Returns the daemon version, the protocol revision and the supported commands and features.
+    /// Daemons that predate this request answer with an "unknown variant" error.
+    Hello {
+        #[serde(default)]
+        client_version: Option<String>,
+        /// Optional features the client uses, see [`features`]
+        #[serde(default)]
+        features: Vec<String>,
+    },
+    /// JSON Schema description of all requests and responses
+    ApiSchema,
     ListDevices,
     SystemInfo,
     DeviceInfo {
@@ -16,6 +92,23 @@ pub enum Request<'a> {
     DeviceStats {
         id: &'a str,
     },
+    /// Start receiving `DeviceStats` events for the given GPU on the current connection
+    SubscribeStats {
+        id: &'a str,
+        interval_ms: u64,
+    },
+    UnsubscribeStats {
+        id: &'a str,
+    },
+    /// Start receiving `ProfileChanged` events on the current connection, beginning with the current profile
+    SubscribeProfile,
+    /// Stats recorded by the daemon, oldest first.
+    /// `since` is a unix timestamp in milliseconds, `resolution` is the minimum time between returned entries in milliseconds.
+    DeviceStatsHistory {
+        id: &'a str,
+        since: Option<u64>,
+        resolution: Option<u64>,
+    },
     DeviceClocksInfo {
         id: &'a str,
     },
@@ -32,6 +125,7 @@ pub enum Request<'a> {
     },
     SetPerformanceLevel {
         id: &'a str,
+        #[schemars(with = "remote::PerformanceLevel")]
         performance_level: PerformanceLevel,
     },
     SetClocksValue {
@@ -53,6 +147,7 @@ pub enum Request<'a> {
     },
     SetEnabledPowerStates {
         id: &'a str,
+        #[schemars(with = "remote::PowerLevelKind")]
         kind: PowerLevelKind,
         states: Vec<u8>,
     },
@@ -91,16 +186,201 @@ pub enum Request<'a> {
     GenerateSnapshot,
     ConfirmPendingConfig(ConfirmCommand),
     RestConfig,
+    /// Entries of the settings change audit log, oldest first.
+    /// `since` is a unix timestamp in milliseconds, `limit` returns only the latest entries.
+    AuditLog {
+        gpu_id: Option<&'a str>,
+        since: Option<u64>,
+        limit: Option<usize>,
+    },
+    /// Checks a config file against the GPUs of the system without applying it,
+    /// and lists the writes that applying it would make
+    ValidateConfig {
+        yaml: String,
+    },
+    /// Previous versions of the config file, oldest first
+    ListConfigRevisions,
+    /// Replaces the config with a previous version and applies it
+    RestoreConfigRevision {
+        id: u64,
+    },
+}
+
+impl Request<'_> {
+    /// Name of the command, as used in the `command` field
+    pub fn command(&self) -> &'static str {
+        match self {
+            Request::Ping => "ping",
+            Request::Authenticate { .. } => "authenticate",
+            Request::Hello { .. } => "hello",
+            Request::ApiSchema => "api_schema",
+            Request::ListDevices => "list_devices",
+            Request::SystemInfo => "system_info",
+            Request::DeviceInfo { .. } => "device_info",
+            Request::DeviceStats { .. } => "device_stats",
+            Request::SubscribeStats { .. } => "subscribe_stats",
+            Request::UnsubscribeStats { .. } => "unsubscribe_stats",
+            Request::SubscribeProfile => "subscribe_profile",
+            Request::DeviceStatsHistory { .. } => "device_stats_history",
+            Request::DeviceClocksInfo { .. } => "device_clocks_info",
+            Request::DevicePowerProfileModes { .. } => "device_power_profile_modes",
+            Request::SetFanControl(_) => "set_fan_control",
+            Request::ResetPmfw { .. } => "reset_pmfw",
+            Request::SetPowerCap { .. } => "set_power_cap",
+            Request::SetPerformanceLevel { .. } => "set_performance_level",
+            Request::SetClocksValue { .. } => "set_clocks_value",
+            Request::BatchSetClocksValue { .. } => "batch_set_clocks_value",
+            Request::SetPowerProfileMode { .. } => "set_power_profile_mode",
+            Request::GetPowerStates { .. } => "get_power_states",
+            Request::SetEnabledPowerStates { .. } => "set_enabled_power_states",
+            Request::VbiosDump { .. } => "vbios_dump",
+            Request::ListProfiles { .. } => "list_profiles",
+            Request::SetProfile { .. } => "set_profile",
+            Request::CreateProfile { .. } => "create_profile",
+            Request::DeleteProfile { .. } => "delete_profile",
+            Request::MoveProfile { .. } => "move_profile",
+            Request::EvaluateProfileRule { .. } => "evaluate_profile_rule",
+            Request::SetProfileRule { .. } => "set_profile_rule",
+            Request::EnableOverdrive => "enable_overdrive",
+            Request::DisableOverdrive => "disable_overdrive",
+            Request::GenerateSnapshot => "generate_snapshot",
+            Request::ConfirmPendingConfig(_) => "confirm_pending_config",
+            Request::RestConfig => "rest_config",
+            Request::AuditLog { .. } => "audit_log",
+            Request::ValidateConfig { .. } => "validate_config",
+            Request::ListConfigRevisions => "list_config_revisions",
+            Request::RestoreConfigRevision { .. } => "restore_config_revision",
+        }
+    }
+
+    /// Whether the request only reads information and does not change any settings.
+    /// Read-only requests are allowed on connections without admin permissions.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Request::Ping
+            | Request::Authenticate { .. }
+            | Request::Hello { .. }
+            | Request::ApiSchema
+            | Request::ListDevices
+            | Request::SystemInfo
+            | Request::DeviceInfo { .. }
+            | Request::DeviceStats { .. }
+            | Request::SubscribeStats { .. }
+            | Request::UnsubscribeStats { .. }
+            | Request::SubscribeProfile
+            | Request::DeviceStatsHistory { .. }
+            | Request::DeviceClocksInfo { .. }
+            | Request::DevicePowerProfileModes { .. }
+            | Request::GetPowerStates { .. }
+            | Request::ListProfiles { .. }
+            | Request::EvaluateProfileRule { .. }
+            | Request::ValidateConfig { .. }
+            | Request::ListConfigRevisions => true,
+            Request::SetFanControl(_)
+            | Request::ResetPmfw { .. }
+            | Request::SetPowerCap { .. }
+            | Request::SetPerformanceLevel { .. }
+            | Request::SetClocksValue { .. }
+            | Request::BatchSetClocksValue { .. }
+            | Request::SetPowerProfileMode { .. }
+            | Request::SetEnabledPowerStates { .. }
+            | Request::SetProfile { .. }
+            | Request::CreateProfile { .. }
+            | Request::DeleteProfile { .. }
+            | Request::MoveProfile { .. }
+            | Request::SetProfileRule { .. }
+            | Request::EnableOverdrive
+            | Request::DisableOverdrive
+            | Request::GenerateSnapshot
+            | Request::ConfirmPendingConfig(_)
+            | Request::RestConfig
+            | Request::RestoreConfigRevision { .. }
+            // Reads the VBIOS from debugfs, which is only accessible by root
+            | Request::VbiosDump { .. }
+            // Reveals who changed settings and when
+            | Request::AuditLog { .. } => false,
+        }
+    }
+
+    /// The GPU that the request targets, if it is specific to a single GPU
+    pub fn gpu_id(&self) -> Option<&str> {
+        match self {
+            Request::DeviceInfo { id }
+            | Request::DeviceStats { id }
+            | Request::SubscribeStats { id, .. }
+            | Request::UnsubscribeStats { id }
+            | Request::DeviceStatsHistory { id, .. }
+            | Request::DeviceClocksInfo { id }
+            | Request::DevicePowerProfileModes { id }
+            | Request::ResetPmfw { id }
+            | Request::SetPowerCap { id, .. }
+            | Request::SetPerformanceLevel { id, .. }
+            | Request::SetClocksValue { id, .. }
+            | Request::BatchSetClocksValue { id, .. }
+            | Request::SetPowerProfileMode { id, .. }
+            | Request::GetPowerStates { id }
+            | Request::SetEnabledPowerStates { id, .. }
+            | Request::VbiosDump { id } => Some(id),
+            Request::SetFanControl(opts) => Some(opts.id),
+            Request::AuditLog {
+                gpu_id: Some(id), ..
+            } => Some(id),
+            Request::Ping
+            | Request::Authenticate { .. }
+            | Request::Hello { .. }
+            | Request::ApiSchema
+            | Request::ListDevices
+            | Request::SystemInfo
+            | Request::SubscribeProfile
+            | Request::ListProfiles { .. }
+            | Request::SetProfile { .. }
+            | Request::CreateProfile { .. }
+            | Request::DeleteProfile { .. }
+            | Request::MoveProfile { .. }
+            | Request::EvaluateProfileRule { .. }
+            | Request::SetProfileRule { .. }
+            | Request::EnableOverdrive
+            | Request::DisableOverdrive
+            | Request::GenerateSnapshot
+            | Request::ConfirmPendingConfig(_)
+            | Request::RestConfig
+            | Request::AuditLog { .. }
+            | Request::ValidateConfig { .. }
+            | Request::ListConfigRevisions
+            | Request::RestoreConfigRevision { .. } => None,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Request along with an optional id, which is echoed in the response.
+/// Requests with an id may be answered out of order, while requests without one are answered in the order they were sent.
+#[derive(Serialize, Debug)]
+pub struct RequestEnvelope<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub request: &'a Request<'a>,
+}
+
+/// Token used to authenticate a connection, which is hidden in debug output
+#[derive(Serialize, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(transparent)]
+pub struct AuthToken<'a>(#[serde(borrow)] pub Cow<'a, str>);
+
+impl fmt::Debug for AuthToken<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuthToken(<redacted>)")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
 #[serde(tag = "command", rename_all = "snake_case")]
 pub enum ConfirmCommand {
     Confirm,
     Revert,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
 pub struct SetClocksCommand {
     pub r#type: ClockspeedType,
     pub value: Option<i32>,
@@ -115,7 +395,7 @@ impl SetClocksCommand {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Eq, Hash, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ClockspeedType {
     MaxCoreClock,
@@ -130,12 +410,14 @@ pub enum ClockspeedType {
     Reset,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ProfileBase {
     Empty,
     Default,
     Profile(String),
+    /// Inherit the settings of another profile, so that later changes to it also apply to the new profile
+    Extends(String),
 }
 
 impl fmt::Display for ProfileBase {
@@ -144,6 +426,7 @@ impl fmt::Display for ProfileBase {
             ProfileBase::Empty => "Empty",
             ProfileBase::Default => "Default",
             ProfileBase::Profile(name) => name,
+            ProfileBase::Extends(name) => return write!(f, "{name} (inherit)"),
         };
         text.fmt(f)
     }
@@ -152,7 +435,7 @@ impl fmt::Display for ProfileBase {
 #[cfg(test)]
 mod tests {
     use crate::{
-        request::{ClockspeedType, SetClocksCommand},
+        request::{AuthToken, ClockspeedType, SetClocksCommand, COMMANDS},
         Request,
     };
 
@@ -170,4 +453,75 @@ mod tests {
                 .unwrap()
         );
     }
+
+    #[test]
+    fn authenticate_request() {
+        let request: Request =
+            serde_json::from_str(r#"{"command": "authenticate", "args": {"token": "secret"}}"#)
+                .unwrap();
+        assert_eq!(
+            Request::Authenticate {
+                token: AuthToken("secret".into())
+            },
+            request
+        );
+        assert!(!format!("{request:?}").contains("secret"));
+
+        // Tokens with escaped characters cannot be borrowed from the input
+        let request: Request = serde_json::from_str(
+            r#"{"command": "authenticate", "args": {"token": "se\"cr\\et\u00e9"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Request::Authenticate {
+                token: AuthToken("se\"cr\\et\u{e9}".into())
+            },
+            request
+        );
+    }
+
+    #[test]
+    fn read_only_requests() {
+        assert!(Request::DeviceStats { id: "gpu" }.is_read_only());
+        assert!(Request::ListProfiles {
+            include_state: true
+        }
+        .is_read_only());
+        assert!(!Request::SetPowerCap {
+            id: "gpu",
+            cap: Some(100.0)
+        }
+        .is_read_only());
+        assert!(!Request::GenerateSnapshot.is_read_only());
+        assert!(!Request::VbiosDump { id: "gpu" }.is_read_only());
+    }
+
+    #[test]
+    fn commands_list_is_complete() {
+        // The error for an unknown command lists all of the known ones
+        let error = serde_json::from_str::<Request>(r#"{"command": "unknown"}"#).unwrap_err();
+        let error = error.to_string();
+        let expected = error
+            .split_once("expected one of ")
+            .unwrap()
+            .1
+            .split(", ")
+            .map(|name| name.split('`').nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(expected, COMMANDS);
+
+        for request in [
+            Request::Ping,
+            Request::ListDevices,
+            Request::DeviceStats { id: "gpu" },
+            Request::AuditLog {
+                gpu_id: None,
+                since: None,
+                limit: None,
+            },
+        ] {
+            let value = serde_json::to_value(&request).unwrap();
+            assert_eq!(value["command"], request.command());
+        }
+    }
 }
@@ -1,14 +1,40 @@
+use crate::{ApiError, DeviceStats};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "status", content = "data", rename_all = "snake_case")]
 pub enum Response<T> {
     Ok(T),
-    Error(serde_error::Error),
+    Error(ApiError),
 }
 
 impl<T> From<anyhow::Error> for Response<T> {
     fn from(value: anyhow::Error) -> Self {
-        Response::Error(serde_error::Error::new(&*value))
+        Response::Error(ApiError::from(&value))
     }
 }
+
+/// Response along with the id of the request it answers, if the request had one
+#[derive(Serialize, Debug)]
+pub struct ResponseEnvelope<T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub response: Response<T>,
+}
+
+/// Message pushed by the daemon without a matching request, such as after subscribing to stats
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    DeviceStats {
+        id: String,
+        stats: DeviceStats,
+    },
+    /// The current profile was switched. Sent after `SubscribeProfile` and by the HTTP event stream
+    ProfileChanged {
+        profile: Option<String>,
+    },
+}
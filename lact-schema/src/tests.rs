@@ -1,6 +1,11 @@
-use crate::{FanControlMode, FanOptions, PmfwOptions, Pong, Request, Response};
+use crate::{
+    api_schema::ApiSchema,
+    request::{RequestEnvelope, COMMANDS},
+    ApiError, DeviceStats, ErrorKind, Event, FanControlMode, FanOptions, PmfwOptions, Pong,
+    Request, Response, ResponseEnvelope,
+};
 use anyhow::anyhow;
-use serde_json::json;
+use serde_json::{json, Value};
 use std::collections::BTreeMap;
 
 #[test]
@@ -24,6 +29,53 @@ fn pong_response() {
     assert_eq!(serde_json::to_value(response).unwrap(), expected_response);
 }
 
+#[test]
+fn request_envelope() {
+    let request = Request::DeviceInfo { id: "1002:67DF" };
+    let envelope = RequestEnvelope {
+        id: Some(5),
+        request: &request,
+    };
+    let expected_request = json!({
+        "id": 5,
+        "command": "device_info",
+        "args": { "id": "1002:67DF" }
+    });
+    assert_eq!(serde_json::to_value(&envelope).unwrap(), expected_request);
+
+    // The id is not part of the request itself
+    let payload = serde_json::to_string(&envelope).unwrap();
+    assert_eq!(request, serde_json::from_str::<Request>(&payload).unwrap());
+
+    let envelope = RequestEnvelope {
+        id: None,
+        request: &Request::Ping,
+    };
+    assert_eq!(
+        serde_json::to_value(&envelope).unwrap(),
+        json!({ "command": "ping" })
+    );
+}
+
+#[test]
+fn response_envelope() {
+    let envelope = ResponseEnvelope {
+        id: Some(5),
+        response: Response::Ok(Pong),
+    };
+    let expected_response = json!({
+        "id": 5,
+        "status": "ok",
+        "data": null
+    });
+    let value = serde_json::to_value(envelope).unwrap();
+    assert_eq!(value, expected_response);
+
+    // Clients that do not use ids can still read the response
+    let response: Response<Pong> = serde_json::from_value(value).unwrap();
+    assert!(matches!(response, Response::Ok(Pong)));
+}
+
 #[test]
 fn controllers_response() {
     let expected_response = json!({
@@ -45,7 +97,9 @@ fn error_response() {
                     "description": "first error",
                     "source": null
                 }
-            }
+            },
+            "kind": "other",
+            "chain": ["third deeper context", "second context", "first error"]
         },
         "status": "error"
     });
@@ -59,6 +113,44 @@ fn error_response() {
     assert_eq!(serde_json::to_value(response).unwrap(), expected_response);
 }
 
+#[test]
+fn error_kind() {
+    let error = ErrorKind::NotFound
+        .error("Controller 'gpu' not found")
+        .context("Could not get stats");
+    let response = Response::<()>::from(error);
+    let value = serde_json::to_value(&response).unwrap();
+    assert_eq!(value["data"]["kind"], "not_found");
+    assert_eq!(value["data"]["description"], "Could not get stats");
+
+    // The outermost kind is used
+    let error = anyhow!("write failed")
+        .context(ErrorKind::HardwareWriteFailed.context("Could not apply settings"));
+    let error = error.context(ErrorKind::PendingConfirmation.context("Pending"));
+    assert_eq!(ErrorKind::PendingConfirmation, ErrorKind::of(&error));
+
+    // The kind is kept after the error is received by a client
+    let Response::<()>::Error(api_error) = serde_json::from_value(value).unwrap() else {
+        panic!("Expected an error response");
+    };
+    let error = anyhow::Error::new(api_error).context("Got error from daemon");
+    assert_eq!(ErrorKind::NotFound, ErrorKind::of(&error));
+    assert_eq!(
+        "Controller 'gpu' not found",
+        error.chain().last().unwrap().to_string()
+    );
+
+    // Errors from older daemons have no kind, and kinds from newer ones may be unknown
+    for data in [
+        json!({ "description": "error", "source": null }),
+        json!({ "description": "error", "source": null, "kind": "something_new", "chain": ["error"] }),
+    ] {
+        let api_error: ApiError = serde_json::from_value(data).unwrap();
+        assert_eq!(ErrorKind::Other, api_error.kind);
+        assert_eq!("error", api_error.to_string());
+    }
+}
+
 #[test]
 fn set_fan_clocks() {
     let value = r#"{
@@ -86,3 +178,43 @@ fn set_fan_clocks() {
     });
     assert_eq!(expected_request, request);
 }
+
+#[test]
+fn stats_event() {
+    let event = Event::DeviceStats {
+        id: "1002:67DF-1DA2:E387-0000:0f:00.0".to_owned(),
+        stats: DeviceStats::default(),
+    };
+    let value = serde_json::to_value(event).unwrap();
+
+    assert_eq!(value["event"], "device_stats");
+    assert_eq!(value["data"]["id"], "1002:67DF-1DA2:E387-0000:0f:00.0");
+    assert!(value["data"]["stats"].is_object());
+}
+
+#[test]
+fn api_schema() {
+    let schema = serde_json::to_value(ApiSchema::generate()).unwrap();
+
+    let responses = schema["responses"].as_object().unwrap();
+    assert_eq!(responses.len(), COMMANDS.len());
+    assert_eq!(
+        schema["responses"]["device_stats"]["$ref"],
+        "#/definitions/DeviceStats"
+    );
+
+    fn check_refs(value: &Value, root: &Value) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(reference)) = map.get("$ref") {
+                    let pointer = reference.strip_prefix('#').unwrap();
+                    assert!(root.pointer(pointer).is_some(), "{reference} not found");
+                }
+                map.values().for_each(|value| check_refs(value, root));
+            }
+            Value::Array(values) => values.iter().for_each(|value| check_refs(value, root)),
+            _ => (),
+        }
+    }
+    check_refs(&schema, &schema);
+}